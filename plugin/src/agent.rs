@@ -0,0 +1,170 @@
+//! Agent profiles: named commands the plugin can launch in a worktree.
+//!
+//! Profiles come from the plugin configuration as flat keys:
+//!
+//! ```kdl
+//! agent.claude "claude"
+//! agent.claude.args "--model opus"
+//! agent.aider "aider"
+//! agent.aider.env "AIDER_AUTO_COMMITS=false"
+//! default_agent "claude"
+//! ```
+//!
+//! Without any `agent.*` keys, the legacy `agent_cmd` key (default `claude`)
+//! becomes a single profile named `default`.
+//!
+//! A command may contain `{{prompt_file}}` to receive the spawn's task prompt
+//! as a file (e.g. `aider --message-file {{prompt_file}}`); see
+//! [`with_prompt_file`].
+
+use std::collections::BTreeMap;
+
+use crate::layout::shell_quote;
+
+const PREFIX: &str = "agent.";
+
+pub const PROMPT_FILE_PLACEHOLDER: &str = "{{prompt_file}}";
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AgentProfile {
+    pub name: String,
+    pub command: String,
+    /// Extra arguments appended to `command`, in shell syntax.
+    pub args: String,
+    /// Environment assignments (`KEY=value ...`) in shell syntax.
+    pub env: String,
+}
+
+impl AgentProfile {
+    pub fn new(name: &str, command: &str) -> Self {
+        AgentProfile { name: name.to_string(), command: command.to_string(), ..Default::default() }
+    }
+
+    /// The shell command line that launches this agent.
+    pub fn command_line(&self) -> String {
+        let mut parts = Vec::new();
+        if !self.env.trim().is_empty() {
+            parts.push(format!("env {}", self.env.trim()));
+        }
+        parts.push(self.command.trim().to_string());
+        if !self.args.trim().is_empty() {
+            parts.push(self.args.trim().to_string());
+        }
+        parts.join(" ")
+    }
+}
+
+/// Hand the task prompt stored in `prompt_file` to `command_line`: at
+/// `{{prompt_file}}` as a quoted path (`/dev/null` without a prompt), or else
+/// as a final argument holding the text. Same rules as zelligent.sh. Only
+/// for real agent commands: the `$SHELL` fallback gets no prompt.
+pub fn with_prompt_file(command_line: &str, prompt_file: &str) -> String {
+    if command_line.contains(PROMPT_FILE_PLACEHOLDER) {
+        let path = if prompt_file.is_empty() { "/dev/null" } else { prompt_file };
+        return command_line.replace(PROMPT_FILE_PLACEHOLDER, &shell_quote(path));
+    }
+    if prompt_file.is_empty() {
+        return command_line.to_string();
+    }
+    format!("{command_line} \"$(cat {})\"", shell_quote(prompt_file))
+}
+
+/// Build the profile list from the plugin configuration, sorted by name.
+pub fn parse_profiles(configuration: &BTreeMap<String, String>) -> Vec<AgentProfile> {
+    let mut profiles: BTreeMap<&str, AgentProfile> = BTreeMap::new();
+    for (key, value) in configuration {
+        let Some(rest) = key.strip_prefix(PREFIX) else {
+            continue;
+        };
+        let (name, field) = match rest.rsplit_once('.') {
+            Some((name, field @ ("args" | "env"))) => (name, field),
+            _ => (rest, "command"),
+        };
+        if name.is_empty() {
+            continue;
+        }
+        let profile = profiles.entry(name).or_insert_with(|| AgentProfile::new(name, ""));
+        match field {
+            "args" => profile.args = value.clone(),
+            "env" => profile.env = value.clone(),
+            _ => profile.command = value.clone(),
+        }
+    }
+    // args/env without a command are incomplete; drop them.
+    let profiles: Vec<AgentProfile> = profiles
+        .into_values()
+        .filter(|p| !p.command.trim().is_empty())
+        .collect();
+
+    if profiles.is_empty() {
+        let cmd = configuration.get("agent_cmd").map(String::as_str).unwrap_or("claude");
+        return vec![AgentProfile::new("default", cmd)];
+    }
+    profiles
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(pairs: &[(&str, &str)]) -> BTreeMap<String, String> {
+        pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    fn legacy_agent_cmd_is_single_profile() {
+        let profiles = parse_profiles(&config(&[("agent_cmd", "claude --resume")]));
+        assert_eq!(profiles, vec![AgentProfile::new("default", "claude --resume")]);
+    }
+
+    #[test]
+    fn no_config_defaults_to_claude() {
+        let profiles = parse_profiles(&BTreeMap::new());
+        assert_eq!(profiles, vec![AgentProfile::new("default", "claude")]);
+    }
+
+    #[test]
+    fn named_profiles_with_args_and_env() {
+        let profiles = parse_profiles(&config(&[
+            ("agent.claude", "claude"),
+            ("agent.claude.args", "--model opus"),
+            ("agent.aider", "aider"),
+            ("agent.aider.env", "AIDER_AUTO_COMMITS=false"),
+            ("agent_cmd", "ignored"),
+            ("zelligent_path", "/usr/local/bin/zelligent"),
+        ]));
+        assert_eq!(profiles.len(), 2);
+        assert_eq!(profiles[0].name, "aider");
+        assert_eq!(profiles[0].command_line(), "env AIDER_AUTO_COMMITS=false aider");
+        assert_eq!(profiles[1].name, "claude");
+        assert_eq!(profiles[1].command_line(), "claude --model opus");
+    }
+
+    #[test]
+    fn profile_names_may_contain_dots() {
+        let profiles = parse_profiles(&config(&[("agent.gpt.4", "aider --model gpt-4")]));
+        assert_eq!(profiles[0].name, "gpt.4");
+        assert_eq!(profiles[0].command, "aider --model gpt-4");
+    }
+
+    #[test]
+    fn prompt_file_placeholder_is_quoted_path() {
+        assert_eq!(
+            with_prompt_file("aider --message-file {{prompt_file}}", "/r/.git/worktrees/it's/zelligent-prompt.md"),
+            r"aider --message-file '/r/.git/worktrees/it'\''s/zelligent-prompt.md'"
+        );
+        assert_eq!(with_prompt_file("agent < {{prompt_file}}", ""), "agent < '/dev/null'");
+    }
+
+    #[test]
+    fn prompt_without_placeholder_is_last_argument() {
+        assert_eq!(with_prompt_file("claude", "/p.md"), r#"claude "$(cat '/p.md')""#);
+        assert_eq!(with_prompt_file("claude", ""), "claude");
+    }
+
+    #[test]
+    fn args_without_command_are_dropped() {
+        let profiles = parse_profiles(&config(&[("agent.shell", "bash"), ("agent.ghost.args", "-x")]));
+        assert_eq!(profiles, vec![AgentProfile::new("shell", "bash")]);
+    }
+}
@@ -0,0 +1,167 @@
+//! Subsequence fuzzy matching used by the branch picker.
+//!
+//! A query matches a candidate when all of its characters appear in the
+//! candidate in order (case-insensitively). Matches are scored so that
+//! contiguous runs, matches at word boundaries (`/`, `-`, `_`, `.`) and
+//! shorter candidates rank higher.
+
+const SCORE_MATCH: i64 = 16;
+const BONUS_CONSECUTIVE: i64 = 24;
+const BONUS_BOUNDARY: i64 = 20;
+const BONUS_FIRST_CHAR: i64 = 12;
+const PENALTY_GAP: i64 = 3;
+const PENALTY_LENGTH: i64 = 1;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Match {
+    pub score: i64,
+    /// Char indices into the candidate of each matched query character.
+    pub positions: Vec<usize>,
+}
+
+fn is_boundary(prev: Option<char>) -> bool {
+    match prev {
+        None => true,
+        Some(c) => matches!(c, '/' | '-' | '_' | '.' | ' '),
+    }
+}
+
+fn eq_ignore_case(a: char, b: char) -> bool {
+    a == b || a.to_lowercase().eq(b.to_lowercase())
+}
+
+/// Greedily match `query` starting at `start`, then walk back from the end
+/// of that match to find the tightest window ending there.
+fn match_from(query: &[char], chars: &[char], start: usize) -> Option<Vec<usize>> {
+    let mut qi = 0;
+    let mut end = None;
+    for (ci, &c) in chars.iter().enumerate().skip(start) {
+        if eq_ignore_case(query[qi], c) {
+            qi += 1;
+            if qi == query.len() {
+                end = Some(ci);
+                break;
+            }
+        }
+    }
+    let end = end?;
+
+    let mut positions = vec![0; query.len()];
+    let mut qi = query.len();
+    let mut ci = end + 1;
+    while qi > 0 {
+        ci -= 1;
+        if eq_ignore_case(query[qi - 1], chars[ci]) {
+            qi -= 1;
+            positions[qi] = ci;
+        }
+    }
+    Some(positions)
+}
+
+fn score(positions: &[usize], chars: &[char]) -> i64 {
+    let mut score = 0;
+    for (i, &pos) in positions.iter().enumerate() {
+        score += SCORE_MATCH;
+        let prev = if pos == 0 { None } else { Some(chars[pos - 1]) };
+        if is_boundary(prev) {
+            score += BONUS_BOUNDARY;
+        }
+        if pos == 0 {
+            score += BONUS_FIRST_CHAR;
+        }
+        if i > 0 {
+            let gap = pos - positions[i - 1] - 1;
+            if gap == 0 {
+                score += BONUS_CONSECUTIVE;
+            } else {
+                score -= PENALTY_GAP * gap as i64;
+            }
+        }
+    }
+    score - PENALTY_LENGTH * chars.len() as i64
+}
+
+/// Match `query` against `candidate`, trying every start position of the
+/// first query character and keeping the best-scoring alignment. An empty
+/// query matches everything with a score of zero.
+pub fn fuzzy_match(query: &str, candidate: &str) -> Option<Match> {
+    let query: Vec<char> = query.chars().collect();
+    let chars: Vec<char> = candidate.chars().collect();
+    if query.is_empty() {
+        return Some(Match { score: 0, positions: Vec::new() });
+    }
+
+    chars
+        .iter()
+        .enumerate()
+        .filter(|(_, &c)| eq_ignore_case(query[0], c))
+        .filter_map(|(start, _)| match_from(&query, &chars, start))
+        .map(|positions| Match { score: score(&positions, &chars), positions })
+        .max_by(|a, b| a.score.cmp(&b.score).then(b.positions[0].cmp(&a.positions[0])))
+}
+
+/// Filter and rank `items` by `query`. Returns indices into `items`, best
+/// match first; ties keep their original order.
+pub fn rank<T>(query: &str, items: &[T], key: impl Fn(&T) -> &str) -> Vec<usize> {
+    let mut scored: Vec<(usize, i64)> = items
+        .iter()
+        .enumerate()
+        .filter_map(|(i, item)| fuzzy_match(query, key(item)).map(|m| (i, m.score)))
+        .collect();
+    if !query.is_empty() {
+        scored.sort_by_key(|&(_, score)| std::cmp::Reverse(score));
+    }
+    scored.into_iter().map(|(i, _)| i).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ranked<'a>(query: &str, items: &[&'a str]) -> Vec<&'a str> {
+        rank(query, items, |s| s).into_iter().map(|i| items[i]).collect()
+    }
+
+    #[test]
+    fn empty_query_matches_all_in_order() {
+        assert_eq!(ranked("", &["b", "a", "c"]), vec!["b", "a", "c"]);
+    }
+
+    #[test]
+    fn subsequence_required() {
+        assert!(fuzzy_match("fcl", "feature/cool").is_some());
+        assert!(fuzzy_match("lcf", "feature/cool").is_none());
+        assert!(fuzzy_match("x", "").is_none());
+    }
+
+    #[test]
+    fn case_insensitive() {
+        assert!(fuzzy_match("FEAT", "feature/x").is_some());
+        assert!(fuzzy_match("feat", "FEATURE/x").is_some());
+    }
+
+    #[test]
+    fn positions_are_char_indices() {
+        let m = fuzzy_match("üb", "äü/bar").unwrap();
+        assert_eq!(m.positions, vec![1, 3]);
+    }
+
+    #[test]
+    fn prefers_tight_window() {
+        let m = fuzzy_match("fb", "foo/bar-fb").unwrap();
+        assert_eq!(m.positions, vec![8, 9]);
+    }
+
+    #[test]
+    fn ranks_boundary_and_contiguous_first() {
+        let items = ["refactor-login", "fix/login", "feature/cool-login-ui"];
+        assert_eq!(ranked("login", &items)[0], "fix/login");
+        assert_eq!(ranked("fl", &items)[0], "fix/login");
+    }
+
+    #[test]
+    fn ranks_shorter_candidate_first_on_equal_match() {
+        assert_eq!(ranked("main", &["main-old", "main"]), vec!["main", "main-old"]);
+    }
+}
@@ -0,0 +1,226 @@
+//! Editable text field with readline-style keys, shared by the plugin's
+//! prompts.
+//!
+//! The cursor is a byte offset that always sits on a char boundary, so
+//! multi-byte characters move and delete as one.
+
+use zellij_tile::prelude::{BareKey, KeyModifier, KeyWithModifier};
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TextInput {
+    text: String,
+    cursor: usize,
+}
+
+impl From<&str> for TextInput {
+    /// Text with the cursor at the end.
+    fn from(text: &str) -> Self {
+        TextInput { text: text.to_string(), cursor: text.len() }
+    }
+}
+
+impl TextInput {
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Text before and after the cursor.
+    pub fn split(&self) -> (&str, &str) {
+        self.text.split_at(self.cursor)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.text.is_empty()
+    }
+
+    /// Replace the text, moving the cursor to the end.
+    pub fn set(&mut self, text: &str) {
+        *self = TextInput::from(text);
+    }
+
+    pub fn clear(&mut self) {
+        *self = TextInput::default();
+    }
+
+    /// Clear the field and return its text.
+    pub fn take(&mut self) -> String {
+        std::mem::take(self).text
+    }
+
+    pub fn insert(&mut self, c: char) {
+        self.text.insert(self.cursor, c);
+        self.cursor += c.len_utf8();
+    }
+
+    /// Apply an editing key. Returns false for keys it doesn't handle, so
+    /// the caller can give them their own meaning (Enter, Esc, Tab, ...).
+    ///
+    /// | Key | Action |
+    /// |---|---|
+    /// | `Left`/`Right`, `Ctrl-b`/`Ctrl-f` | Move one character |
+    /// | `Home`/`End`, `Ctrl-a`/`Ctrl-e` | Move to the start/end |
+    /// | `Backspace`/`Delete` | Delete before/at the cursor |
+    /// | `Ctrl-w` | Delete the whitespace-separated word before the cursor |
+    /// | `Alt-Backspace` | Delete the word (letters and digits) before the cursor |
+    /// | `Ctrl-u`/`Ctrl-k` | Delete to the start/end |
+    pub fn handle_key(&mut self, key: &KeyWithModifier) -> bool {
+        let no_mod = key.has_no_modifiers();
+        let only = |m: KeyModifier| key.key_modifiers.len() == 1 && key.key_modifiers.contains(&m);
+        let (ctrl, alt, shift) = (only(KeyModifier::Ctrl), only(KeyModifier::Alt), only(KeyModifier::Shift));
+        match key.bare_key {
+            BareKey::Left if no_mod => self.cursor = self.prev_boundary(),
+            BareKey::Char('b') if ctrl => self.cursor = self.prev_boundary(),
+            BareKey::Right if no_mod => self.cursor = self.next_boundary(),
+            BareKey::Char('f') if ctrl => self.cursor = self.next_boundary(),
+            BareKey::Home if no_mod => self.cursor = 0,
+            BareKey::Char('a') if ctrl => self.cursor = 0,
+            BareKey::End if no_mod => self.cursor = self.text.len(),
+            BareKey::Char('e') if ctrl => self.cursor = self.text.len(),
+            BareKey::Backspace if no_mod => self.delete_back_to(self.prev_boundary()),
+            BareKey::Delete if no_mod => {
+                let end = self.next_boundary();
+                self.text.replace_range(self.cursor..end, "");
+            }
+            BareKey::Char('w') if ctrl => self.delete_back_to(self.word_start(char::is_whitespace)),
+            BareKey::Backspace if alt => self.delete_back_to(self.word_start(|c| !c.is_alphanumeric())),
+            BareKey::Char('u') if ctrl => self.delete_back_to(0),
+            BareKey::Char('k') if ctrl => self.text.truncate(self.cursor),
+            BareKey::Char(c) if no_mod || shift => self.insert(c),
+            _ => return false,
+        }
+        true
+    }
+
+    fn prev_boundary(&self) -> usize {
+        self.text[..self.cursor].char_indices().next_back().map_or(0, |(i, _)| i)
+    }
+
+    fn next_boundary(&self) -> usize {
+        self.text[self.cursor..].chars().next().map_or(self.cursor, |c| self.cursor + c.len_utf8())
+    }
+
+    /// Start of the word before the cursor: skip separators, then the word.
+    fn word_start(&self, is_separator: impl Fn(char) -> bool) -> usize {
+        let before = &self.text[..self.cursor];
+        let word_end = before.trim_end_matches(&is_separator).len();
+        before[..word_end]
+            .char_indices()
+            .rev()
+            .find(|&(_, c)| is_separator(c))
+            .map_or(0, |(i, c)| i + c.len_utf8())
+    }
+
+    fn delete_back_to(&mut self, start: usize) {
+        self.text.replace_range(start..self.cursor, "");
+        self.cursor = start;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+
+    fn press(input: &mut TextInput, bare: BareKey, mods: &[KeyModifier]) -> bool {
+        let key_modifiers: BTreeSet<KeyModifier> = mods.iter().copied().collect();
+        input.handle_key(&KeyWithModifier { bare_key: bare, key_modifiers })
+    }
+
+    fn with_cursor(input: &TextInput) -> String {
+        let (before, after) = input.split();
+        format!("{before}|{after}")
+    }
+
+    #[test]
+    fn typing_inserts_at_cursor() {
+        let mut input = TextInput::from("fix");
+        press(&mut input, BareKey::Left, &[]);
+        press(&mut input, BareKey::Char('X'), &[KeyModifier::Shift]);
+        assert_eq!(with_cursor(&input), "fiX|x");
+    }
+
+    #[test]
+    fn movement_keys() {
+        let mut input = TextInput::from("abc");
+        press(&mut input, BareKey::Home, &[]);
+        assert_eq!(with_cursor(&input), "|abc");
+        press(&mut input, BareKey::Left, &[]);
+        assert_eq!(with_cursor(&input), "|abc");
+        press(&mut input, BareKey::Right, &[]);
+        assert_eq!(with_cursor(&input), "a|bc");
+        press(&mut input, BareKey::Char('e'), &[KeyModifier::Ctrl]);
+        assert_eq!(with_cursor(&input), "abc|");
+        press(&mut input, BareKey::Right, &[]);
+        assert_eq!(with_cursor(&input), "abc|");
+        press(&mut input, BareKey::Char('a'), &[KeyModifier::Ctrl]);
+        press(&mut input, BareKey::Char('f'), &[KeyModifier::Ctrl]);
+        assert_eq!(with_cursor(&input), "a|bc");
+        press(&mut input, BareKey::End, &[]);
+        press(&mut input, BareKey::Char('b'), &[KeyModifier::Ctrl]);
+        assert_eq!(with_cursor(&input), "ab|c");
+    }
+
+    #[test]
+    fn multibyte_chars_move_and_delete_whole() {
+        let mut input = TextInput::from("añ🙂b");
+        press(&mut input, BareKey::Left, &[]);
+        press(&mut input, BareKey::Left, &[]);
+        assert_eq!(with_cursor(&input), "añ|🙂b");
+        press(&mut input, BareKey::Delete, &[]);
+        assert_eq!(with_cursor(&input), "añ|b");
+        press(&mut input, BareKey::Backspace, &[]);
+        assert_eq!(with_cursor(&input), "a|b");
+        press(&mut input, BareKey::Char('é'), &[]);
+        assert_eq!(with_cursor(&input), "aé|b");
+    }
+
+    #[test]
+    fn delete_at_edges_is_noop() {
+        let mut input = TextInput::from("ab");
+        press(&mut input, BareKey::Delete, &[]);
+        assert_eq!(with_cursor(&input), "ab|");
+        press(&mut input, BareKey::Home, &[]);
+        press(&mut input, BareKey::Backspace, &[]);
+        assert_eq!(with_cursor(&input), "|ab");
+    }
+
+    #[test]
+    fn ctrl_w_deletes_whitespace_word() {
+        let mut input = TextInput::from("fix the feature/login  ");
+        press(&mut input, BareKey::Char('w'), &[KeyModifier::Ctrl]);
+        assert_eq!(with_cursor(&input), "fix the |");
+        press(&mut input, BareKey::Char('w'), &[KeyModifier::Ctrl]);
+        assert_eq!(with_cursor(&input), "fix |");
+    }
+
+    #[test]
+    fn alt_backspace_deletes_alphanumeric_word() {
+        let mut input = TextInput::from("feature/login-page");
+        press(&mut input, BareKey::Backspace, &[KeyModifier::Alt]);
+        assert_eq!(with_cursor(&input), "feature/login-|");
+        press(&mut input, BareKey::Backspace, &[KeyModifier::Alt]);
+        assert_eq!(with_cursor(&input), "feature/|");
+    }
+
+    #[test]
+    fn kill_to_start_and_end() {
+        let mut input = TextInput::from("feature/login");
+        for _ in 0..5 {
+            press(&mut input, BareKey::Left, &[]);
+        }
+        press(&mut input, BareKey::Char('k'), &[KeyModifier::Ctrl]);
+        assert_eq!(with_cursor(&input), "feature/|");
+        press(&mut input, BareKey::Left, &[]);
+        press(&mut input, BareKey::Char('u'), &[KeyModifier::Ctrl]);
+        assert_eq!(with_cursor(&input), "|/");
+    }
+
+    #[test]
+    fn unhandled_keys_are_left_to_caller() {
+        let mut input = TextInput::from("a");
+        assert!(!press(&mut input, BareKey::Enter, &[]));
+        assert!(!press(&mut input, BareKey::Esc, &[]));
+        assert!(!press(&mut input, BareKey::Char('x'), &[KeyModifier::Ctrl]));
+        assert_eq!(input.text(), "a");
+    }
+}
@@ -0,0 +1,259 @@
+//! Tab layouts for spawned worktrees.
+//!
+//! Templates are KDL with `{{placeholder}}`s inside string literals. Values
+//! are escaped for KDL before substitution, and the result is parsed before
+//! it is handed to Zellij, so paths, branch names and agent commands with
+//! quotes, `|`, `&` or newlines can't break (or inject into) the layout.
+
+use kdl::{KdlDocument, KdlNode};
+
+/// Built-in layout, kept in sync with the one in zelligent.sh: agent on the
+/// left (70%), lazygit on the right (30%). The agent pane's trailing
+/// `zelligent-worktree <cwd>` arguments are ignored by bash (`$0`/`$1`) but
+/// let the plugin find the tab after it is renamed.
+pub const DEFAULT_TEMPLATE: &str = r#"layout {
+    pane size=1 borderless=true {
+        plugin location="zellij:tab-bar"
+    }
+    pane split_direction="vertical" {
+        pane command="bash" cwd="{{cwd}}" size="70%" {
+            args "-c" "{{setup_preamble}}exec {{agent_cmd}}" "zelligent-worktree" "{{cwd}}"
+        }
+        pane command="lazygit" cwd="{{cwd}}" size="30%"
+    }
+    pane size=1 borderless=true {
+        plugin location="zellij:status-bar"
+    }
+}"#;
+
+pub const SETUP_PREAMBLE_PLACEHOLDER: &str = "{{setup_preamble}}";
+
+/// Values available to layout templates.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LayoutVars {
+    /// Worktree path (`{{cwd}}`).
+    pub cwd: String,
+    /// Shell command line launching the agent (`{{agent_cmd}}`).
+    pub agent_cmd: String,
+    pub branch: String,
+    pub repo_root: String,
+    /// Shell snippet running setup.sh before the agent, or empty
+    /// (`{{setup_preamble}}`). Comes from `zelligent prepare`.
+    pub setup_preamble: String,
+    /// Path of the task prompt, or `/dev/null` without one (`{{prompt_file}}`).
+    pub prompt_file: String,
+}
+
+impl LayoutVars {
+    fn lookup(&self, name: &str) -> Option<&str> {
+        match name {
+            "cwd" => Some(&self.cwd),
+            "agent_cmd" => Some(&self.agent_cmd),
+            "branch" => Some(&self.branch),
+            "repo_root" => Some(&self.repo_root),
+            "setup_preamble" => Some(&self.setup_preamble),
+            "prompt_file" => Some(&self.prompt_file),
+            _ => None,
+        }
+    }
+}
+
+/// Escape a value for embedding inside a KDL string literal.
+pub fn kdl_escape(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '"' => out.push_str("\\\""),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => out.push_str(&format!("\\u{{{:x}}}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Single-quote `value` for a POSIX shell command line.
+pub fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+/// Substitute every `{{name}}` in `template` with its KDL-escaped value.
+/// Unknown placeholders are an error rather than being left in the layout.
+pub fn render(template: &str, vars: &LayoutVars) -> Result<String, String> {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let end = after
+            .find("}}")
+            .ok_or_else(|| "unterminated {{ placeholder in layout".to_string())?;
+        let name = after[..end].trim();
+        let value = vars
+            .lookup(name)
+            .ok_or_else(|| format!("unknown placeholder {{{{{name}}}}} in layout"))?;
+        out.push_str(&kdl_escape(value));
+        rest = &after[end + 2..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+/// Render `template` and check it is a single `layout { ... }` document.
+/// Layouts without a `tab` are wrapped in one named `tab_name`, so the
+/// result always opens as exactly the tabs it declares.
+pub fn tab_layout(template: &str, vars: &LayoutVars, tab_name: &str) -> Result<String, String> {
+    let rendered = render(template, vars)?;
+    let mut doc: KdlDocument = rendered
+        .parse()
+        .map_err(|e: kdl::KdlError| format!("invalid layout: {e}"))?;
+
+    let layouts = doc.nodes().iter().filter(|n| n.name().value() == "layout").count();
+    if layouts != 1 || doc.nodes().len() != 1 {
+        return Err("invalid layout: expected a single top-level `layout` node".to_string());
+    }
+    let layout = &mut doc.nodes_mut()[0];
+    let has_tab = layout
+        .children()
+        .is_some_and(|c| c.nodes().iter().any(|n| n.name().value() == "tab"));
+    if !has_tab {
+        let mut tab = KdlNode::new("tab");
+        tab.insert("name", tab_name);
+        *tab.children_mut() = Some(layout.children_mut().take().unwrap_or_default());
+        let mut children = KdlDocument::new();
+        children.nodes_mut().push(tab);
+        *layout.children_mut() = Some(children);
+        doc.fmt();
+    }
+    Ok(doc.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars() -> LayoutVars {
+        LayoutVars {
+            cwd: "/home/u/.zelligent/worktrees/repo/feature/x".into(),
+            agent_cmd: "claude".into(),
+            branch: "feature/x".into(),
+            repo_root: "/home/u/repo".into(),
+            setup_preamble: String::new(),
+            prompt_file: "/dev/null".into(),
+        }
+    }
+
+    /// String arguments of the first `args` node in the layout.
+    fn agent_args(layout: &str) -> Vec<String> {
+        let doc: KdlDocument = layout.parse().unwrap();
+        fn find(doc: &KdlDocument) -> Option<&KdlNode> {
+            doc.nodes().iter().find_map(|n| {
+                if n.name().value() == "args" {
+                    Some(n)
+                } else {
+                    n.children().and_then(find)
+                }
+            })
+        }
+        find(&doc)
+            .unwrap()
+            .entries()
+            .iter()
+            .map(|e| e.value().as_string().unwrap().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn kdl_escape_specials() {
+        assert_eq!(kdl_escape(r#"a"b\c"#), r#"a\"b\\c"#);
+        assert_eq!(kdl_escape("x\ny\tz"), r"x\ny\tz");
+        assert_eq!(kdl_escape("bell\u{7}"), r"bell\u{7}");
+        assert_eq!(kdl_escape("a|b&c$d"), "a|b&c$d");
+    }
+
+    #[test]
+    fn shell_quote_embedded_quote() {
+        assert_eq!(shell_quote("it's"), r"'it'\''s'");
+    }
+
+    #[test]
+    fn default_layout_is_wrapped_in_named_tab() {
+        let layout = tab_layout(DEFAULT_TEMPLATE, &vars(), "feature-x").unwrap();
+        assert!(layout.contains(r#"tab name="feature-x""#));
+        assert!(layout.contains(r#"cwd="/home/u/.zelligent/worktrees/repo/feature/x""#));
+        assert!(layout.contains("zellij:status-bar"));
+        assert_eq!(
+            agent_args(&layout),
+            vec!["-c", "exec claude", "zelligent-worktree", "/home/u/.zelligent/worktrees/repo/feature/x"]
+        );
+    }
+
+    #[test]
+    fn nasty_agent_command_round_trips() {
+        let cmd = "claude \"fix | this & that\" --note 'it\\'s'\nsecond line";
+        let v = LayoutVars { agent_cmd: cmd.into(), ..vars() };
+        let layout = tab_layout(DEFAULT_TEMPLATE, &v, "x").unwrap();
+        assert_eq!(agent_args(&layout)[1], format!("exec {cmd}"));
+    }
+
+    #[test]
+    fn nasty_branch_and_paths_round_trip() {
+        let template = r#"layout { pane name="{{branch}}" cwd="{{cwd}}" { args "{{repo_root}}"; }; }"#;
+        let v = LayoutVars {
+            branch: r#"feat/"quoted"\slash"#.into(),
+            cwd: "/tmp/a|b&c/{x}".into(),
+            repo_root: "/tmp/with space/and\"quote".into(),
+            ..vars()
+        };
+        let layout = tab_layout(template, &v, "t").unwrap();
+        let doc: KdlDocument = layout.parse().unwrap();
+        let pane = doc.nodes()[0].children().unwrap().nodes()[0].children().unwrap().nodes()[0].clone();
+        assert_eq!(pane.get("name").unwrap().value().as_string(), Some(r#"feat/"quoted"\slash"#));
+        assert_eq!(pane.get("cwd").unwrap().value().as_string(), Some("/tmp/a|b&c/{x}"));
+        assert_eq!(agent_args(&layout), vec!["/tmp/with space/and\"quote"]);
+    }
+
+    #[test]
+    fn setup_preamble_runs_before_agent() {
+        let preamble = "bash '/r/.zelligent/setup.sh' '/r' '/w/it'\\''s' || \
+                        { echo 'Setup failed (exit '$?'). Press Enter to close.'; read; exit 1; }; ";
+        let v = LayoutVars { setup_preamble: preamble.into(), ..vars() };
+        let layout = tab_layout(DEFAULT_TEMPLATE, &v, "x").unwrap();
+        assert_eq!(agent_args(&layout)[1], format!("{preamble}exec claude"));
+    }
+
+    #[test]
+    fn prompt_file_placeholder() {
+        let template = r#"layout { pane command="less" { args "{{prompt_file}}"; }; }"#;
+        let v = LayoutVars { prompt_file: "/r/.git/worktrees/x/zelligent-prompt.md".into(), ..vars() };
+        assert_eq!(agent_args(&tab_layout(template, &v, "x").unwrap()), vec!["/r/.git/worktrees/x/zelligent-prompt.md"]);
+        assert_eq!(agent_args(&tab_layout(template, &vars(), "x").unwrap()), vec!["/dev/null"]);
+    }
+
+    #[test]
+    fn layout_with_tabs_is_kept() {
+        let template = r#"layout { tab name="{{branch}}" { pane; }; }"#;
+        let layout = tab_layout(template, &vars(), "ignored").unwrap();
+        assert!(layout.contains(r#"tab name="feature/x""#));
+        assert!(!layout.contains("ignored"));
+    }
+
+    #[test]
+    fn unknown_placeholder_is_an_error() {
+        let err = tab_layout(r#"layout { pane cwd="{{home}}"; }"#, &vars(), "x").unwrap_err();
+        assert_eq!(err, "unknown placeholder {{home}} in layout");
+        assert!(render("layout { {{cwd", &vars()).is_err());
+    }
+
+    #[test]
+    fn invalid_kdl_is_an_error() {
+        // Placeholder outside a string literal: the value is not a valid token
+        let err = tab_layout("layout { pane cwd={{cwd}}; }", &vars(), "x").unwrap_err();
+        assert!(err.starts_with("invalid layout:"), "{err}");
+        let err = tab_layout("pane; pane;", &vars(), "x").unwrap_err();
+        assert!(err.contains("single top-level `layout`"), "{err}");
+    }
+}
@@ -1,34 +1,392 @@
+mod agent;
+mod fuzzy;
+mod input;
+mod layout;
+mod order;
+mod refname;
 mod ui;
 
-use std::collections::BTreeMap;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
 use std::path::PathBuf;
 use zellij_tile::prelude::*;
 
+use agent::AgentProfile;
+use input::TextInput;
+use layout::LayoutVars;
+use order::SortOrder;
+
 // Command context keys used to route RunCommandResult
 const CMD_GIT_TOPLEVEL: &str = "git_toplevel";
 const CMD_LIST_WORKTREES: &str = "list_worktrees";
 const CMD_GIT_BRANCHES: &str = "git_branches";
 const CMD_SPAWN: &str = "spawn";
 const CMD_REMOVE: &str = "remove";
+const CMD_PREVIEW: &str = "preview";
+const CMD_MERGE: &str = "merge";
 
-#[derive(Debug, Clone, PartialEq)]
+/// Name external callers use: `zellij pipe --name zelligent -- "<command>"`.
+const PIPE_NAME: &str = "zelligent";
+const PIPE_USAGE: &str = "usage: spawn [--base <ref>] [--agent <name>] <branch> | remove [--force] [--delete-branch|--force-delete-branch] <branch> | focus <branch> | list";
+
+/// Version of the `zelligent --json` output schema this plugin understands.
+pub const SCHEMA_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Default, PartialEq)]
 pub enum Mode {
+    #[default]
     Loading,
     BrowseWorktrees,
     SelectBranch,
     InputBranch,
+    /// Picking the base ref for the new branch typed in `InputBranch`.
+    SelectBase,
+    /// Typing the task prompt for a pending spawn.
+    InputPrompt,
+    /// Picking which agent profile to launch for a pending spawn.
+    SelectAgent,
+    /// Picking how to merge the selected worktree into the base branch.
+    ChooseMerge,
+    /// Remove refused because of uncommitted changes; offering `--force`.
+    ConfirmForceRemove,
     Confirming,
 }
 
-impl Default for Mode {
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct Worktree {
+    pub branch: String,
+    pub path: String,
+    pub head: String,
+    /// Number of uncommitted (modified, staged or untracked) files.
+    pub dirty: usize,
+    /// Commits on this branch that are not on the base branch.
+    pub ahead: usize,
+    /// Commits on the base branch that are not on this branch.
+    pub behind: usize,
+    /// Committer time of HEAD as a unix timestamp (0 if unknown).
+    pub last_commit_time: u64,
+    pub last_commit_subject: String,
+    /// The branch has landed on the base branch (merged, squashed or rebased).
+    pub merged: bool,
+    /// Created by zelligent under `~/.zelligent/worktrees`, rather than with
+    /// plain `git worktree add`.
+    pub managed: bool,
+    /// HEAD is detached; `branch` is empty.
+    pub detached: bool,
+    /// When the worktree was created, as a unix timestamp (0 if unknown).
+    pub created_time: u64,
+}
+
+impl Default for Worktree {
     fn default() -> Self {
-        Mode::Loading
+        Worktree {
+            branch: String::new(),
+            path: String::new(),
+            head: String::new(),
+            dirty: 0,
+            ahead: 0,
+            behind: 0,
+            last_commit_time: 0,
+            last_commit_subject: String::new(),
+            merged: false,
+            managed: true,
+            detached: false,
+            created_time: 0,
+        }
+    }
+}
+
+impl Worktree {
+    /// Name for tabs, marks and the list: the branch, or for a detached HEAD
+    /// the directory name and short sha (`scratch@1a2b3c4`).
+    pub fn name(&self) -> String {
+        if !self.detached && !self.branch.is_empty() {
+            return self.branch.clone();
+        }
+        let dir = self.path.rsplit('/').next().unwrap_or_default();
+        let sha: String = self.head.chars().take(7).collect();
+        format!("{dir}@{sha}")
+    }
+
+    /// How to open a tab for this worktree. Worktrees zelligent did not
+    /// create are opened by path.
+    pub fn spawn_request(&self) -> SpawnRequest {
+        SpawnRequest {
+            branch: self.name(),
+            path: (!self.managed || self.detached).then(|| self.path.clone()),
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct Branch {
+    /// Local branch name. For remote-only branches this is the name the local
+    /// tracking branch will get (without the remote prefix).
+    pub name: String,
+    /// Remote the branch exists on, if there is no local branch yet.
+    pub remote: Option<String>,
+    /// Unix timestamp of the tip commit.
+    pub last_commit_time: u64,
+    /// Checked out in the main repo, so it can't get a worktree.
+    pub main_checkout: bool,
+}
+
+impl From<&str> for Branch {
+    fn from(name: &str) -> Self {
+        Branch { name: name.to_string(), ..Default::default() }
+    }
+}
+
+impl Branch {
+    /// Ref that resolves to this branch's tip (`origin/x` for remote-only branches).
+    pub fn ref_name(&self) -> String {
+        match &self.remote {
+            Some(remote) => format!("{remote}/{}", self.name),
+            None => self.name.clone(),
+        }
     }
 }
 
+/// One file in the preview's diff against the base branch.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileChange {
+    pub path: String,
+    /// Added/deleted line counts; `None` for binary files.
+    pub added: Option<usize>,
+    pub deleted: Option<usize>,
+}
+
+/// Diff summary and recent commits of the highlighted worktree. Each half is
+/// `None` until its `git` command returns.
 #[derive(Debug, Clone, Default, PartialEq)]
-pub struct Worktree {
+pub struct Preview {
+    pub branch: String,
+    pub changes: Option<Result<Vec<FileChange>, String>>,
+    pub commits: Option<Result<Vec<String>, String>>,
+}
+
+/// Parse `git diff --numstat` output.
+pub fn parse_numstat(output: &str) -> Vec<FileChange> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let mut parts = line.splitn(3, '\t');
+            let added = parts.next()?;
+            let deleted = parts.next()?;
+            let path = parts.next()?;
+            Some(FileChange {
+                path: path.to_string(),
+                added: added.parse().ok(),
+                deleted: deleted.parse().ok(),
+            })
+        })
+        .collect()
+}
+
+/// State of the agent running in a worktree's tab, derived from `PaneUpdate`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AgentStatus {
+    /// No tab is open for the worktree.
+    NoTab,
+    /// A tab is open but no agent pane could be identified in it.
+    Unknown,
+    Running,
+    /// The agent's command pane exited, with its exit code if Zellij knows it.
+    Exited(Option<i32>),
+}
+
+/// `zelligent show-repo --json`
+#[derive(Debug, Deserialize)]
+pub struct RepoInfo {
+    pub repo_root: String,
+    pub repo_name: String,
+    #[serde(default)]
+    pub base_branch: String,
+    /// Repo's preferred agent profile (`git config zelligent.agent`).
+    #[serde(default)]
+    pub default_agent: String,
+}
+
+/// `zelligent prepare --json`: the worktree is ready, the plugin renders the
+/// layout and opens the tab.
+#[derive(Debug, Deserialize)]
+pub struct Prepared {
+    pub branch: String,
+    pub worktree_path: String,
+    pub repo_root: String,
+    /// setup.sh to run before the agent; empty unless the worktree is new.
+    #[serde(default)]
+    pub setup_script: String,
+    /// Shell snippet running `setup_script` ahead of the agent, for the
+    /// layout's `{{setup_preamble}}`; empty without a setup script.
+    #[serde(default)]
+    pub setup_preamble: String,
+    /// File holding the task prompt; empty without one.
+    #[serde(default)]
+    pub prompt_file: String,
+    /// Contents of `.zelligent/layout.kdl`; empty to use the default layout.
+    #[serde(default)]
+    pub layout_template: String,
+}
+
+/// `zelligent list-worktrees --json`
+#[derive(Debug, Deserialize)]
+struct WorktreeList {
+    worktrees: Vec<Worktree>,
+}
+
+/// `zelligent list-branches --json`
+#[derive(Debug, Deserialize)]
+struct BranchList {
+    branches: Vec<Branch>,
+}
+
+/// Arguments for `zelligent spawn`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SpawnRequest {
+    pub branch: String,
+    /// Ref to create a new branch from; `None` uses the repo's default base.
+    pub base: Option<String>,
+    /// Agent profile name; `None` uses the default profile.
+    pub agent: Option<String>,
+    /// Existing worktree to open (`prepare --path`); `branch` then only
+    /// names the tab.
+    pub path: Option<String>,
+    /// Task for the agent (`prepare --prompt`).
+    pub prompt: Option<String>,
+}
+
+impl SpawnRequest {
+    fn status(&self) -> String {
+        match &self.base {
+            Some(base) => format!("Spawning '{}' from '{base}'...", self.branch),
+            None => format!("Spawning '{}'...", self.branch),
+        }
+    }
+}
+
+impl From<&str> for SpawnRequest {
+    fn from(branch: &str) -> Self {
+        SpawnRequest { branch: branch.to_string(), ..Default::default() }
+    }
+}
+
+impl From<String> for SpawnRequest {
+    fn from(branch: String) -> Self {
+        SpawnRequest { branch, ..Default::default() }
+    }
+}
+
+/// What `zelligent remove` does with the worktree's local branch.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum BranchCleanup {
+    #[default]
+    Keep,
+    /// Delete it only if it is merged into the base branch.
+    DeleteMerged,
+    /// Delete it even if unmerged.
+    ForceDelete,
+}
+
+impl BranchCleanup {
+    pub fn flag(self) -> Option<&'static str> {
+        match self {
+            BranchCleanup::Keep => None,
+            BranchCleanup::DeleteMerged => Some("--delete-branch"),
+            BranchCleanup::ForceDelete => Some("--force-delete-branch"),
+        }
+    }
+
+    fn from_flag(flag: &str) -> Option<Self> {
+        match flag {
+            "--delete-branch" => Some(BranchCleanup::DeleteMerged),
+            "--force-delete-branch" => Some(BranchCleanup::ForceDelete),
+            _ => None,
+        }
+    }
+}
+
+/// Arguments for `zelligent remove`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RemoveRequest {
+    pub branch: String,
+    pub branch_cleanup: BranchCleanup,
+    /// Remove even with uncommitted changes, after saving them to
+    /// `zelligent/backup/<branch>`.
+    pub force: bool,
+}
+
+impl From<&str> for RemoveRequest {
+    fn from(branch: &str) -> Self {
+        RemoveRequest { branch: branch.to_string(), ..Default::default() }
+    }
+}
+
+/// How `zelligent merge` lands a branch on the base branch.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MergeStrategy {
+    /// Merge commit (`--no-ff`).
+    Merge,
+    /// One squashed commit.
+    Squash,
+    /// Rebase the branch onto the base, then fast-forward.
+    Rebase,
+}
+
+impl MergeStrategy {
+    pub fn as_arg(self) -> &'static str {
+        match self {
+            MergeStrategy::Merge => "merge",
+            MergeStrategy::Squash => "squash",
+            MergeStrategy::Rebase => "rebase",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MergeRequest {
+    pub branch: String,
+    pub strategy: MergeStrategy,
+}
+
+/// `zelligent merge --json`, printed on success and on conflicts.
+#[derive(Debug, Deserialize)]
+pub struct MergeOutcome {
     pub branch: String,
+    pub base: String,
+    pub strategy: String,
+    #[serde(default)]
+    pub conflicts: Vec<String>,
+}
+
+/// Exit status of `zelligent merge` when the merge conflicted and was aborted.
+const MERGE_CONFLICT_EXIT: i32 = 3;
+
+/// Default for the `stale_days` configuration key.
+const DEFAULT_STALE_DAYS: u64 = 14;
+
+/// Default for the `refresh_interval` configuration key, in seconds.
+const DEFAULT_REFRESH_INTERVAL: u64 = 5;
+
+/// Exit status of `zelligent remove` when the worktree has uncommitted changes.
+const REMOVE_DIRTY_EXIT: i32 = 2;
+
+/// A bulk operation over the marked worktrees. Items run one at a time:
+/// merges share the main checkout and removes share the branch refs.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Batch {
+    /// Command type of the items: `CMD_SPAWN`, `CMD_REMOVE` or `CMD_MERGE`.
+    pub cmd_type: &'static str,
+    pub total: usize,
+    /// Branch of the item in flight.
+    pub current: String,
+    pub queue: VecDeque<Action>,
+    /// Outcome per branch, in order; errors carry the item's status message.
+    pub results: Vec<(String, Result<(), String>)>,
 }
 
 /// Actions returned by key/event handlers, executed by the plugin shell.
@@ -36,11 +394,32 @@ pub struct Worktree {
 pub enum Action {
     None,
     Close,
-    Spawn(String),
-    Remove(String),
+    Spawn(SpawnRequest),
+    /// Switch to the existing tab for a branch and close the plugin.
+    Focus(String),
+    Remove(RemoveRequest),
     Refresh,
     FetchToplevel,
     FetchWorktreesAndBranches,
+    /// Fetch worktrees only; used to answer `list` over the pipe.
+    ListWorktrees,
+    /// Open a rendered KDL layout as new tab(s), then refresh.
+    OpenTab(String),
+    Merge(MergeRequest),
+    /// Several actions, executed in order.
+    Batch(Vec<Action>),
+}
+
+impl Action {
+    /// Branch a spawn, remove or merge applies to.
+    fn branch(&self) -> Option<&str> {
+        match self {
+            Action::Spawn(r) => Some(&r.branch),
+            Action::Remove(r) => Some(&r.branch),
+            Action::Merge(r) => Some(&r.branch),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Default)]
@@ -48,37 +427,153 @@ pub struct State {
     pub mode: Mode,
     pub repo_root: String,
     pub repo_name: String,
+    pub base_branch: String,
     pub worktrees: Vec<Worktree>,
-    pub branches: Vec<String>,
-    pub filtered_branches: Vec<String>,
+    pub branches: Vec<Branch>,
+    pub filtered_branches: Vec<Branch>,
+    pub filter_query: String,
     pub selected_index: usize,
-    pub input_buffer: String,
-    pub agent_cmd: String,
+    pub input_buffer: TextInput,
+    /// While Tab cycles through branch names in `Mode::InputBranch`: the
+    /// typed prefix and the index of the completion shown.
+    pub completion: Option<(String, usize)>,
+    /// New branch name awaiting a base ref in `Mode::SelectBase`.
+    pub pending_branch: String,
+    pub agents: Vec<AgentProfile>,
+    /// `default_agent` from the plugin configuration.
+    pub default_agent: String,
+    /// `default_agent` from `show-repo`; takes precedence over the plugin's.
+    pub repo_default_agent: String,
+    /// Spawn waiting for a prompt in `Mode::InputPrompt` or an agent choice
+    /// in `Mode::SelectAgent`.
+    pub pending_spawn: Option<SpawnRequest>,
+    /// Prepended to branch names generated from free text (e.g. `agent/`).
+    pub branch_prefix: String,
+    /// Whether new worktrees ask for a task prompt first.
+    pub ask_prompt: bool,
+    pub prompt_buffer: TextInput,
+    pub agent_index: usize,
+    /// Remove to retry with `--force` in `Mode::ConfirmForceRemove`.
+    pub pending_remove: Option<RemoveRequest>,
+    /// Branches of the marked worktrees; actions apply to all of them.
+    pub marked: BTreeSet<String>,
+    pub batch: Option<Batch>,
+    /// Unmerged worktrees without commits for this many days are flagged stale.
+    pub stale_days: u64,
+    pub sort_order: SortOrder,
+    /// Whether the list is split into sections by branch prefix.
+    pub group_by_prefix: bool,
+    /// Prefix groups folded down to their header.
+    pub collapsed: BTreeSet<String>,
+    /// Worktree names in `git worktree list` order, for [`SortOrder::Git`].
+    pub listed_order: Vec<String>,
+    /// Seconds between background refreshes of the worktree list; 0 disables.
+    pub refresh_interval: u64,
+    /// Whether the plugin pane is shown; the list only refreshes while it is.
+    pub visible: bool,
+    /// Whether a refresh timer is scheduled, so ticks don't pile up.
+    pub timer_armed: bool,
     pub status_message: String,
     pub status_is_error: bool,
     pub zelligent_path: String,
     pub tabs: Vec<TabInfo>,
+    pub panes: PaneManifest,
+    /// Pipe commands received before the repo was resolved, with their CLI
+    /// pipe id (if any). Run once `show-repo` succeeds.
+    pub pending_pipes: Vec<(Option<String>, Action)>,
+    /// Whether browse mode shows the diff preview (toggled with `p`).
+    pub show_preview: bool,
+    pub preview: Option<Preview>,
 }
 
+// The macro exports unmangled `load`/`update`/`pipe`/`render` symbols; on a
+// native target `pipe` would shadow libc's, so only register for wasm (unit
+// tests run on the host).
+#[cfg(target_family = "wasm")]
 register_plugin!(State);
 
-/// Parse `zelligent list-worktrees` output (one branch per line).
-pub fn parse_worktrees(output: &str) -> Vec<Worktree> {
-    output
-        .lines()
-        .map(|l| l.trim().to_string())
-        .filter(|l| !l.is_empty())
-        .map(|branch| Worktree { branch })
-        .collect()
+#[cfg(not(target_family = "wasm"))]
+fn main() {}
+
+/// Status line while a batch runs, e.g. `Removing 2/5: 'feat-x'...`.
+fn batch_progress(batch: &Batch) -> String {
+    let verb = match batch.cmd_type {
+        CMD_SPAWN => "Opening",
+        CMD_MERGE => "Merging",
+        _ => "Removing",
+    };
+    format!("{verb} {}/{}: '{}'...", batch.results.len() + 1, batch.total, batch.current)
 }
 
-/// Parse `git branch --format=%(refname:short)` output into a list of branch names.
-pub fn parse_branches(output: &str) -> Vec<String> {
-    output
-        .lines()
-        .map(|l| l.trim().to_string())
-        .filter(|l| !l.is_empty())
-        .collect()
+/// Per-item results of a finished batch, e.g.
+/// `Removed 2 of 3: ✓ feat-a  ✓ feat-b  ✗ feat-c (uncommitted changes)`.
+fn batch_summary(batch: &Batch) -> String {
+    let verb = match batch.cmd_type {
+        CMD_SPAWN => "Opened",
+        CMD_MERGE => "Merged",
+        _ => "Removed",
+    };
+    let ok = batch.results.iter().filter(|(_, r)| r.is_ok()).count();
+    let items: Vec<String> = batch
+        .results
+        .iter()
+        .map(|(branch, result)| match result {
+            Ok(()) => format!("✓ {branch}"),
+            Err(err) => format!("✗ {branch} ({err})"),
+        })
+        .collect();
+    format!("{verb} {ok} of {}: {}", batch.total, items.join("  "))
+}
+
+fn reply_pipe(pipe_id: &str, message: &str) {
+    cli_pipe_output(pipe_id, &format!("{message}\n"));
+    unblock_cli_pipe_input(pipe_id);
+}
+
+/// Worktree path an agent pane was started for: the layouts run the agent as
+/// `bash -c <cmd> zelligent-worktree <path>`, so the path survives tab renames.
+fn pane_worktree_path(pane: &PaneInfo) -> Option<&str> {
+    let cmd = pane.terminal_command.as_deref()?;
+    let at = cmd.rfind(" zelligent-worktree ")?;
+    Some(&cmd[at + " zelligent-worktree ".len()..])
+}
+
+fn is_agent_pane(pane: &PaneInfo) -> bool {
+    !pane.is_plugin
+        && pane
+            .terminal_command
+            .as_deref()
+            .is_some_and(|cmd| !cmd.trim_start().starts_with("lazygit"))
+}
+
+/// Parse versioned `zelligent --json` output. The schema version is checked
+/// before the payload so a mismatched script yields a clear error instead of
+/// a confusing field-level parse failure.
+pub fn parse_json<T: DeserializeOwned>(output: &str) -> Result<T, String> {
+    #[derive(Deserialize)]
+    struct Header {
+        version: u32,
+    }
+    let header: Header = serde_json::from_str(output)
+        .map_err(|e| format!("Invalid JSON from zelligent: {e}"))?;
+    if header.version != SCHEMA_VERSION {
+        return Err(format!(
+            "zelligent outputs schema v{}, plugin expects v{SCHEMA_VERSION}. \
+             Reinstall zelligent and the plugin from the same version.",
+            header.version
+        ));
+    }
+    serde_json::from_str(output).map_err(|e| format!("Invalid JSON from zelligent: {e}"))
+}
+
+/// Parse `zelligent list-worktrees --json` output.
+pub fn parse_worktrees(output: &str) -> Result<Vec<Worktree>, String> {
+    parse_json::<WorktreeList>(output).map(|list| list.worktrees)
+}
+
+/// Parse `zelligent list-branches --json` output.
+pub fn parse_branches(output: &str) -> Result<Vec<Branch>, String> {
+    parse_json::<BranchList>(output).map(|list| list.branches)
 }
 
 /// Wrapping navigation: move `current` by `delta` within `[0, len)`, wrapping around.
@@ -96,62 +591,127 @@ impl State {
         m
     }
 
+    /// Command context that also records the CLI pipe waiting for the result.
+    fn pipe_ctx(cmd_type: &str, pipe_id: Option<&str>) -> BTreeMap<String, String> {
+        let mut m = Self::ctx(cmd_type);
+        if let Some(pipe_id) = pipe_id {
+            m.insert("pipe_id".to_string(), pipe_id.to_string());
+        }
+        m
+    }
+
     fn fire_git_toplevel(&self) {
         run_command(
-            &[&self.zelligent_path, "show-repo"],
+            &[&self.zelligent_path, "show-repo", "--json"],
             Self::ctx(CMD_GIT_TOPLEVEL),
         );
     }
 
-    fn fire_list_worktrees(&self) {
+    fn fire_list_worktrees(&self, pipe_id: Option<&str>) {
         run_command_with_env_variables_and_cwd(
-            &[&self.zelligent_path, "list-worktrees"],
+            &[&self.zelligent_path, "list-worktrees", "--json"],
             BTreeMap::new(),
             PathBuf::from(&self.repo_root),
-            Self::ctx(CMD_LIST_WORKTREES),
+            Self::pipe_ctx(CMD_LIST_WORKTREES, pipe_id),
         );
     }
 
     fn fire_git_branches(&self) {
         run_command_with_env_variables_and_cwd(
-            &[&self.zelligent_path, "list-branches"],
+            &[&self.zelligent_path, "list-branches", "--json"],
             BTreeMap::new(),
             PathBuf::from(&self.repo_root),
             Self::ctx(CMD_GIT_BRANCHES),
         );
     }
 
-    fn fire_spawn(&self, branch: &str) {
-        let mut env = BTreeMap::new();
-        if let Ok(val) = std::env::var("ZELLIJ") {
-            env.insert("ZELLIJ".to_string(), val);
+    /// Create the worktree with `zelligent prepare`; the tab is opened from
+    /// the result (see `handle_spawn_result`).
+    fn fire_spawn(&self, request: &SpawnRequest, pipe_id: Option<&str>) {
+        let branch = &request.branch;
+        let agent_cmd = self
+            .agent_for(request.agent.as_deref())
+            .map(AgentProfile::command_line)
+            .unwrap_or_default();
+
+        let mut ctx = Self::pipe_ctx(CMD_SPAWN, pipe_id);
+        ctx.insert("branch".to_string(), branch.to_string());
+        ctx.insert("agent_cmd".to_string(), agent_cmd);
+
+        let mut args = vec![self.zelligent_path.as_str(), "prepare"];
+        if let Some(base) = &request.base {
+            args.extend(["--base", base.as_str()]);
         }
-        if let Ok(val) = std::env::var("ZELLIJ_SESSION_NAME") {
-            env.insert("ZELLIJ_SESSION_NAME".to_string(), val);
+        if let Some(path) = &request.path {
+            args.extend(["--path", path.as_str()]);
         }
+        if let Some(prompt) = &request.prompt {
+            args.extend(["--prompt", prompt.as_str()]);
+        }
+        args.extend([branch.as_str(), "--json"]);
 
-        let mut ctx = Self::ctx(CMD_SPAWN);
-        ctx.insert("branch".to_string(), branch.to_string());
+        run_command_with_env_variables_and_cwd(
+            &args,
+            BTreeMap::new(),
+            PathBuf::from(&self.repo_root),
+            ctx,
+        );
+    }
+
+    /// Load the preview for `wt`: the diff of its working tree against the
+    /// merge base with the base branch, and its last few commits.
+    fn fire_preview(&self, wt: &Worktree) {
+        for (section, args) in [
+            ("changes", vec!["git", "diff", "--numstat", "--merge-base", self.base_branch.as_str()]),
+            ("commits", vec!["git", "log", "--format=%h %s (%cr)", "-n", "5"]),
+        ] {
+            let mut ctx = Self::ctx(CMD_PREVIEW);
+            ctx.insert("branch".to_string(), wt.name());
+            ctx.insert("section".to_string(), section.to_string());
+            run_command_with_env_variables_and_cwd(&args, BTreeMap::new(), PathBuf::from(&wt.path), ctx);
+        }
+    }
 
+    fn fire_merge(&self, request: &MergeRequest) {
+        let mut ctx = Self::ctx(CMD_MERGE);
+        ctx.insert("branch".to_string(), request.branch.clone());
         run_command_with_env_variables_and_cwd(
-            &[&self.zelligent_path, "spawn", branch, &self.agent_cmd],
-            env,
+            &[
+                &self.zelligent_path,
+                "merge",
+                "--strategy",
+                request.strategy.as_arg(),
+                &request.branch,
+                "--json",
+            ],
+            BTreeMap::new(),
             PathBuf::from(&self.repo_root),
             ctx,
         );
     }
 
-    fn fire_remove(&self, branch: &str) {
+    fn fire_remove(&self, request: &RemoveRequest, pipe_id: Option<&str>) {
         let mut env = BTreeMap::new();
         if let Ok(val) = std::env::var("ZELLIJ") {
             env.insert("ZELLIJ".to_string(), val);
         }
 
-        let mut ctx = Self::ctx(CMD_REMOVE);
-        ctx.insert("branch".to_string(), branch.to_string());
+        let mut ctx = Self::pipe_ctx(CMD_REMOVE, pipe_id);
+        ctx.insert("branch".to_string(), request.branch.clone());
+
+        let mut cmd = vec![self.zelligent_path.as_str(), "remove"];
+        if request.force {
+            cmd.push("--force");
+            ctx.insert("force".to_string(), "true".to_string());
+        }
+        if let Some(flag) = request.branch_cleanup.flag() {
+            cmd.push(flag);
+            ctx.insert("branch_cleanup".to_string(), flag.to_string());
+        }
+        cmd.push(&request.branch);
 
         run_command_with_env_variables_and_cwd(
-            &[&self.zelligent_path, "remove", branch],
+            &cmd,
             env,
             PathBuf::from(&self.repo_root),
             ctx,
@@ -162,17 +722,79 @@ impl State {
         match action {
             Action::None => {}
             Action::Close => close_self(),
-            Action::Spawn(branch) => self.fire_spawn(branch),
-            Action::Remove(branch) => self.fire_remove(branch),
+            Action::Spawn(request) => self.fire_spawn(request, None),
+            Action::Focus(branch) => {
+                if let Some(tab) = self.tabs_for_branch(branch).first() {
+                    go_to_tab_name(&tab.name);
+                }
+                close_self();
+            }
+            Action::Remove(request) => self.fire_remove(request, None),
             Action::Refresh => {
-                self.fire_list_worktrees();
+                self.fire_list_worktrees(None);
                 self.fire_git_branches();
             }
             Action::FetchToplevel => self.fire_git_toplevel(),
             Action::FetchWorktreesAndBranches => {
-                self.fire_list_worktrees();
+                self.fire_list_worktrees(None);
+                self.fire_git_branches();
+            }
+            Action::ListWorktrees => self.fire_list_worktrees(None),
+            Action::OpenTab(layout) => {
+                new_tabs_with_layout(layout);
+                self.fire_list_worktrees(None);
                 self.fire_git_branches();
             }
+            Action::Merge(request) => self.fire_merge(request),
+            Action::Batch(actions) => {
+                for action in actions {
+                    self.execute(action);
+                }
+            }
+        }
+    }
+
+    /// Execute an action requested over the pipe. Commands that run
+    /// `zelligent` block the CLI pipe and answer from the command result;
+    /// the rest answer immediately. Unlike a keypress, focusing a tab leaves
+    /// the plugin open.
+    fn execute_pipe(&self, action: &Action, pipe_id: Option<&str>) {
+        match action {
+            Action::Spawn(request) => self.fire_spawn(request, pipe_id),
+            Action::Remove(request) => self.fire_remove(request, pipe_id),
+            Action::ListWorktrees => self.fire_list_worktrees(pipe_id),
+            Action::Focus(branch) => {
+                if let Some(tab) = self.tabs_for_branch(branch).first() {
+                    go_to_tab_name(&tab.name);
+                }
+                if let Some(pipe_id) = pipe_id {
+                    reply_pipe(pipe_id, &format!("focused '{branch}'"));
+                }
+                return;
+            }
+            other => {
+                self.execute(other);
+                return;
+            }
+        }
+        if let Some(pipe_id) = pipe_id {
+            block_cli_pipe_input(pipe_id);
+        }
+    }
+
+    /// Run (or fail) the pipe commands that arrived while loading.
+    fn flush_pending_pipes(&mut self) {
+        if self.repo_root.is_empty() && !self.status_is_error {
+            return;
+        }
+        for (pipe_id, action) in std::mem::take(&mut self.pending_pipes) {
+            if self.repo_root.is_empty() {
+                if let Some(pipe_id) = &pipe_id {
+                    reply_pipe(pipe_id, &format!("error: {}", self.status_message));
+                }
+            } else {
+                self.execute_pipe(&action, pipe_id.as_deref());
+            }
         }
     }
 
@@ -186,11 +808,17 @@ impl State {
             return Action::None;
         }
         let output = String::from_utf8_lossy(stdout);
-        for line in output.lines() {
-            if let Some(val) = line.strip_prefix("repo_root=") {
-                self.repo_root = val.to_string();
-            } else if let Some(val) = line.strip_prefix("repo_name=") {
-                self.repo_name = val.to_string();
+        match parse_json::<RepoInfo>(&output) {
+            Ok(info) => {
+                self.repo_root = info.repo_root;
+                self.repo_name = info.repo_name;
+                self.base_branch = info.base_branch;
+                self.repo_default_agent = info.default_agent;
+            }
+            Err(err) => {
+                self.status_message = err;
+                self.status_is_error = true;
+                return Action::None;
             }
         }
         if self.repo_root.is_empty() || self.repo_name.is_empty() {
@@ -210,9 +838,101 @@ impl State {
             return;
         }
         let output = String::from_utf8_lossy(stdout);
-        self.worktrees = parse_worktrees(&output);
-        if self.selected_index >= self.worktrees.len() && !self.worktrees.is_empty() {
-            self.selected_index = self.worktrees.len() - 1;
+        let worktrees = match parse_worktrees(&output) {
+            Ok(worktrees) => worktrees,
+            Err(err) => {
+                self.status_message = format!("Failed to list worktrees: {err}");
+                self.status_is_error = true;
+                return;
+            }
+        };
+        // Keep the selection on the same worktree when the list shifts.
+        let selected = self.worktrees.get(self.selected_index).map(Worktree::name);
+        self.listed_order = worktrees.iter().map(Worktree::name).collect();
+        let old = std::mem::replace(&mut self.worktrees, worktrees);
+        self.sort_worktrees();
+        match selected.and_then(|name| self.worktrees.iter().position(|wt| wt.name() == name)) {
+            Some(index) => self.selected_index = index,
+            None if self.selected_index >= self.worktrees.len() && !self.worktrees.is_empty() => {
+                self.selected_index = self.worktrees.len() - 1;
+            }
+            None => {}
+        }
+        self.snap_selection();
+        let worktrees = &self.worktrees;
+        self.marked.retain(|name| worktrees.iter().any(|wt| &wt.name() == name));
+        // Reload the preview if its worktree changed.
+        let find = |list: &[Worktree], name: &str| list.iter().find(|wt| wt.name() == name).cloned();
+        if let Some(preview) = &self.preview {
+            if find(&old, &preview.branch) != find(&self.worktrees, &preview.branch) {
+                self.preview = None;
+            }
+        }
+    }
+
+    /// Refresh tick; the next one is scheduled after the event is handled.
+    pub fn handle_timer(&mut self) -> Action {
+        self.timer_armed = false;
+        self.background_refresh()
+    }
+
+    /// Reload the worktree list in the background unless the user is in the
+    /// middle of something the list backs (a prompt, a confirmation or a
+    /// running batch).
+    pub fn background_refresh(&self) -> Action {
+        if self.visible && self.mode == Mode::BrowseWorktrees && self.batch.is_none() && !self.repo_root.is_empty() {
+            Action::ListWorktrees
+        } else {
+            Action::None
+        }
+    }
+
+    /// Whether to schedule the next refresh tick.
+    pub fn wants_timer(&self) -> bool {
+        self.visible && self.refresh_interval > 0 && !self.timer_armed
+    }
+
+    /// Start a preview for the highlighted worktree if it is shown and not
+    /// already loaded or loading. Returns the worktree to load it for.
+    pub fn start_preview(&mut self) -> Option<Worktree> {
+        if !self.show_preview || self.mode != Mode::BrowseWorktrees {
+            return None;
+        }
+        let wt = self.selected_worktree()?.clone();
+        if self.preview.as_ref().is_some_and(|p| p.branch == wt.name()) {
+            return None;
+        }
+        self.preview = Some(Preview { branch: wt.name(), ..Default::default() });
+        Some(wt)
+    }
+
+    pub fn handle_preview_result(
+        &mut self,
+        exit_code: Option<i32>,
+        stdout: &[u8],
+        stderr: &[u8],
+        context: &BTreeMap<String, String>,
+    ) {
+        // Results for a worktree that is no longer highlighted are dropped.
+        let Some(preview) = self.preview.as_mut().filter(|p| Some(&p.branch) == context.get("branch")) else {
+            return;
+        };
+        let output = String::from_utf8_lossy(stdout);
+        let error = (exit_code != Some(0)).then(|| String::from_utf8_lossy(stderr).trim().to_string());
+        match context.get("section").map(String::as_str) {
+            Some("changes") => {
+                preview.changes = Some(match error {
+                    Some(err) => Err(err),
+                    None => Ok(parse_numstat(&output)),
+                });
+            }
+            Some("commits") => {
+                preview.commits = Some(match error {
+                    Some(err) => Err(err),
+                    None => Ok(output.lines().map(str::to_string).collect()),
+                });
+            }
+            _ => {}
         }
     }
 
@@ -224,36 +944,164 @@ impl State {
             return;
         }
         let output = String::from_utf8_lossy(stdout);
-        self.branches = parse_branches(&output);
+        match parse_branches(&output) {
+            Ok(branches) => self.branches = branches,
+            Err(err) => {
+                self.status_message = format!("Failed to list branches: {err}");
+                self.status_is_error = true;
+            }
+        }
     }
 
-    pub fn handle_spawn_result(&mut self, exit_code: Option<i32>, stderr: &[u8], context: &BTreeMap<String, String>) -> Action {
+    pub fn handle_spawn_result(
+        &mut self,
+        exit_code: Option<i32>,
+        stdout: &[u8],
+        stderr: &[u8],
+        context: &BTreeMap<String, String>,
+    ) -> Action {
         let branch = context.get("branch").cloned().unwrap_or_default();
-        if exit_code == Some(0) {
-            self.status_message = format!("Spawned '{branch}'");
-            self.status_is_error = false;
-        } else {
+        if exit_code != Some(0) {
             let err = String::from_utf8_lossy(stderr).trim().to_string();
             self.status_message = format!("Error: {err}");
             self.status_is_error = true;
+            return Action::Refresh;
+        }
+        let layout = parse_json::<Prepared>(&String::from_utf8_lossy(stdout))
+            .and_then(|prepared| Self::spawn_layout(&prepared, context));
+        match layout {
+            Ok((layout, warning)) => {
+                self.status_message = match warning {
+                    Some(warning) => format!("Spawned '{branch}' ({warning})"),
+                    None => format!("Spawned '{branch}'"),
+                };
+                self.status_is_error = false;
+                Action::OpenTab(layout)
+            }
+            Err(err) => {
+                self.status_message = format!("Error: {err}");
+                self.status_is_error = true;
+                Action::Refresh
+            }
+        }
+    }
+
+    /// Render the tab layout for a prepared worktree, plus a warning when a
+    /// custom layout leaves out setup.sh or the prompt has no agent to go to.
+    fn spawn_layout(prepared: &Prepared, context: &BTreeMap<String, String>) -> Result<(String, Option<String>), String> {
+        let mut warnings = Vec::new();
+        // Without an agent the pane runs a plain $SHELL, which would take an
+        // appended prompt for a script path; leave the prompt out instead.
+        let agent_cmd = match context.get("agent_cmd").filter(|cmd| !cmd.is_empty()) {
+            Some(cmd) => agent::with_prompt_file(cmd, &prepared.prompt_file),
+            None => {
+                if !prepared.prompt_file.is_empty() {
+                    warnings.push("no agent command; prompt not passed");
+                }
+                "$SHELL".to_string()
+            }
+        };
+        let setup_preamble = prepared.setup_preamble.clone();
+        let template = if prepared.layout_template.is_empty() {
+            layout::DEFAULT_TEMPLATE
+        } else {
+            &prepared.layout_template
+        };
+        if !setup_preamble.is_empty() && !template.contains(layout::SETUP_PREAMBLE_PLACEHOLDER) {
+            warnings.push("layout.kdl has no {{setup_preamble}}; setup.sh skipped");
+        }
+        let warning = (!warnings.is_empty()).then(|| warnings.join("; "));
+        let vars = LayoutVars {
+            cwd: prepared.worktree_path.clone(),
+            agent_cmd,
+            branch: prepared.branch.clone(),
+            repo_root: prepared.repo_root.clone(),
+            setup_preamble,
+            prompt_file: if prepared.prompt_file.is_empty() { "/dev/null".into() } else { prepared.prompt_file.clone() },
+        };
+        let layout = layout::tab_layout(template, &vars, &prepared.branch)?;
+        Ok((layout, warning))
+    }
+
+    /// On success, offer to remove the merged worktree; conflicts and other
+    /// failures are reported and leave everything as it was.
+    pub fn handle_merge_result(
+        &mut self,
+        exit_code: Option<i32>,
+        stdout: &[u8],
+        stderr: &[u8],
+        context: &BTreeMap<String, String>,
+    ) -> Action {
+        let branch = context.get("branch").cloned().unwrap_or_default();
+        self.mode = Mode::BrowseWorktrees;
+        let outcome = match exit_code {
+            Some(0) | Some(MERGE_CONFLICT_EXIT) => parse_json::<MergeOutcome>(&String::from_utf8_lossy(stdout)),
+            _ => Err(String::from_utf8_lossy(stderr).trim().to_string()),
+        };
+        match outcome {
+            Ok(outcome) if exit_code == Some(0) => {
+                self.status_message = format!(
+                    "Merged '{}' into '{}' ({}). Remove the worktree?",
+                    outcome.branch, outcome.base, outcome.strategy
+                );
+                self.status_is_error = false;
+                if let Some(idx) = self.worktrees.iter().position(|wt| wt.name() == outcome.branch) {
+                    self.selected_index = idx;
+                    self.mode = Mode::Confirming;
+                }
+                Action::Refresh
+            }
+            Ok(outcome) => {
+                self.status_message = format!(
+                    "Conflicts merging '{}' into '{}'; nothing was changed: {}",
+                    outcome.branch,
+                    outcome.base,
+                    outcome.conflicts.join(", ")
+                );
+                self.status_is_error = true;
+                Action::None
+            }
+            Err(err) => {
+                self.status_message = format!("Merge of '{branch}' failed: {err}");
+                self.status_is_error = true;
+                Action::None
+            }
         }
-        Action::Refresh
     }
 
     pub fn handle_remove_result(&mut self, exit_code: Option<i32>, stderr: &[u8], context: &BTreeMap<String, String>) -> Action {
         let branch = context.get("branch").cloned().unwrap_or_default();
+        let branch_cleanup = context
+            .get("branch_cleanup")
+            .and_then(|flag| BranchCleanup::from_flag(flag))
+            .unwrap_or_default();
+        if exit_code == Some(REMOVE_DIRTY_EXIT) && !context.contains_key("pipe_id") {
+            self.status_message = format!(
+                "'{branch}' has uncommitted changes. Force remove? They will be saved to branch 'zelligent/backup/{branch}'."
+            );
+            self.status_is_error = true;
+            self.pending_remove = Some(RemoveRequest { branch, branch_cleanup, force: true });
+            self.mode = Mode::ConfirmForceRemove;
+            return Action::None;
+        }
         if exit_code == Some(0) {
-            self.status_message = format!("Removed '{branch}'");
+            let mut message = format!("Removed '{branch}'");
+            if branch_cleanup != BranchCleanup::Keep {
+                message.push_str(" and deleted its branch");
+            }
+            if context.contains_key("force") {
+                message.push_str(&format!("; changes saved to 'zelligent/backup/{branch}'"));
+            }
+            self.status_message = message;
             self.status_is_error = false;
             // Close the worktree's tab if it exists. We use go_to_tab_name
             // instead of close_tab_with_index because the latter expects an
             // internal tab index, but TabInfo only exposes position (which
             // diverges from index when tabs are closed).
             #[cfg(target_arch = "wasm32")]
-            if self.has_tab_for_branch(&branch) {
-                let tab_name = Self::tab_name_for_branch(&branch);
+            if let Some(tab) = self.tabs_for_branch(&branch).first() {
                 let return_tab = self.tabs.iter().find(|t| t.active).map(|t| t.name.clone());
-                go_to_tab_name(&tab_name);
+                go_to_tab_name(&tab.name);
                 close_focused_tab();
                 if let Some(name) = return_tab {
                     go_to_tab_name(&name);
@@ -268,54 +1116,654 @@ impl State {
         Action::Refresh
     }
 
-    /// Convert a branch name to the corresponding Zellij tab name.
-    /// Tab names use the branch with `/` replaced by `-` (matching zelligent.sh).
-    pub fn tab_name_for_branch(branch: &str) -> String {
-        branch.replace('/', "-")
+    /// Re-rank `filtered_branches` against `filter_query` and reset the selection.
+    /// When picking a base with no query, the default base branch comes first.
+    fn refilter_branches(&mut self) {
+        self.filtered_branches = fuzzy::rank(&self.filter_query, &self.branches, |b| &b.name)
+            .into_iter()
+            .map(|i| self.branches[i].clone())
+            .collect();
+        // Without a query, branches being worked on come first, then the
+        // most recently committed to.
+        if self.mode == Mode::SelectBranch && self.filter_query.is_empty() {
+            let worktree_branches: BTreeSet<&str> = self.worktrees.iter().map(|wt| wt.branch.as_str()).collect();
+            self.filtered_branches.sort_by_key(|b| {
+                let active = b.remote.is_none() && worktree_branches.contains(b.name.as_str());
+                (!active, std::cmp::Reverse(b.last_commit_time))
+            });
+        }
+        if self.mode == Mode::SelectBase && self.filter_query.is_empty() {
+            if let Some(pos) = self
+                .filtered_branches
+                .iter()
+                .position(|b| b.remote.is_none() && b.name == self.base_branch)
+            {
+                let base = self.filtered_branches.remove(pos);
+                self.filtered_branches.insert(0, base);
+            }
+        }
+        self.selected_index = 0;
     }
 
-    /// Check whether a tab with the given branch's name exists.
-    pub fn has_tab_for_branch(&self, branch: &str) -> bool {
-        let tab_name = Self::tab_name_for_branch(branch);
-        self.tabs.iter().any(|t| t.name == tab_name)
-    }
+    /// Shared key handling for the fuzzy branch lists: navigation, typing and
+    /// Backspace. Returns true if the key was consumed.
+    fn handle_filter_key(&mut self, key: &KeyWithModifier) -> bool {
+        let no_mod = key.has_no_modifiers();
+        let shift_only = key.key_modifiers.len() == 1
+            && key.key_modifiers.contains(&KeyModifier::Shift);
+        let ctrl_only = key.key_modifiers.len() == 1
+            && key.key_modifiers.contains(&KeyModifier::Ctrl);
+        let len = self.filtered_branches.len();
 
-    pub fn handle_key_browse(&mut self, key: &KeyWithModifier) -> Action {
-        if key.has_no_modifiers() {
-            match key.bare_key {
-                BareKey::Char('j') | BareKey::Down => {
-                    self.selected_index = wrap_navigate(self.selected_index, self.worktrees.len(), 1);
-                }
-                BareKey::Char('k') | BareKey::Up => {
-                    self.selected_index = wrap_navigate(self.selected_index, self.worktrees.len(), -1);
-                }
-                BareKey::Enter => {
-                    if let Some(wt) = self.worktrees.get(self.selected_index) {
-                        let branch = wt.branch.clone();
-                        self.status_message = format!("Spawning '{branch}'...");
+        match key.bare_key {
+            BareKey::Down if no_mod => {
+                self.selected_index = wrap_navigate(self.selected_index, len, 1);
+            }
+            BareKey::Char('n') if ctrl_only => {
+                self.selected_index = wrap_navigate(self.selected_index, len, 1);
+            }
+            BareKey::Up if no_mod => {
+                self.selected_index = wrap_navigate(self.selected_index, len, -1);
+            }
+            BareKey::Char('p') if ctrl_only => {
+                self.selected_index = wrap_navigate(self.selected_index, len, -1);
+            }
+            BareKey::Backspace if no_mod => {
+                if self.filter_query.pop().is_some() {
+                    self.refilter_branches();
+                }
+            }
+            BareKey::Char(c) if no_mod || shift_only => {
+                self.filter_query.push(c);
+                self.refilter_branches();
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    /// Index of the agent profile to preselect: the repo's default, then the
+    /// plugin's `default_agent`, then the first profile.
+    pub fn default_agent_index(&self) -> usize {
+        [&self.repo_default_agent, &self.default_agent]
+            .into_iter()
+            .filter(|name| !name.is_empty())
+            .find_map(|name| self.agents.iter().position(|a| &a.name == name))
+            .unwrap_or(0)
+    }
+
+    /// Look up a profile by name, falling back to the default profile.
+    fn agent_for(&self, name: Option<&str>) -> Option<&AgentProfile> {
+        name.and_then(|n| self.agents.iter().find(|a| a.name == n))
+            .or_else(|| self.agents.get(self.default_agent_index()))
+    }
+
+    /// Start a spawn that creates a worktree: ask for the agent's task first
+    /// if configured, then continue as [`Self::begin_spawn`].
+    fn begin_new_spawn(&mut self, request: SpawnRequest) -> Action {
+        if !self.ask_prompt {
+            let status = request.status();
+            return self.begin_spawn(request, status);
+        }
+        self.pending_spawn = Some(request);
+        self.prompt_buffer.clear();
+        self.mode = Mode::InputPrompt;
+        Action::None
+    }
+
+    /// Start a spawn. With more than one agent profile and none chosen yet,
+    /// park the request and ask which agent to launch; otherwise spawn now.
+    fn begin_spawn(&mut self, request: SpawnRequest, status: String) -> Action {
+        self.status_is_error = false;
+        if request.agent.is_none() && self.agents.len() > 1 {
+            self.pending_spawn = Some(request);
+            self.agent_index = self.default_agent_index();
+            self.mode = Mode::SelectAgent;
+            return Action::None;
+        }
+        self.status_message = status;
+        self.mode = Mode::BrowseWorktrees;
+        Action::Spawn(request)
+    }
+
+    /// Open an existing worktree: switch to its tab if one is open rather
+    /// than starting a second agent there, otherwise spawn a tab without
+    /// asking for a new task.
+    fn open_worktree(&mut self, request: SpawnRequest) -> Action {
+        if self.has_tab_for_branch(&request.branch) {
+            self.mode = Mode::BrowseWorktrees;
+            return Action::Focus(request.branch);
+        }
+        let status = format!("Spawning '{}'...", request.branch);
+        self.begin_spawn(request, status)
+    }
+
+    /// How to open the worktree `branch` is checked out in, if any.
+    fn worktree_request(&self, branch: &str) -> Option<SpawnRequest> {
+        self.worktrees
+            .iter()
+            .find(|wt| !wt.detached && wt.branch == branch)
+            .map(Worktree::spawn_request)
+    }
+
+    /// Tabs open for the worktree named `branch`. A tab whose agent pane
+    /// carries a worktree path (see [`pane_worktree_path`]) belongs to that
+    /// worktree whatever it is called now; other tabs, e.g. from custom
+    /// layouts, are matched by name: tabs are named after the branch
+    /// verbatim, as in zelligent.sh.
+    pub fn tabs_for_branch(&self, branch: &str) -> Vec<&TabInfo> {
+        let path = self.worktrees.iter().find(|wt| wt.name() == branch).map(|wt| wt.path.as_str());
+        self.tabs
+            .iter()
+            .filter(|t| {
+                let marked = self
+                    .panes
+                    .panes
+                    .get(&t.position)
+                    .and_then(|panes| panes.iter().find_map(pane_worktree_path));
+                match marked {
+                    Some(marked) => path == Some(marked),
+                    None => t.name == branch,
+                }
+            })
+            .collect()
+    }
+
+    /// Check whether a tab for the given branch exists.
+    pub fn has_tab_for_branch(&self, branch: &str) -> bool {
+        !self.tabs_for_branch(branch).is_empty()
+    }
+
+    /// Picker annotations for each entry in `filtered_branches`. Remote-only
+    /// branches have no local checkout, so they never have a worktree or tab.
+    pub fn branch_states(&self) -> Vec<ui::BranchState> {
+        self.filtered_branches
+            .iter()
+            .map(|b| {
+                let local = b.remote.is_none();
+                ui::BranchState {
+                    worktree: local && self.worktrees.iter().any(|wt| wt.branch == b.name),
+                    tab: local && self.has_tab_for_branch(&b.name),
+                    base: local && b.name == self.base_branch,
+                }
+            })
+            .collect()
+    }
+
+    /// Answer for a CLI pipe waiting on a `zelligent` command, if `context`
+    /// carries one: `list` gets the raw JSON, others a one-line summary.
+    /// Called after the result was handled, so a spawn whose layout failed
+    /// reports that error.
+    pub fn pipe_reply(
+        &self,
+        exit_code: Option<i32>,
+        stdout: &[u8],
+        stderr: &[u8],
+        context: &BTreeMap<String, String>,
+    ) -> Option<(String, String)> {
+        let pipe_id = context.get("pipe_id")?.clone();
+        let branch = context.get("branch").map(String::as_str).unwrap_or_default();
+        let message = if exit_code != Some(0) {
+            format!("error: {}", String::from_utf8_lossy(stderr).trim())
+        } else {
+            match context.get("cmd_type").map(String::as_str) {
+                Some(CMD_LIST_WORKTREES) => String::from_utf8_lossy(stdout).trim().to_string(),
+                Some(CMD_SPAWN) if self.status_is_error => format!("error: {}", self.status_message),
+                Some(CMD_SPAWN) => format!("spawned '{branch}'"),
+                Some(CMD_REMOVE) => format!("removed '{branch}'"),
+                _ => "ok".to_string(),
+            }
+        };
+        Some((pipe_id, message))
+    }
+
+    /// Parse a pipe payload such as `spawn --base main feature/x` into the
+    /// action a keypress would produce. Words are split on whitespace only;
+    /// branches, refs and agent names never need quoting, so quotes are
+    /// rejected rather than taken literally.
+    pub fn handle_pipe_command(&self, payload: &str) -> Result<Action, String> {
+        if payload.contains(['"', '\'']) {
+            return Err("quotes are not supported; arguments are separated by spaces".to_string());
+        }
+        let mut words = payload.split_whitespace();
+        let command = words.next().unwrap_or_default();
+        let args: Vec<&str> = words.collect();
+        match (command, args.as_slice()) {
+            ("list", []) => Ok(Action::ListWorktrees),
+            ("remove", args) => {
+                let mut request = RemoveRequest::default();
+                for arg in args {
+                    match *arg {
+                        "--force" => request.force = true,
+                        flag if flag.starts_with("--") => {
+                            request.branch_cleanup =
+                                BranchCleanup::from_flag(flag).ok_or_else(|| format!("unknown option {flag}"))?;
+                        }
+                        branch if request.branch.is_empty() => request.branch = branch.to_string(),
+                        _ => return Err(PIPE_USAGE.to_string()),
+                    }
+                }
+                if request.branch.is_empty() {
+                    return Err(PIPE_USAGE.to_string());
+                }
+                Ok(Action::Remove(request))
+            }
+            ("focus", [branch]) if self.has_tab_for_branch(branch) => Ok(Action::Focus(branch.to_string())),
+            ("focus", [branch]) => Err(format!("no tab open for '{branch}'")),
+            ("spawn", args) => {
+                let mut request = SpawnRequest::default();
+                let mut args = args.iter();
+                while let Some(arg) = args.next() {
+                    match *arg {
+                        "--base" => request.base = args.next().map(|s| s.to_string()),
+                        "--agent" => request.agent = args.next().map(|s| s.to_string()),
+                        branch if request.branch.is_empty() => request.branch = branch.to_string(),
+                        _ => return Err(PIPE_USAGE.to_string()),
+                    }
+                }
+                if request.branch.is_empty() {
+                    return Err(PIPE_USAGE.to_string());
+                }
+                if let Some(agent) = &request.agent {
+                    if !self.agents.iter().any(|a| &a.name == agent) {
+                        return Err(format!("unknown agent '{agent}'"));
+                    }
+                }
+                Ok(Action::Spawn(request))
+            }
+            _ => Err(PIPE_USAGE.to_string()),
+        }
+    }
+
+    /// Status of the agent in the tab(s) for `branch`. The agent pane is the
+    /// command pane that isn't lazygit (see the layouts in zelligent.sh); if
+    /// several tabs are open for the branch, any running agent wins.
+    pub fn agent_status(&self, branch: &str) -> AgentStatus {
+        let tabs = self.tabs_for_branch(branch);
+        if tabs.is_empty() {
+            return AgentStatus::NoTab;
+        }
+        let agents: Vec<&PaneInfo> = tabs
+            .iter()
+            .filter_map(|t| self.panes.panes.get(&t.position))
+            .flatten()
+            .filter(|p| is_agent_pane(p))
+            .collect();
+        if agents.iter().any(|p| !p.exited) {
+            return AgentStatus::Running;
+        }
+        match agents.first() {
+            Some(pane) => AgentStatus::Exited(pane.exit_status),
+            None => AgentStatus::Unknown,
+        }
+    }
+
+    /// Order `worktrees` by `sort_order`, and by prefix group if grouping.
+    fn sort_worktrees(&mut self) {
+        // Agent status finds marked tabs through `self.worktrees`, so look it
+        // up before the list is taken out for sorting.
+        let agents: BTreeMap<String, AgentStatus> = self
+            .worktrees
+            .iter()
+            .map(|wt| (wt.name(), self.agent_status(&wt.name())))
+            .collect();
+        let mut worktrees = std::mem::take(&mut self.worktrees);
+        let listed = &self.listed_order;
+        order::sort(
+            &mut worktrees,
+            self.sort_order,
+            self.group_by_prefix,
+            |wt| listed.iter().position(|name| *name == wt.name()).unwrap_or(usize::MAX),
+            |wt| agents.get(&wt.name()).copied().unwrap_or(AgentStatus::NoTab),
+        );
+        self.worktrees = worktrees;
+    }
+
+    /// Re-sort after the order or grouping changed, keeping the selection.
+    fn resort(&mut self) {
+        let selected = self.worktrees.get(self.selected_index).map(Worktree::name);
+        self.sort_worktrees();
+        if let Some(index) = selected.and_then(|name| self.worktrees.iter().position(|wt| wt.name() == name)) {
+            self.selected_index = index;
+        }
+        self.snap_selection();
+    }
+
+    /// The collapsed group the selected worktree is folded into, with the
+    /// worktrees it covers.
+    fn selected_collapsed_group(&self) -> Option<(&str, std::ops::Range<usize>)> {
+        if !self.group_by_prefix {
+            return None;
+        }
+        order::groups(&self.worktrees)
+            .into_iter()
+            .find(|(name, range)| range.contains(&self.selected_index) && self.collapsed.contains(*name))
+    }
+
+    /// Inside a collapsed group the cursor sits on the header, which stands
+    /// for the group's first worktree.
+    fn snap_selection(&mut self) {
+        if let Some((_, range)) = self.selected_collapsed_group() {
+            self.selected_index = range.start;
+        }
+    }
+
+    /// The highlighted worktree; `None` when a collapsed group's header is.
+    pub fn selected_worktree(&self) -> Option<&Worktree> {
+        if self.selected_collapsed_group().is_some() {
+            return None;
+        }
+        self.worktrees.get(self.selected_index)
+    }
+
+    /// Worktree index behind each row the cursor can stop on: every
+    /// worktree, except that a collapsed group has one stop for its header.
+    fn list_stops(&self) -> Vec<usize> {
+        if !self.group_by_prefix {
+            return (0..self.worktrees.len()).collect();
+        }
+        order::groups(&self.worktrees)
+            .into_iter()
+            .flat_map(|(name, range)| match self.collapsed.contains(name) {
+                true => range.start..range.start + 1,
+                false => range,
+            })
+            .collect()
+    }
+
+    fn navigate_list(&mut self, delta: isize) {
+        let stops = self.list_stops();
+        let current = stops.iter().position(|&i| i == self.selected_index).unwrap_or_default();
+        if let Some(&index) = stops.get(wrap_navigate(current, stops.len(), delta)) {
+            self.selected_index = index;
+        }
+    }
+
+    /// Fold or unfold the group of the highlighted row.
+    fn toggle_group(&mut self) {
+        if !self.group_by_prefix {
+            return;
+        }
+        let Some(group) = self.worktrees.get(self.selected_index).map(|wt| order::group_of(wt).to_string()) else {
+            return;
+        };
+        if !self.collapsed.remove(&group) {
+            self.collapsed.insert(group);
+        }
+        self.snap_selection();
+    }
+
+    /// Rows of the list as shown, and the one the cursor is on. With
+    /// grouping, each group gets a header and collapsed groups show nothing
+    /// else.
+    pub fn list_rows(&self, now: u64) -> (Vec<ui::ListRow>, usize) {
+        let row = |i: usize| {
+            let wt = &self.worktrees[i];
+            ui::ListRow::Worktree(
+                i,
+                ui::RowState {
+                    agent: self.agent_status(&wt.name()),
+                    marked: self.marked.contains(&wt.name()),
+                    stale: self.is_stale(wt, now),
+                },
+            )
+        };
+        if !self.group_by_prefix {
+            return ((0..self.worktrees.len()).map(row).collect(), self.selected_index);
+        }
+        let mut rows = Vec::new();
+        let mut selected = 0;
+        for (name, range) in order::groups(&self.worktrees) {
+            let collapsed = self.collapsed.contains(name);
+            if collapsed && range.contains(&self.selected_index) {
+                selected = rows.len();
+            }
+            rows.push(ui::ListRow::Group { name: name.to_string(), len: range.len(), collapsed });
+            if !collapsed {
+                for i in range {
+                    if i == self.selected_index {
+                        selected = rows.len();
+                    }
+                    rows.push(row(i));
+                }
+            }
+        }
+        (rows, selected)
+    }
+
+    /// Worktrees an action applies to: the marked ones in list order, or the
+    /// selected one.
+    pub fn target_worktrees(&self) -> Vec<&Worktree> {
+        if self.marked.is_empty() {
+            return self.selected_worktree().into_iter().collect();
+        }
+        self.worktrees.iter().filter(|wt| self.marked.contains(&wt.name())).collect()
+    }
+
+    /// Names of [`Self::target_worktrees`].
+    pub fn target_branches(&self) -> Vec<String> {
+        self.target_worktrees().iter().map(|wt| wt.name()).collect()
+    }
+
+    /// Refuse to remove or merge worktrees that don't fit the operation:
+    /// zelligent only removes worktrees it created, and a detached HEAD has no
+    /// branch to merge. Returns whether the targets are fine.
+    fn check_targets(&mut self, op: &str) -> bool {
+        let (rejected, reason): (Vec<String>, &str) = if op == "remove" {
+            let names = self
+                .target_worktrees()
+                .iter()
+                .filter(|wt| !wt.managed || wt.detached)
+                .map(|wt| wt.name())
+                .collect();
+            (names, "not a zelligent branch worktree; remove with `git worktree remove`")
+        } else {
+            let names = self.target_worktrees().iter().filter(|wt| wt.detached).map(|wt| wt.name()).collect();
+            (names, "detached HEAD, no branch to merge")
+        };
+        if rejected.is_empty() {
+            return true;
+        }
+        self.status_message = format!("Cannot {op} {}: {reason}", rejected.join(", "));
+        self.status_is_error = true;
+        false
+    }
+
+    /// Unmerged and without commits for `stale_days` days.
+    pub fn is_stale(&self, wt: &Worktree, now: u64) -> bool {
+        !wt.merged
+            && self.stale_days > 0
+            && wt.last_commit_time > 0
+            && now.saturating_sub(wt.last_commit_time) >= self.stale_days * 86_400
+    }
+
+    /// Mark or unmark the highlighted worktree; on a collapsed group's
+    /// header, the whole group.
+    fn toggle_mark(&mut self) {
+        if let Some((_, range)) = self.selected_collapsed_group() {
+            let names: Vec<String> = self.worktrees[range].iter().map(Worktree::name).collect();
+            if names.iter().all(|name| self.marked.contains(name)) {
+                names.iter().for_each(|name| {
+                    self.marked.remove(name);
+                });
+            } else {
+                self.marked.extend(names);
+            }
+            return;
+        }
+        let Some(wt) = self.worktrees.get(self.selected_index) else {
+            return;
+        };
+        if !self.marked.remove(&wt.name()) {
+            self.marked.insert(wt.name());
+        }
+    }
+
+    /// Start running `actions` one at a time; returns the first.
+    pub fn begin_batch(&mut self, cmd_type: &'static str, actions: Vec<Action>) -> Action {
+        let mut queue = VecDeque::from(actions);
+        let Some(first) = queue.pop_front() else {
+            return Action::None;
+        };
+        let mut batch = Batch { cmd_type, total: queue.len() + 1, queue, ..Default::default() };
+        batch.current = first.branch().unwrap_or_default().to_string();
+        self.status_message = batch_progress(&batch);
+        self.status_is_error = false;
+        self.batch = Some(batch);
+        first
+    }
+
+    /// Record the result of a batch item, already handled like a single
+    /// command, and return the next item. Results that are not the batch's
+    /// item in flight pass through untouched.
+    pub fn continue_batch(&mut self, action: Action, context: &BTreeMap<String, String>) -> Action {
+        let Some(batch) = self.batch.as_mut() else {
+            return action;
+        };
+        if context.contains_key("pipe_id")
+            || context.get("cmd_type").map(String::as_str) != Some(batch.cmd_type)
+            || context.get("branch") != Some(&batch.current)
+        {
+            return action;
+        }
+
+        let result = if self.mode == Mode::ConfirmForceRemove {
+            Err("uncommitted changes".to_string())
+        } else if self.status_is_error {
+            Err(self.status_message.clone())
+        } else {
+            Ok(())
+        };
+        batch.results.push((std::mem::take(&mut batch.current), result));
+        // Items never stop for follow-up questions.
+        self.mode = Mode::BrowseWorktrees;
+        self.pending_remove = None;
+
+        let opened = matches!(action, Action::OpenTab(_)).then_some(action);
+        if let Some(next) = batch.queue.pop_front() {
+            batch.current = next.branch().unwrap_or_default().to_string();
+            self.status_message = batch_progress(batch);
+            self.status_is_error = false;
+            return Action::Batch(opened.into_iter().chain([next]).collect());
+        }
+
+        let batch = self.batch.take().unwrap_or_default();
+        // Merged worktrees stay marked so they can be removed next.
+        self.marked = if batch.cmd_type == CMD_MERGE {
+            batch.results.iter().filter(|(_, r)| r.is_ok()).map(|(b, _)| b.clone()).collect()
+        } else {
+            BTreeSet::new()
+        };
+        self.status_is_error = batch.results.iter().any(|(_, r)| r.is_err());
+        self.status_message = batch_summary(&batch);
+        Action::Batch(opened.into_iter().chain([Action::Refresh]).collect())
+    }
+
+    pub fn handle_key_browse(&mut self, key: &KeyWithModifier) -> Action {
+        if key.has_no_modifiers() {
+            match key.bare_key {
+                BareKey::Char('j') | BareKey::Down => self.navigate_list(1),
+                BareKey::Char('k') | BareKey::Up => self.navigate_list(-1),
+                BareKey::Char(' ') => {
+                    self.toggle_mark();
+                    self.navigate_list(1);
+                }
+                BareKey::Tab => self.toggle_group(),
+                BareKey::Char('s') => {
+                    self.sort_order = self.sort_order.next();
+                    self.resort();
+                    self.status_message = format!("Sorted by {}", self.sort_order.label());
+                    self.status_is_error = false;
+                }
+                BareKey::Char('g') => {
+                    self.group_by_prefix = !self.group_by_prefix;
+                    self.resort();
+                    self.status_message = match self.group_by_prefix {
+                        true => "Grouped by branch prefix".to_string(),
+                        false => "Not grouped".to_string(),
+                    };
+                    self.status_is_error = false;
+                }
+                BareKey::Char('a') => {
+                    if self.marked.len() == self.worktrees.len() {
+                        self.marked.clear();
+                    } else {
+                        self.marked = self.worktrees.iter().map(Worktree::name).collect();
+                    }
+                }
+                BareKey::Char('c') => {
+                    // Clean up: mark merged worktrees without edits and ask
+                    // to remove them.
+                    self.marked = self
+                        .worktrees
+                        .iter()
+                        .filter(|wt| wt.merged && wt.managed && wt.dirty == 0)
+                        .map(Worktree::name)
+                        .collect();
+                    if self.marked.is_empty() {
+                        self.status_message = "No merged worktrees to clean up".to_string();
+                        self.status_is_error = false;
+                    } else {
+                        self.status_message.clear();
+                        self.mode = Mode::Confirming;
+                    }
+                }
+                BareKey::Enter | BareKey::Char('o') if !self.marked.is_empty() => {
+                    // Enter skips worktrees that already have a tab; o opens
+                    // another one regardless.
+                    let actions: Vec<Action> = self
+                        .target_worktrees()
+                        .into_iter()
+                        .filter(|wt| key.bare_key == BareKey::Char('o') || !self.has_tab_for_branch(&wt.name()))
+                        .map(|wt| Action::Spawn(wt.spawn_request()))
+                        .collect();
+                    if actions.is_empty() {
+                        self.status_message = "All marked worktrees already have a tab".to_string();
                         self.status_is_error = false;
-                        return Action::Spawn(branch);
+                        return Action::None;
+                    }
+                    return self.begin_batch(CMD_SPAWN, actions);
+                }
+                BareKey::Enter if self.selected_collapsed_group().is_some() => self.toggle_group(),
+                BareKey::Enter => {
+                    if let Some(wt) = self.selected_worktree() {
+                        let request = wt.spawn_request();
+                        return self.open_worktree(request);
+                    }
+                }
+                BareKey::Char('o') => {
+                    if let Some(wt) = self.selected_worktree() {
+                        let request = wt.spawn_request();
+                        let status = format!("Spawning new tab for '{}'...", request.branch);
+                        return self.begin_spawn(request, status);
                     }
                 }
                 BareKey::Char('n') => {
-                    self.filtered_branches = self.branches.clone();
+                    self.filter_query.clear();
                     self.mode = Mode::SelectBranch;
-                    self.selected_index = 0;
+                    self.refilter_branches();
                 }
                 BareKey::Char('i') => {
                     self.mode = Mode::InputBranch;
                     self.input_buffer.clear();
                 }
-                BareKey::Char('d') => {
-                    if !self.worktrees.is_empty() {
-                        self.mode = Mode::Confirming;
-                    }
+                BareKey::Char('d') if !self.target_worktrees().is_empty() && self.check_targets("remove") => {
+                    self.mode = Mode::Confirming;
+                }
+                BareKey::Char('p') => {
+                    self.show_preview = !self.show_preview;
+                }
+                BareKey::Char('m') if !self.target_worktrees().is_empty() && self.check_targets("merge") => {
+                    self.mode = Mode::ChooseMerge;
                 }
                 BareKey::Char('r') => {
                     self.status_message = "Refreshed".to_string();
                     self.status_is_error = false;
                     return Action::Refresh;
                 }
+                BareKey::Esc if !self.marked.is_empty() => {
+                    self.marked.clear();
+                }
                 BareKey::Char('q') | BareKey::Esc => {
                     return Action::Close;
                 }
@@ -326,92 +1774,357 @@ impl State {
     }
 
     pub fn handle_key_select_branch(&mut self, key: &KeyWithModifier) -> Action {
-        if key.has_no_modifiers() {
-            match key.bare_key {
-                BareKey::Char('j') | BareKey::Down => {
-                    self.selected_index = wrap_navigate(self.selected_index, self.filtered_branches.len(), 1);
-                }
-                BareKey::Char('k') | BareKey::Up => {
-                    self.selected_index = wrap_navigate(self.selected_index, self.filtered_branches.len(), -1);
-                }
-                BareKey::Enter => {
-                    if let Some(branch) = self.filtered_branches.get(self.selected_index).cloned() {
-                        self.status_message = format!("Spawning '{branch}'...");
-                        self.status_is_error = false;
-                        self.mode = Mode::BrowseWorktrees;
-                        return Action::Spawn(branch);
+        if self.handle_filter_key(key) || !key.has_no_modifiers() {
+            return Action::None;
+        }
+        match key.bare_key {
+            BareKey::Enter => {
+                if let Some(branch) = self.filtered_branches.get(self.selected_index).map(|b| b.name.clone()) {
+                    if let Some(request) = self.worktree_request(&branch) {
+                        self.filter_query.clear();
+                        return self.open_worktree(request);
                     }
+                    if self.refuse_main_checkout(&branch) {
+                        return Action::None;
+                    }
+                    self.filter_query.clear();
+                    return self.begin_new_spawn(branch.into());
                 }
-                BareKey::Esc => {
-                    self.mode = Mode::BrowseWorktrees;
-                    self.selected_index = 0;
-                }
-                _ => {}
             }
+            BareKey::Esc => {
+                self.mode = Mode::BrowseWorktrees;
+                self.selected_index = 0;
+                self.filter_query.clear();
+            }
+            _ => {}
         }
         Action::None
     }
 
     pub fn handle_key_input_branch(&mut self, key: &KeyWithModifier) -> Action {
         let no_mod = key.has_no_modifiers();
-        let shift_only = key.key_modifiers.len() == 1
-            && key.key_modifiers.contains(&KeyModifier::Shift);
+        if key.bare_key != BareKey::Tab {
+            self.completion = None;
+        }
 
         match key.bare_key {
+            BareKey::Tab if no_mod => self.complete_branch(),
             BareKey::Enter if no_mod => {
-                let branch = self.input_buffer.trim().to_string();
-                if !branch.is_empty() {
-                    self.status_message = format!("Spawning '{branch}'...");
-                    self.status_is_error = false;
-                    self.mode = Mode::BrowseWorktrees;
-                    return Action::Spawn(branch);
+                let branch = self.input_buffer.text().trim().to_string();
+                if branch.is_empty() {
+                    return Action::None;
+                }
+                // Not a valid ref: replace the text with its slug, which the
+                // next Enter accepts.
+                if refname::validate(&branch).is_err() {
+                    let slug = refname::slugify(&branch, &self.branch_prefix);
+                    if !slug.is_empty() {
+                        self.input_buffer.set(&slug);
+                    }
+                    return Action::None;
+                }
+                if let Some(request) = self.worktree_request(&branch) {
+                    return self.open_worktree(request);
+                }
+                // Existing branches are reattached as-is; only new ones need a base.
+                if self.branches.iter().any(|b| b.name == branch) {
+                    if self.refuse_main_checkout(&branch) {
+                        return Action::None;
+                    }
+                    return self.begin_new_spawn(branch.into());
                 }
+                self.pending_branch = branch;
+                self.filter_query.clear();
+                self.mode = Mode::SelectBase;
+                self.refilter_branches();
             }
             BareKey::Esc if no_mod => {
                 self.mode = Mode::BrowseWorktrees;
                 self.selected_index = 0;
                 self.input_buffer.clear();
             }
-            BareKey::Backspace if no_mod => {
-                self.input_buffer.pop();
+            _ => {
+                self.input_buffer.handle_key(key);
             }
-            BareKey::Char(c) if no_mod || shift_only => {
-                self.input_buffer.push(c);
+        }
+        Action::None
+    }
+
+    pub fn handle_key_select_base(&mut self, key: &KeyWithModifier) -> Action {
+        if self.handle_filter_key(key) || !key.has_no_modifiers() {
+            return Action::None;
+        }
+        match key.bare_key {
+            BareKey::Enter => {
+                // A query that matches no branch is taken as a literal ref
+                // (tag or commit); an empty list with no query means the default.
+                let base = match self.filtered_branches.get(self.selected_index) {
+                    Some(b) => Some(b.ref_name()),
+                    None if !self.filter_query.trim().is_empty() => Some(self.filter_query.trim().to_string()),
+                    None => None,
+                };
+                let branch = std::mem::take(&mut self.pending_branch);
+                self.filter_query.clear();
+                self.input_buffer.clear();
+                self.selected_index = 0;
+                return self.begin_new_spawn(SpawnRequest { branch, base, ..Default::default() });
+            }
+            BareKey::Esc => {
+                self.mode = Mode::InputBranch;
+                self.filter_query.clear();
+                self.pending_branch.clear();
             }
             _ => {}
         }
         Action::None
     }
 
-    pub fn handle_key_confirming(&mut self, key: &KeyWithModifier) -> Action {
-        if key.has_no_modifiers() {
-            match key.bare_key {
-                BareKey::Char('y') => {
-                    if let Some(wt) = self.worktrees.get(self.selected_index) {
-                        let branch = wt.branch.clone();
-                        self.status_message = format!("Removing '{branch}'...");
-                        self.status_is_error = false;
-                        return Action::Remove(branch);
-                    }
-                }
-                BareKey::Char('n') | BareKey::Esc => {
-                    self.mode = Mode::BrowseWorktrees;
-                }
-                _ => {}
+    /// `git worktree add` can't check out a branch the main repo is on, so
+    /// stop here with a readable error instead of failing in the script.
+    fn refuse_main_checkout(&mut self, branch: &str) -> bool {
+        let checked_out = self.branches.iter().any(|b| b.remote.is_none() && b.name == branch && b.main_checkout);
+        if checked_out {
+            self.status_message = format!("'{branch}' is checked out in the main repo; switch it to another branch first");
+            self.status_is_error = true;
+        }
+        checked_out
+    }
+
+    /// Existing branch names extending `prefix`, shortest first.
+    pub fn branch_completions(&self, prefix: &str) -> Vec<&str> {
+        let mut names: Vec<&str> = self
+            .branches
+            .iter()
+            .map(|b| b.name.as_str())
+            .filter(|name| name.len() > prefix.len() && name.starts_with(prefix))
+            .collect();
+        names.sort_by_key(|name| (name.len(), *name));
+        names.dedup();
+        names
+    }
+
+    /// Rest of the first completion of the typed text, shown as ghost text
+    /// while the cursor is at the end.
+    pub fn completion_hint(&self) -> Option<&str> {
+        let (text, after) = self.input_buffer.split();
+        if text.is_empty() || !after.is_empty() || self.completion.is_some() {
+            return None;
+        }
+        self.branch_completions(text).first().map(|name| &name[text.len()..])
+    }
+
+    /// Tab: replace the typed prefix with its first completion; repeated
+    /// presses cycle through the others.
+    fn complete_branch(&mut self) {
+        let (prefix, index) = match self.completion.take() {
+            Some((prefix, index)) => (prefix, index + 1),
+            None => (self.input_buffer.text().to_string(), 0),
+        };
+        let candidates = self.branch_completions(&prefix);
+        if candidates.is_empty() {
+            return;
+        }
+        let index = index % candidates.len();
+        let name = candidates[index].to_string();
+        self.input_buffer.set(&name);
+        self.completion = Some((prefix, index));
+    }
+
+    /// Whether the typed branch name already has a worktree.
+    pub fn input_has_worktree(&self) -> bool {
+        let name = self.input_buffer.text().trim();
+        !name.is_empty() && self.worktrees.iter().any(|wt| wt.branch == name)
+    }
+
+    /// Multi-line prompt: Enter spawns (an empty prompt spawns without one),
+    /// Alt-Enter or Ctrl-j starts a new line.
+    pub fn handle_key_input_prompt(&mut self, key: &KeyWithModifier) -> Action {
+        let no_mod = key.has_no_modifiers();
+        let newline = match key.bare_key {
+            BareKey::Enter => key.has_modifiers(&[KeyModifier::Alt]),
+            BareKey::Char('j') => key.has_modifiers(&[KeyModifier::Ctrl]),
+            _ => false,
+        };
+        match key.bare_key {
+            _ if newline => self.prompt_buffer.insert('\n'),
+            BareKey::Enter if no_mod => {
+                let Some(mut request) = self.pending_spawn.take() else {
+                    return Action::None;
+                };
+                let prompt = self.prompt_buffer.take();
+                let prompt = prompt.trim();
+                request.prompt = (!prompt.is_empty()).then(|| prompt.to_string());
+                let status = request.status();
+                return self.begin_spawn(request, status);
+            }
+            BareKey::Esc if no_mod => {
+                self.pending_spawn = None;
+                self.prompt_buffer.clear();
+                self.mode = Mode::BrowseWorktrees;
+            }
+            _ => {
+                self.prompt_buffer.handle_key(key);
             }
         }
         Action::None
     }
-}
 
-impl ZellijPlugin for State {
-    fn load(&mut self, configuration: BTreeMap<String, String>) {
-        self.agent_cmd = configuration
-            .get("agent_cmd")
-            .cloned()
-            .unwrap_or_else(|| "claude".to_string());
+    pub fn handle_key_select_agent(&mut self, key: &KeyWithModifier) -> Action {
+        if !key.has_no_modifiers() {
+            return Action::None;
+        }
+        let chosen = match key.bare_key {
+            BareKey::Char('j') | BareKey::Down => {
+                self.agent_index = wrap_navigate(self.agent_index, self.agents.len(), 1);
+                None
+            }
+            BareKey::Char('k') | BareKey::Up => {
+                self.agent_index = wrap_navigate(self.agent_index, self.agents.len(), -1);
+                None
+            }
+            BareKey::Enter => Some(self.agent_index),
+            // 1-9 pick and launch in one keypress
+            BareKey::Char(c @ '1'..='9') => {
+                let idx = c as usize - '1' as usize;
+                (idx < self.agents.len()).then_some(idx)
+            }
+            BareKey::Esc => {
+                self.pending_spawn = None;
+                self.mode = Mode::BrowseWorktrees;
+                None
+            }
+            _ => None,
+        };
+        let Some(agent) = chosen.and_then(|idx| self.agents.get(idx)) else {
+            return Action::None;
+        };
+        let Some(mut request) = self.pending_spawn.take() else {
+            return Action::None;
+        };
+        self.status_message = format!("Spawning '{}' with {}...", request.branch, agent.name);
+        self.status_is_error = false;
+        self.mode = Mode::BrowseWorktrees;
+        request.agent = Some(agent.name.clone());
+        Action::Spawn(request)
+    }
 
-        self.zelligent_path = configuration
+    pub fn handle_key_choose_merge(&mut self, key: &KeyWithModifier) -> Action {
+        if !key.has_no_modifiers() {
+            return Action::None;
+        }
+        let strategy = match key.bare_key {
+            BareKey::Char('m') => MergeStrategy::Merge,
+            BareKey::Char('s') => MergeStrategy::Squash,
+            BareKey::Char('r') => MergeStrategy::Rebase,
+            BareKey::Esc | BareKey::Char('q') => {
+                self.mode = Mode::BrowseWorktrees;
+                return Action::None;
+            }
+            _ => return Action::None,
+        };
+        self.mode = Mode::BrowseWorktrees;
+        if !self.marked.is_empty() {
+            let actions = self
+                .target_branches()
+                .into_iter()
+                .map(|branch| Action::Merge(MergeRequest { branch, strategy }))
+                .collect();
+            return self.begin_batch(CMD_MERGE, actions);
+        }
+        let Some(wt) = self.selected_worktree() else {
+            return Action::None;
+        };
+        let branch = wt.name();
+        self.status_message = format!("Merging '{branch}' ({})...", strategy.as_arg());
+        self.status_is_error = false;
+        Action::Merge(MergeRequest { branch, strategy })
+    }
+
+    pub fn handle_key_confirming(&mut self, key: &KeyWithModifier) -> Action {
+        if !key.has_no_modifiers() {
+            return Action::None;
+        }
+        let branch_cleanup = match key.bare_key {
+            BareKey::Char('y') => BranchCleanup::Keep,
+            BareKey::Char('b') => BranchCleanup::DeleteMerged,
+            BareKey::Char('f') => BranchCleanup::ForceDelete,
+            BareKey::Char('n') | BareKey::Esc => {
+                self.mode = Mode::BrowseWorktrees;
+                return Action::None;
+            }
+            _ => return Action::None,
+        };
+        if !self.marked.is_empty() {
+            self.mode = Mode::BrowseWorktrees;
+            let actions = self
+                .target_branches()
+                .into_iter()
+                .map(|branch| Action::Remove(RemoveRequest { branch, branch_cleanup, force: false }))
+                .collect();
+            return self.begin_batch(CMD_REMOVE, actions);
+        }
+        let Some(wt) = self.selected_worktree() else {
+            return Action::None;
+        };
+        let branch = wt.name();
+        self.status_message = match branch_cleanup {
+            BranchCleanup::Keep => format!("Removing '{branch}'..."),
+            _ => format!("Removing '{branch}' and its branch..."),
+        };
+        self.status_is_error = false;
+        Action::Remove(RemoveRequest { branch, branch_cleanup, force: false })
+    }
+
+    pub fn handle_key_confirm_force_remove(&mut self, key: &KeyWithModifier) -> Action {
+        if !key.has_no_modifiers() {
+            return Action::None;
+        }
+        match key.bare_key {
+            BareKey::Char('y') => {
+                let Some(request) = self.pending_remove.take() else {
+                    self.mode = Mode::BrowseWorktrees;
+                    return Action::None;
+                };
+                self.status_message = format!("Force-removing '{}'...", request.branch);
+                self.status_is_error = false;
+                self.mode = Mode::BrowseWorktrees;
+                Action::Remove(request)
+            }
+            BareKey::Char('n') | BareKey::Esc => {
+                self.pending_remove = None;
+                self.status_message.clear();
+                self.status_is_error = false;
+                self.mode = Mode::BrowseWorktrees;
+                Action::None
+            }
+            _ => Action::None,
+        }
+    }
+}
+
+impl ZellijPlugin for State {
+    fn load(&mut self, configuration: BTreeMap<String, String>) {
+        self.agents = agent::parse_profiles(&configuration);
+        self.default_agent = configuration.get("default_agent").cloned().unwrap_or_default();
+        self.show_preview = configuration.get("preview").map(String::as_str) != Some("false");
+        self.ask_prompt = configuration.get("ask_prompt").map(String::as_str) != Some("false");
+        self.branch_prefix = configuration.get("branch_prefix").cloned().unwrap_or_default();
+        self.stale_days = configuration
+            .get("stale_days")
+            .and_then(|d| d.parse().ok())
+            .unwrap_or(DEFAULT_STALE_DAYS);
+        self.sort_order = configuration
+            .get("sort")
+            .and_then(|s| SortOrder::parse(s))
+            .unwrap_or_default();
+        self.group_by_prefix = configuration.get("group_by_prefix").map(String::as_str) == Some("true");
+        self.refresh_interval = configuration
+            .get("refresh_interval")
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(DEFAULT_REFRESH_INTERVAL);
+        self.visible = true;
+
+        self.zelligent_path = configuration
             .get("zelligent_path")
             .cloned()
             .unwrap_or_else(|| "zelligent".to_string());
@@ -422,543 +2135,2330 @@ impl ZellijPlugin for State {
             PermissionType::ReadApplicationState,
         ]);
 
-        subscribe(&[
-            EventType::Key,
-            EventType::RunCommandResult,
-            EventType::PermissionRequestResult,
-            EventType::TabUpdate,
-        ]);
+        subscribe(&[
+            EventType::Key,
+            EventType::RunCommandResult,
+            EventType::PermissionRequestResult,
+            EventType::TabUpdate,
+            EventType::PaneUpdate,
+            EventType::Timer,
+            EventType::Visible,
+        ]);
+    }
+
+    fn update(&mut self, event: Event) -> bool {
+        let action = match event {
+            Event::PermissionRequestResult(PermissionStatus::Granted) => {
+                Action::FetchToplevel
+            }
+            Event::PermissionRequestResult(PermissionStatus::Denied) => {
+                self.status_message = "Permissions denied. Plugin cannot run commands.".to_string();
+                self.status_is_error = true;
+                self.flush_pending_pipes();
+                Action::None
+            }
+            Event::RunCommandResult(exit_code, stdout, stderr, context) => {
+                let action = match context.get("cmd_type").map(|s| s.as_str()) {
+                    Some(CMD_GIT_TOPLEVEL) => {
+                        let action = self.handle_git_toplevel(exit_code, &stdout, &stderr);
+                        self.flush_pending_pipes();
+                        action
+                    }
+                    Some(CMD_LIST_WORKTREES) => {
+                        self.handle_list_worktrees(exit_code, &stdout, &stderr);
+                        Action::None
+                    }
+                    Some(CMD_GIT_BRANCHES) => {
+                        self.handle_git_branches(exit_code, &stdout, &stderr);
+                        Action::None
+                    }
+                    Some(CMD_SPAWN) => {
+                        let action = self.handle_spawn_result(exit_code, &stdout, &stderr, &context);
+                        self.continue_batch(action, &context)
+                    }
+                    Some(CMD_REMOVE) => {
+                        let action = self.handle_remove_result(exit_code, &stderr, &context);
+                        self.continue_batch(action, &context)
+                    }
+                    Some(CMD_MERGE) => {
+                        let action = self.handle_merge_result(exit_code, &stdout, &stderr, &context);
+                        self.continue_batch(action, &context)
+                    }
+                    Some(CMD_PREVIEW) => {
+                        self.handle_preview_result(exit_code, &stdout, &stderr, &context);
+                        Action::None
+                    }
+                    _ => Action::None,
+                };
+                if let Some((pipe_id, message)) = self.pipe_reply(exit_code, &stdout, &stderr, &context) {
+                    reply_pipe(&pipe_id, &message);
+                }
+                action
+            }
+            Event::TabUpdate(tab_info) => {
+                self.tabs = tab_info;
+                Action::None
+            }
+            Event::PaneUpdate(manifest) => {
+                self.panes = manifest;
+                Action::None
+            }
+            Event::Timer(_) => self.handle_timer(),
+            Event::Visible(visible) => {
+                let shown = visible && !self.visible;
+                self.visible = visible;
+                // Catch up on what changed while hidden.
+                if shown {
+                    self.background_refresh()
+                } else {
+                    Action::None
+                }
+            }
+            Event::Key(key) => {
+                match self.mode {
+                    Mode::Loading => Action::None,
+                    Mode::BrowseWorktrees => self.handle_key_browse(&key),
+                    Mode::SelectBranch => self.handle_key_select_branch(&key),
+                    Mode::InputBranch => self.handle_key_input_branch(&key),
+                    Mode::SelectBase => self.handle_key_select_base(&key),
+                    Mode::InputPrompt => self.handle_key_input_prompt(&key),
+                    Mode::SelectAgent => self.handle_key_select_agent(&key),
+                    Mode::ChooseMerge => self.handle_key_choose_merge(&key),
+                    Mode::Confirming => self.handle_key_confirming(&key),
+                    Mode::ConfirmForceRemove => self.handle_key_confirm_force_remove(&key),
+                }
+            }
+            _ => return false,
+        };
+        self.execute(&action);
+        if let Some(wt) = self.start_preview() {
+            self.fire_preview(&wt);
+        }
+        if self.wants_timer() {
+            self.timer_armed = true;
+            set_timeout(self.refresh_interval as f64);
+        }
+        true
+    }
+
+    fn pipe(&mut self, pipe_message: PipeMessage) -> bool {
+        if pipe_message.name != PIPE_NAME {
+            return false;
+        }
+        let pipe_id = match pipe_message.source {
+            PipeSource::Cli(pipe_id) => Some(pipe_id),
+            _ => None,
+        };
+        let payload = pipe_message.payload.unwrap_or_default();
+        match self.handle_pipe_command(&payload) {
+            Err(err) => {
+                if let Some(pipe_id) = &pipe_id {
+                    reply_pipe(pipe_id, &format!("error: {err}"));
+                }
+            }
+            Ok(action) if self.repo_root.is_empty() => {
+                if let Some(pipe_id) = &pipe_id {
+                    block_cli_pipe_input(pipe_id);
+                }
+                self.pending_pipes.push((pipe_id, action));
+            }
+            Ok(action) => self.execute_pipe(&action, pipe_id.as_deref()),
+        }
+        true
+    }
+
+    fn render(&mut self, rows: usize, cols: usize) {
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        match self.mode {
+            Mode::Loading => {
+                ui::render_header("loading...", cols);
+                if self.status_is_error {
+                    ui::render_status(&self.status_message, true);
+                } else {
+                    println!();
+                    println!("  Waiting for permissions...");
+                }
+            }
+            Mode::BrowseWorktrees => {
+                let mut view = Vec::new();
+                if self.sort_order != SortOrder::Git {
+                    view.push(format!("sorted by {}", self.sort_order.label()));
+                }
+                if self.group_by_prefix {
+                    view.push("grouped".to_string());
+                }
+                match view.is_empty() {
+                    true => ui::render_header(&self.repo_name, cols),
+                    false => ui::render_header(&format!("{} ({})", self.repo_name, view.join(", ")), cols),
+                }
+                let (list, selected) = self.list_rows(now);
+                // A collapsed group's header has no preview.
+                let preview = self
+                    .preview
+                    .as_ref()
+                    .filter(|_| self.show_preview && self.selected_worktree().is_some());
+                ui::render_worktree_list(
+                    &self.worktrees,
+                    &list,
+                    selected,
+                    preview.map(|p| (p, self.base_branch.as_str())),
+                    rows,
+                    cols,
+                    now,
+                );
+                ui::render_status(&self.status_message, self.status_is_error);
+                ui::render_footer(&self.mode);
+            }
+            Mode::SelectBranch => {
+                ui::render_header(&self.repo_name, cols);
+                ui::render_branch_list(
+                    "Select a branch:",
+                    &self.filtered_branches,
+                    &self.branch_states(),
+                    &self.filter_query,
+                    self.selected_index,
+                    rows,
+                    now,
+                );
+                ui::render_footer(&self.mode);
+            }
+            Mode::InputBranch => {
+                ui::render_header(&self.repo_name, cols);
+                let name = self.input_buffer.text().trim();
+                let problem = refname::validate(name)
+                    .err()
+                    .filter(|_| !name.is_empty())
+                    .map(|err| (err, refname::slugify(name, &self.branch_prefix)));
+                let warning = self
+                    .input_has_worktree()
+                    .then(|| format!("'{name}' already has a worktree; Enter opens it"));
+                ui::render_input(&self.input_buffer, self.completion_hint(), problem.as_ref(), warning.as_deref());
+                ui::render_footer(&self.mode);
+            }
+            Mode::SelectBase => {
+                ui::render_header(&self.repo_name, cols);
+                let title = format!("Base for '{}' (or type a tag/commit):", self.pending_branch);
+                ui::render_branch_list(
+                    &title,
+                    &self.filtered_branches,
+                    &self.branch_states(),
+                    &self.filter_query,
+                    self.selected_index,
+                    rows,
+                    now,
+                );
+                ui::render_footer(&self.mode);
+            }
+            Mode::InputPrompt => {
+                ui::render_header(&self.repo_name, cols);
+                let branch = self.pending_spawn.as_ref().map(|r| r.branch.as_str()).unwrap_or_default();
+                ui::render_prompt_input(branch, &self.prompt_buffer);
+                ui::render_footer(&self.mode);
+            }
+            Mode::SelectAgent => {
+                ui::render_header(&self.repo_name, cols);
+                let branch = self.pending_spawn.as_ref().map(|r| r.branch.as_str()).unwrap_or_default();
+                ui::render_agent_list(&self.agents, branch, self.agent_index, self.default_agent_index());
+                ui::render_footer(&self.mode);
+            }
+            Mode::ChooseMerge => {
+                ui::render_header(&self.repo_name, cols);
+                ui::render_merge_prompt(&self.target_branches(), &self.base_branch);
+            }
+            Mode::ConfirmForceRemove => {
+                ui::render_header(&self.repo_name, cols);
+                ui::render_status(&self.status_message, self.status_is_error);
+                ui::render_confirm_force();
+            }
+            Mode::Confirming => {
+                ui::render_header(&self.repo_name, cols);
+                ui::render_status(&self.status_message, self.status_is_error);
+                ui::render_confirm(&self.target_branches());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(bare: BareKey) -> KeyWithModifier {
+        KeyWithModifier { bare_key: bare, key_modifiers: BTreeSet::new() }
+    }
+
+    fn key_shift(bare: BareKey) -> KeyWithModifier {
+        let mut mods = BTreeSet::new();
+        mods.insert(KeyModifier::Shift);
+        KeyWithModifier { bare_key: bare, key_modifiers: mods }
+    }
+
+    fn wt(branch: &str) -> Worktree {
+        Worktree { branch: branch.into(), ..Default::default() }
+    }
+
+    fn state_with_worktrees() -> State {
+        State {
+            mode: Mode::BrowseWorktrees,
+            worktrees: vec![wt("feat-a"), wt("feat-b"), wt("feat-c")],
+            branches: vec!["main".into(), "feat-a".into(), "feat-b".into(), "dev".into()],
+            ..Default::default()
+        }
+    }
+
+    // --- Parsing tests ---
+
+    #[test]
+    fn parse_worktrees_basic() {
+        let output = r#"{"version":1,"worktrees":[{"branch":"feature/cool"},{"branch":"fix-bug"}]}"#;
+        let wts = parse_worktrees(output).unwrap();
+        assert_eq!(wts.len(), 2);
+        assert_eq!(wts[0].branch, "feature/cool");
+        assert_eq!(wts[1].branch, "fix-bug");
+    }
+
+    #[test]
+    fn parse_worktrees_empty_output() {
+        let wts = parse_worktrees(r#"{"version":1,"worktrees":[]}"#).unwrap();
+        assert!(wts.is_empty());
+    }
+
+    #[test]
+    fn parse_worktrees_metadata_fields() {
+        let output = r#"{"version":1,"worktrees":[{"branch":"feat-a","path":"/wt/feat-a","head":"abc123",
+            "dirty":3,"ahead":2,"behind":5,"last_commit_time":1700000000,
+            "last_commit_subject":"Fix the \"thing\"\twith a tab","merged":true,"created_time":1690000000}]}"#;
+        let wts = parse_worktrees(output).unwrap();
+        assert_eq!(wts.len(), 1);
+        assert_eq!(
+            wts[0],
+            Worktree {
+                branch: "feat-a".into(),
+                path: "/wt/feat-a".into(),
+                head: "abc123".into(),
+                dirty: 3,
+                ahead: 2,
+                behind: 5,
+                last_commit_time: 1_700_000_000,
+                last_commit_subject: "Fix the \"thing\"\twith a tab".into(),
+                merged: true,
+                managed: true,
+                detached: false,
+                created_time: 1_690_000_000,
+            }
+        );
+    }
+
+    #[test]
+    fn parse_worktrees_odd_branch_names() {
+        let output = r#"{"version":1,"worktrees":[{"branch":"feat/a b\tc\\d\"e"}]}"#;
+        let wts = parse_worktrees(output).unwrap();
+        assert_eq!(wts[0].branch, "feat/a b\tc\\d\"e");
+    }
+
+    #[test]
+    fn parse_worktrees_ignores_unknown_fields() {
+        let output = r#"{"version":1,"extra":true,"worktrees":[{"branch":"feat-a","future_field":42}]}"#;
+        let wts = parse_worktrees(output).unwrap();
+        assert_eq!(wts[0].branch, "feat-a");
+    }
+
+    #[test]
+    fn parse_json_version_mismatch() {
+        let err = parse_worktrees(r#"{"version":2,"worktrees":[]}"#).unwrap_err();
+        assert!(err.contains("schema v2"));
+        assert!(err.contains("expects v1"));
+    }
+
+    #[test]
+    fn parse_json_invalid() {
+        let err = parse_worktrees("feat-a\nfeat-b\n").unwrap_err();
+        assert!(err.contains("Invalid JSON"));
+        let err = parse_worktrees(r#"{"worktrees":[]}"#).unwrap_err();
+        assert!(err.contains("Invalid JSON"));
+    }
+
+    #[test]
+    fn parse_branches_basic() {
+        let output = r#"{"version":1,"branches":[{"name":"main"},{"name":"feature/cool"},{"name":"fix-bug"}]}"#;
+        let branches = parse_branches(output).unwrap();
+        assert_eq!(branches, vec!["main".into(), "feature/cool".into(), "fix-bug".into()]);
+    }
+
+    #[test]
+    fn parse_branches_remote() {
+        let output = r#"{"version":1,"branches":[{"name":"main"},{"name":"feat/x","remote":"origin"}]}"#;
+        let branches = parse_branches(output).unwrap();
+        assert_eq!(branches[0].remote, None);
+        assert_eq!(branches[1], Branch { name: "feat/x".into(), remote: Some("origin".into()), ..Default::default() });
+    }
+
+    #[test]
+    fn parse_branches_commit_time_and_main_checkout() {
+        let output = r#"{"version":1,"branches":[{"name":"main","last_commit_time":1700000000,"main_checkout":true}]}"#;
+        let branches = parse_branches(output).unwrap();
+        assert_eq!(branches[0].last_commit_time, 1_700_000_000);
+        assert!(branches[0].main_checkout);
+    }
+
+    #[test]
+    fn select_branch_enter_spawns_remote_by_local_name() {
+        let mut s = state_with_worktrees();
+        s.branches = vec![Branch { name: "review-me".into(), remote: Some("origin".into()), ..Default::default() }];
+        s.handle_key_browse(&key(BareKey::Char('n')));
+        let action = s.handle_key_select_branch(&key(BareKey::Enter));
+        assert_eq!(action, Action::Spawn("review-me".into()));
+    }
+
+    #[test]
+    fn parse_branches_version_mismatch() {
+        assert!(parse_branches(r#"{"version":0,"branches":[]}"#).is_err());
+    }
+
+    // --- BrowseWorktrees key handler tests ---
+
+    #[test]
+    fn browse_j_moves_down() {
+        let mut s = state_with_worktrees();
+        s.handle_key_browse(&key(BareKey::Char('j')));
+        assert_eq!(s.selected_index, 1);
+        s.handle_key_browse(&key(BareKey::Down));
+        assert_eq!(s.selected_index, 2);
+    }
+
+    #[test]
+    fn browse_j_wraps_around() {
+        let mut s = state_with_worktrees();
+        s.selected_index = 2;
+        s.handle_key_browse(&key(BareKey::Char('j')));
+        assert_eq!(s.selected_index, 0);
+    }
+
+    #[test]
+    fn browse_k_moves_up() {
+        let mut s = state_with_worktrees();
+        s.selected_index = 2;
+        s.handle_key_browse(&key(BareKey::Char('k')));
+        assert_eq!(s.selected_index, 1);
+        s.handle_key_browse(&key(BareKey::Up));
+        assert_eq!(s.selected_index, 0);
+    }
+
+    #[test]
+    fn browse_k_wraps_around() {
+        let mut s = state_with_worktrees();
+        s.selected_index = 0;
+        s.handle_key_browse(&key(BareKey::Char('k')));
+        assert_eq!(s.selected_index, 2);
+    }
+
+    #[test]
+    fn browse_jk_noop_on_empty() {
+        let mut s = State { mode: Mode::BrowseWorktrees, ..Default::default() };
+        s.handle_key_browse(&key(BareKey::Char('j')));
+        assert_eq!(s.selected_index, 0);
+        s.handle_key_browse(&key(BareKey::Char('k')));
+        assert_eq!(s.selected_index, 0);
+    }
+
+    #[test]
+    fn browse_enter_spawns_selected() {
+        let mut s = state_with_worktrees();
+        s.selected_index = 1;
+        let action = s.handle_key_browse(&key(BareKey::Enter));
+        assert_eq!(action, Action::Spawn("feat-b".into()));
+        assert_eq!(s.status_message, "Spawning 'feat-b'...");
+    }
+
+    #[test]
+    fn browse_enter_focuses_existing_tab() {
+        let mut s = state_with_worktrees();
+        s.tabs = vec![make_tab("main", true), make_tab("feat-b", false)];
+        s.selected_index = 1;
+        let action = s.handle_key_browse(&key(BareKey::Enter));
+        assert_eq!(action, Action::Focus("feat-b".into()));
+    }
+
+    #[test]
+    fn browse_enter_spawns_when_other_tabs_open() {
+        let mut s = state_with_worktrees();
+        s.tabs = vec![make_tab("feat-a", false)];
+        s.selected_index = 1;
+        let action = s.handle_key_browse(&key(BareKey::Enter));
+        assert_eq!(action, Action::Spawn("feat-b".into()));
+    }
+
+    #[test]
+    fn browse_o_forces_new_tab() {
+        let mut s = state_with_worktrees();
+        s.tabs = vec![make_tab("feat-b", false)];
+        s.selected_index = 1;
+        let action = s.handle_key_browse(&key(BareKey::Char('o')));
+        assert_eq!(action, Action::Spawn("feat-b".into()));
+        assert_eq!(s.status_message, "Spawning new tab for 'feat-b'...");
+    }
+
+    #[test]
+    fn browse_o_noop_on_empty() {
+        let mut s = State { mode: Mode::BrowseWorktrees, ..Default::default() };
+        assert_eq!(s.handle_key_browse(&key(BareKey::Char('o'))), Action::None);
+    }
+
+    #[test]
+    fn browse_enter_noop_on_empty() {
+        let mut s = State { mode: Mode::BrowseWorktrees, ..Default::default() };
+        let action = s.handle_key_browse(&key(BareKey::Enter));
+        assert_eq!(action, Action::None);
+    }
+
+    #[test]
+    fn browse_n_switches_to_select_branch() {
+        let mut s = state_with_worktrees();
+        s.selected_index = 2;
+        s.handle_key_browse(&key(BareKey::Char('n')));
+        assert_eq!(s.mode, Mode::SelectBranch);
+        assert_eq!(s.selected_index, 0);
+        let names: Vec<&str> = s.filtered_branches.iter().map(|b| b.name.as_str()).collect();
+        assert_eq!(names, vec!["feat-a", "feat-b", "main", "dev"]);
+    }
+
+    #[test]
+    fn browse_i_switches_to_input_branch() {
+        let mut s = state_with_worktrees();
+        s.input_buffer = "leftover".into();
+        s.handle_key_browse(&key(BareKey::Char('i')));
+        assert_eq!(s.mode, Mode::InputBranch);
+        assert!(s.input_buffer.is_empty());
+    }
+
+    #[test]
+    fn browse_d_switches_to_confirming() {
+        let mut s = state_with_worktrees();
+        s.handle_key_browse(&key(BareKey::Char('d')));
+        assert_eq!(s.mode, Mode::Confirming);
+    }
+
+    #[test]
+    fn browse_d_noop_on_empty() {
+        let mut s = State { mode: Mode::BrowseWorktrees, ..Default::default() };
+        s.handle_key_browse(&key(BareKey::Char('d')));
+        assert_eq!(s.mode, Mode::BrowseWorktrees);
+    }
+
+    #[test]
+    fn browse_r_returns_refresh() {
+        let mut s = state_with_worktrees();
+        let action = s.handle_key_browse(&key(BareKey::Char('r')));
+        assert_eq!(action, Action::Refresh);
+        assert_eq!(s.status_message, "Refreshed");
+    }
+
+    #[test]
+    fn browse_q_returns_close() {
+        let mut s = state_with_worktrees();
+        assert_eq!(s.handle_key_browse(&key(BareKey::Char('q'))), Action::Close);
+    }
+
+    #[test]
+    fn browse_esc_returns_close() {
+        let mut s = state_with_worktrees();
+        assert_eq!(s.handle_key_browse(&key(BareKey::Esc)), Action::Close);
+    }
+
+    // --- SelectBranch key handler tests ---
+
+    fn key_ctrl(bare: BareKey) -> KeyWithModifier {
+        let mut mods = BTreeSet::new();
+        mods.insert(KeyModifier::Ctrl);
+        KeyWithModifier { bare_key: bare, key_modifiers: mods }
+    }
+
+    #[test]
+    fn select_branch_arrows_navigate() {
+        let mut s = state_with_worktrees();
+        s.mode = Mode::SelectBranch;
+        s.filtered_branches = s.branches.clone();
+        s.selected_index = 0;
+
+        s.handle_key_select_branch(&key(BareKey::Down));
+        assert_eq!(s.selected_index, 1);
+        s.handle_key_select_branch(&key(BareKey::Up));
+        assert_eq!(s.selected_index, 0);
+        s.handle_key_select_branch(&key_ctrl(BareKey::Char('n')));
+        assert_eq!(s.selected_index, 1);
+        s.handle_key_select_branch(&key_ctrl(BareKey::Char('p')));
+        assert_eq!(s.selected_index, 0);
+    }
+
+    #[test]
+    fn select_branch_wraps() {
+        let mut s = state_with_worktrees();
+        s.mode = Mode::SelectBranch;
+        s.filtered_branches = vec!["a".into(), "b".into()];
+        s.selected_index = 1;
+
+        s.handle_key_select_branch(&key(BareKey::Down));
+        assert_eq!(s.selected_index, 0);
+
+        s.handle_key_select_branch(&key(BareKey::Up));
+        assert_eq!(s.selected_index, 1);
+    }
+
+    #[test]
+    fn select_branch_typing_filters() {
+        let mut s = state_with_worktrees();
+        s.handle_key_browse(&key(BareKey::Char('n')));
+        s.handle_key_select_branch(&key(BareKey::Char('f')));
+        s.handle_key_select_branch(&key(BareKey::Char('b')));
+        assert_eq!(s.filter_query, "fb");
+        assert_eq!(s.filtered_branches, vec![Branch::from("feat-b")]);
+        assert_eq!(s.selected_index, 0);
+    }
+
+    #[test]
+    fn select_branch_jk_are_query_chars() {
+        let mut s = state_with_worktrees();
+        s.handle_key_browse(&key(BareKey::Char('n')));
+        s.handle_key_select_branch(&key(BareKey::Char('j')));
+        assert_eq!(s.filter_query, "j");
+        assert!(s.filtered_branches.is_empty());
+    }
+
+    #[test]
+    fn select_branch_ranks_matches() {
+        let mut s = state_with_worktrees();
+        s.branches = vec!["old-main-backup".into(), "dev".into(), "main".into()];
+        s.handle_key_browse(&key(BareKey::Char('n')));
+        for c in "main".chars() {
+            s.handle_key_select_branch(&key(BareKey::Char(c)));
+        }
+        assert_eq!(s.filtered_branches, vec![Branch::from("main"), Branch::from("old-main-backup")]);
+    }
+
+    #[test]
+    fn select_branch_backspace_widens_filter() {
+        let mut s = state_with_worktrees();
+        s.handle_key_browse(&key(BareKey::Char('n')));
+        s.handle_key_select_branch(&key(BareKey::Char('d')));
+        s.handle_key_select_branch(&key(BareKey::Char('e')));
+        assert_eq!(s.filtered_branches, vec![Branch::from("dev")]);
+        s.handle_key_select_branch(&key(BareKey::Backspace));
+        s.handle_key_select_branch(&key(BareKey::Backspace));
+        assert_eq!(s.filter_query, "");
+        assert_eq!(s.filtered_branches.len(), s.branches.len());
+    }
+
+    #[test]
+    fn select_branch_orders_worktrees_then_recent_commits() {
+        let mut s = state_with_worktrees();
+        s.branches = vec![
+            Branch { name: "old".into(), last_commit_time: 100, ..Default::default() },
+            Branch { name: "new".into(), last_commit_time: 300, ..Default::default() },
+            Branch { name: "feat-b".into(), last_commit_time: 50, ..Default::default() },
+            Branch { name: "feat-a".into(), remote: Some("origin".into()), last_commit_time: 400, ..Default::default() },
+        ];
+        s.handle_key_browse(&key(BareKey::Char('n')));
+        let order: Vec<String> = s.filtered_branches.iter().map(Branch::ref_name).collect();
+        assert_eq!(order, vec!["feat-b", "origin/feat-a", "new", "old"]);
+    }
+
+    #[test]
+    fn branch_states_mark_worktree_tab_and_base() {
+        let mut s = state_with_worktrees();
+        s.base_branch = "main".into();
+        s.tabs = vec![TabInfo { name: "feat-b".into(), ..Default::default() }];
+        s.branches.push(Branch { name: "feat-a".into(), remote: Some("origin".into()), ..Default::default() });
+        s.mode = Mode::SelectBranch;
+        s.filter_query = "a".into();
+        s.refilter_branches();
+        let states: BTreeMap<String, ui::BranchState> = s
+            .filtered_branches
+            .iter()
+            .map(Branch::ref_name)
+            .zip(s.branch_states())
+            .collect();
+        assert_eq!(states["feat-a"], ui::BranchState { worktree: true, tab: false, base: false });
+        assert_eq!(states["feat-b"], ui::BranchState { worktree: true, tab: true, base: false });
+        assert_eq!(states["main"], ui::BranchState { worktree: false, tab: false, base: true });
+        assert_eq!(states["origin/feat-a"], ui::BranchState::default());
+    }
+
+    #[test]
+    fn main_checkout_branch_is_refused() {
+        let mut s = state_with_worktrees();
+        s.branches = vec![Branch { name: "main".into(), main_checkout: true, ..Default::default() }];
+        s.handle_key_browse(&key(BareKey::Char('n')));
+        assert_eq!(s.handle_key_select_branch(&key(BareKey::Enter)), Action::None);
+        assert_eq!(s.mode, Mode::SelectBranch);
+        assert!(s.status_is_error);
+        assert!(s.status_message.contains("checked out in the main repo"));
+
+        s.mode = Mode::InputBranch;
+        s.input_buffer = "main".into();
+        s.status_message.clear();
+        assert_eq!(s.handle_key_input_branch(&key(BareKey::Enter)), Action::None);
+        assert_eq!(s.mode, Mode::InputBranch);
+        assert!(s.status_message.contains("checked out in the main repo"));
+    }
+
+    #[test]
+    fn select_branch_enter_spawns_filtered_selection() {
+        let mut s = state_with_worktrees();
+        s.handle_key_browse(&key(BareKey::Char('n')));
+        s.handle_key_select_branch(&key(BareKey::Char('d')));
+        let action = s.handle_key_select_branch(&key(BareKey::Enter));
+        assert_eq!(action, Action::Spawn("dev".into()));
+        assert!(s.filter_query.is_empty());
+    }
+
+    #[test]
+    fn select_branch_enter_spawns() {
+        let mut s = state_with_worktrees();
+        s.mode = Mode::SelectBranch;
+        s.filtered_branches = vec!["dev".into(), "main".into()];
+        s.selected_index = 0;
+
+        let action = s.handle_key_select_branch(&key(BareKey::Enter));
+        assert_eq!(action, Action::Spawn("dev".into()));
+        assert_eq!(s.mode, Mode::BrowseWorktrees);
+    }
+
+    #[test]
+    fn select_branch_enter_focuses_open_tab() {
+        let mut s = state_with_worktrees();
+        s.ask_prompt = true;
+        s.tabs = vec![make_tab("feat-b", false)];
+        s.mode = Mode::SelectBranch;
+        s.filtered_branches = vec!["feat-b".into(), "dev".into()];
+        s.selected_index = 0;
+        assert_eq!(s.handle_key_select_branch(&key(BareKey::Enter)), Action::Focus("feat-b".into()));
+        assert_eq!(s.mode, Mode::BrowseWorktrees);
+    }
+
+    #[test]
+    fn select_branch_esc_goes_back() {
+        let mut s = state_with_worktrees();
+        s.mode = Mode::SelectBranch;
+        s.selected_index = 2;
+        s.filter_query = "fe".into();
+        s.handle_key_select_branch(&key(BareKey::Esc));
+        assert_eq!(s.mode, Mode::BrowseWorktrees);
+        assert_eq!(s.selected_index, 0);
+        assert!(s.filter_query.is_empty());
+    }
+
+    // --- InputBranch key handler tests ---
+
+    #[test]
+    fn input_branch_typing() {
+        let mut s = State { mode: Mode::InputBranch, ..Default::default() };
+        s.handle_key_input_branch(&key(BareKey::Char('f')));
+        s.handle_key_input_branch(&key(BareKey::Char('o')));
+        s.handle_key_input_branch(&key(BareKey::Char('o')));
+        assert_eq!(s.input_buffer.text(), "foo");
+    }
+
+    #[test]
+    fn input_branch_shift_chars() {
+        let mut s = State { mode: Mode::InputBranch, ..Default::default() };
+        s.handle_key_input_branch(&key_shift(BareKey::Char('F')));
+        assert_eq!(s.input_buffer.text(), "F");
+    }
+
+    #[test]
+    fn input_branch_backspace() {
+        let mut s = State { mode: Mode::InputBranch, input_buffer: "ab".into(), ..Default::default() };
+        s.handle_key_input_branch(&key(BareKey::Backspace));
+        assert_eq!(s.input_buffer.text(), "a");
+    }
+
+    #[test]
+    fn input_branch_enter_asks_for_base() {
+        let mut s = state_with_worktrees();
+        s.mode = Mode::InputBranch;
+        s.input_buffer = "feat/new".into();
+        let action = s.handle_key_input_branch(&key(BareKey::Enter));
+        assert_eq!(action, Action::None);
+        assert_eq!(s.mode, Mode::SelectBase);
+        assert_eq!(s.pending_branch, "feat/new");
+        assert_eq!(s.filtered_branches.len(), s.branches.len());
+    }
+
+    #[test]
+    fn input_branch_enter_existing_branch_spawns_directly() {
+        let mut s = state_with_worktrees();
+        s.mode = Mode::InputBranch;
+        s.input_buffer = "dev".into();
+        let action = s.handle_key_input_branch(&key(BareKey::Enter));
+        assert_eq!(action, Action::Spawn("dev".into()));
+        assert_eq!(s.mode, Mode::BrowseWorktrees);
+    }
+
+    #[test]
+    fn input_branch_enter_existing_worktree_opens_it() {
+        let mut s = state_with_worktrees();
+        s.ask_prompt = true;
+        s.tabs = vec![make_tab("feat-a", false)];
+        s.mode = Mode::InputBranch;
+        s.input_buffer = "feat-a".into();
+        assert_eq!(s.handle_key_input_branch(&key(BareKey::Enter)), Action::Focus("feat-a".into()));
+        assert_eq!(s.mode, Mode::BrowseWorktrees);
+
+        // Without a tab it spawns right away: no prompt for a new task.
+        s.mode = Mode::InputBranch;
+        s.input_buffer = "feat-b".into();
+        assert_eq!(s.handle_key_input_branch(&key(BareKey::Enter)), Action::Spawn("feat-b".into()));
+        assert_eq!(s.mode, Mode::BrowseWorktrees);
+        assert_eq!(s.status_message, "Spawning 'feat-b'...");
+    }
+
+    // --- SelectBase key handler tests ---
+
+    fn state_selecting_base() -> State {
+        let mut s = state_with_worktrees();
+        s.base_branch = "main".into();
+        s.branches = vec![
+            "feat-a".into(),
+            "main".into(),
+            Branch { name: "release-2".into(), remote: Some("origin".into()), ..Default::default() },
+        ];
+        s.mode = Mode::InputBranch;
+        s.input_buffer = "feat/new".into();
+        s.handle_key_input_branch(&key(BareKey::Enter));
+        s
+    }
+
+    #[test]
+    fn select_base_lists_default_base_first() {
+        let s = state_selecting_base();
+        assert_eq!(s.filtered_branches[0].name, "main");
+        assert_eq!(s.selected_index, 0);
+    }
+
+    #[test]
+    fn select_base_enter_spawns_with_default_base() {
+        let mut s = state_selecting_base();
+        let action = s.handle_key_select_base(&key(BareKey::Enter));
+        assert_eq!(
+            action,
+            Action::Spawn(SpawnRequest { branch: "feat/new".into(), base: Some("main".into()), agent: None, path: None, prompt: None })
+        );
+        assert_eq!(s.mode, Mode::BrowseWorktrees);
+        assert!(s.pending_branch.is_empty());
+        assert!(s.input_buffer.is_empty());
+    }
+
+    #[test]
+    fn select_base_filtered_remote_uses_remote_ref() {
+        let mut s = state_selecting_base();
+        for c in "rel".chars() {
+            s.handle_key_select_base(&key(BareKey::Char(c)));
+        }
+        let action = s.handle_key_select_base(&key(BareKey::Enter));
+        assert_eq!(
+            action,
+            Action::Spawn(SpawnRequest {
+                branch: "feat/new".into(),
+                base: Some("origin/release-2".into()),
+                agent: None,
+                path: None,
+                prompt: None,
+            })
+        );
+    }
+
+    #[test]
+    fn select_base_unmatched_query_is_literal_ref() {
+        let mut s = state_selecting_base();
+        for c in "v1.2.0".chars() {
+            s.handle_key_select_base(&key(BareKey::Char(c)));
+        }
+        assert!(s.filtered_branches.is_empty());
+        let action = s.handle_key_select_base(&key(BareKey::Enter));
+        assert_eq!(
+            action,
+            Action::Spawn(SpawnRequest { branch: "feat/new".into(), base: Some("v1.2.0".into()), agent: None, path: None, prompt: None })
+        );
+        assert_eq!(s.status_message, "Spawning 'feat/new' from 'v1.2.0'...");
+    }
+
+    #[test]
+    fn select_base_no_branches_uses_default() {
+        let mut s = State { mode: Mode::InputBranch, input_buffer: "feat/new".into(), ..Default::default() };
+        s.handle_key_input_branch(&key(BareKey::Enter));
+        assert_eq!(s.mode, Mode::SelectBase);
+        let action = s.handle_key_select_base(&key(BareKey::Enter));
+        assert_eq!(action, Action::Spawn("feat/new".into()));
+    }
+
+    #[test]
+    fn select_base_esc_returns_to_input() {
+        let mut s = state_selecting_base();
+        s.handle_key_select_base(&key(BareKey::Char('m')));
+        s.handle_key_select_base(&key(BareKey::Esc));
+        assert_eq!(s.mode, Mode::InputBranch);
+        assert_eq!(s.input_buffer.text(), "feat/new");
+        assert!(s.filter_query.is_empty());
+    }
+
+    #[test]
+    fn input_branch_enter_noop_on_empty() {
+        let mut s = State { mode: Mode::InputBranch, input_buffer: "  ".into(), ..Default::default() };
+        let action = s.handle_key_input_branch(&key(BareKey::Enter));
+        assert_eq!(action, Action::None);
+        assert_eq!(s.mode, Mode::InputBranch);
+    }
+
+    #[test]
+    fn input_branch_edits_at_cursor() {
+        let mut s = State { mode: Mode::InputBranch, input_buffer: "feat/login".into(), ..Default::default() };
+        s.handle_key_input_branch(&key(BareKey::Home));
+        for c in "my-".chars() {
+            s.handle_key_input_branch(&key(BareKey::Char(c)));
+        }
+        s.handle_key_input_branch(&key(BareKey::Enter));
+        assert_eq!(s.pending_branch, "my-feat/login");
+    }
+
+    fn state_completing(text: &str) -> State {
+        State {
+            mode: Mode::InputBranch,
+            input_buffer: text.into(),
+            branches: vec![
+                "feature/login".into(),
+                "feature/logout".into(),
+                "feature/lo".into(),
+                Branch { name: "feature/login".into(), remote: Some("origin".into()), ..Default::default() },
+                "main".into(),
+            ],
+            worktrees: vec![wt("feature/login")],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn branch_completions_shortest_first() {
+        let s = state_completing("");
+        assert_eq!(s.branch_completions("feature/lo"), vec!["feature/login", "feature/logout"]);
+        assert_eq!(s.branch_completions("feature/login"), Vec::<&str>::new());
+    }
+
+    #[test]
+    fn completion_hint_is_ghost_text() {
+        let mut s = state_completing("feature/l");
+        assert_eq!(s.completion_hint(), Some("o"));
+        s.input_buffer.set("ma");
+        assert_eq!(s.completion_hint(), Some("in"));
+        s.handle_key_input_branch(&key(BareKey::Left));
+        assert_eq!(s.completion_hint(), None);
+        s.input_buffer.set("x");
+        assert_eq!(s.completion_hint(), None);
+    }
+
+    #[test]
+    fn tab_completes_and_cycles() {
+        let mut s = state_completing("feature/log");
+        s.handle_key_input_branch(&key(BareKey::Tab));
+        assert_eq!(s.input_buffer.text(), "feature/login");
+        assert_eq!(s.completion_hint(), None);
+        s.handle_key_input_branch(&key(BareKey::Tab));
+        assert_eq!(s.input_buffer.text(), "feature/logout");
+        s.handle_key_input_branch(&key(BareKey::Tab));
+        assert_eq!(s.input_buffer.text(), "feature/login");
+        // Typing ends the cycle; the next Tab completes the new text.
+        s.handle_key_input_branch(&key(BareKey::Backspace));
+        assert_eq!(s.completion, None);
+        s.handle_key_input_branch(&key(BareKey::Tab));
+        assert_eq!(s.input_buffer.text(), "feature/login");
+    }
+
+    #[test]
+    fn tab_without_completions_keeps_text() {
+        let mut s = state_completing("zzz");
+        assert_eq!(s.handle_key_input_branch(&key(BareKey::Tab)), Action::None);
+        assert_eq!(s.input_buffer.text(), "zzz");
+        assert_eq!(s.mode, Mode::InputBranch);
+    }
+
+    #[test]
+    fn input_warns_about_existing_worktree() {
+        let mut s = state_completing("feature/login");
+        assert!(s.input_has_worktree());
+        s.input_buffer.set("feature/logout");
+        assert!(!s.input_has_worktree());
+    }
+
+    #[test]
+    fn input_branch_invalid_name_becomes_slug() {
+        let mut s = State { mode: Mode::InputBranch, input_buffer: "Fix login timeout bug".into(), ..Default::default() };
+        assert_eq!(s.handle_key_input_branch(&key(BareKey::Enter)), Action::None);
+        assert_eq!(s.mode, Mode::InputBranch);
+        assert_eq!(s.input_buffer.text(), "fix-login-timeout-bug");
+        s.handle_key_input_branch(&key(BareKey::Enter));
+        assert_eq!(s.mode, Mode::SelectBase);
+        assert_eq!(s.pending_branch, "fix-login-timeout-bug");
+    }
+
+    #[test]
+    fn input_branch_slug_uses_prefix() {
+        let mut s = State {
+            mode: Mode::InputBranch,
+            input_buffer: "Retry uploads?".into(),
+            branch_prefix: "agent/".into(),
+            ..Default::default()
+        };
+        s.handle_key_input_branch(&key(BareKey::Enter));
+        assert_eq!(s.input_buffer.text(), "agent/retry-uploads");
+    }
+
+    #[test]
+    fn input_branch_without_slug_stays() {
+        let mut s = State { mode: Mode::InputBranch, input_buffer: "..".into(), ..Default::default() };
+        assert_eq!(s.handle_key_input_branch(&key(BareKey::Enter)), Action::None);
+        assert_eq!(s.input_buffer.text(), "..");
+        assert_eq!(s.mode, Mode::InputBranch);
+    }
+
+    #[test]
+    fn input_branch_esc_goes_back() {
+        let mut s = State { mode: Mode::InputBranch, input_buffer: "wip".into(), ..Default::default() };
+        s.handle_key_input_branch(&key(BareKey::Esc));
+        assert_eq!(s.mode, Mode::BrowseWorktrees);
+    }
+
+    // --- SelectAgent key handler tests ---
+
+    fn state_with_agents() -> State {
+        let mut s = state_with_worktrees();
+        s.agents = vec![
+            AgentProfile::new("aider", "aider"),
+            AgentProfile::new("claude", "claude"),
+            AgentProfile::new("shell", "bash"),
+        ];
+        s
+    }
+
+    fn with_mods(bare: BareKey, mods: &[KeyModifier]) -> KeyWithModifier {
+        KeyWithModifier { bare_key: bare, key_modifiers: mods.iter().copied().collect() }
+    }
+
+    #[test]
+    fn new_branch_asks_for_prompt() {
+        let mut s = State { ask_prompt: true, ..state_selecting_base() };
+        assert_eq!(s.handle_key_select_base(&key(BareKey::Enter)), Action::None);
+        assert_eq!(s.mode, Mode::InputPrompt);
+        for c in "Fix it".chars() {
+            s.handle_key_input_prompt(&key(BareKey::Char(c)));
+        }
+        s.handle_key_input_prompt(&with_mods(BareKey::Enter, &[KeyModifier::Alt]));
+        s.handle_key_input_prompt(&key(BareKey::Char('x')));
+        s.handle_key_input_prompt(&with_mods(BareKey::Char('j'), &[KeyModifier::Ctrl]));
+        s.handle_key_input_prompt(&key(BareKey::Char('y')));
+        s.handle_key_input_prompt(&key(BareKey::Backspace));
+        assert_eq!(s.prompt_buffer.text(), "Fix it\nx\n");
+        let action = s.handle_key_input_prompt(&key(BareKey::Enter));
+        assert_eq!(
+            action,
+            Action::Spawn(SpawnRequest {
+                branch: "feat/new".into(),
+                base: Some("main".into()),
+                prompt: Some("Fix it\nx".into()),
+                ..Default::default()
+            })
+        );
+        assert_eq!(s.status_message, "Spawning 'feat/new' from 'main'...");
+        assert_eq!(s.mode, Mode::BrowseWorktrees);
+    }
+
+    #[test]
+    fn empty_prompt_spawns_without_one() {
+        let mut s = State { ask_prompt: true, ..state_with_worktrees() };
+        s.mode = Mode::SelectBranch;
+        s.filter_query = "main".into();
+        s.refilter_branches();
+        s.handle_key_select_branch(&key(BareKey::Enter));
+        assert_eq!(s.mode, Mode::InputPrompt);
+        s.handle_key_input_prompt(&key(BareKey::Char(' ')));
+        assert_eq!(s.handle_key_input_prompt(&key(BareKey::Enter)), Action::Spawn("main".into()));
+    }
+
+    #[test]
+    fn prompt_then_agent_choice() {
+        let mut s = State { ask_prompt: true, mode: Mode::InputBranch, input_buffer: "feat-b".into(), ..state_with_agents() };
+        s.handle_key_input_branch(&key(BareKey::Enter));
+        s.handle_key_input_prompt(&key(BareKey::Char('t')));
+        assert_eq!(s.handle_key_input_prompt(&key(BareKey::Enter)), Action::None);
+        assert_eq!(s.mode, Mode::SelectAgent);
+        assert_eq!(s.pending_spawn.as_ref().and_then(|r| r.prompt.as_deref()), Some("t"));
+    }
+
+    #[test]
+    fn prompt_esc_cancels_spawn() {
+        let mut s = State { ask_prompt: true, ..state_selecting_base() };
+        s.handle_key_select_base(&key(BareKey::Enter));
+        s.handle_key_input_prompt(&key(BareKey::Char('t')));
+        assert_eq!(s.handle_key_input_prompt(&key(BareKey::Esc)), Action::None);
+        assert_eq!(s.mode, Mode::BrowseWorktrees);
+        assert_eq!(s.pending_spawn, None);
+        assert!(s.prompt_buffer.is_empty());
+    }
+
+    #[test]
+    fn open_existing_worktree_skips_prompt() {
+        let mut s = State { ask_prompt: true, ..state_with_worktrees() };
+        assert_eq!(s.handle_key_browse(&key(BareKey::Enter)), Action::Spawn("feat-a".into()));
+    }
+
+    #[test]
+    fn spawn_with_single_agent_skips_selection() {
+        let mut s = state_with_worktrees();
+        s.agents = vec![AgentProfile::new("default", "claude")];
+        let action = s.handle_key_browse(&key(BareKey::Enter));
+        assert_eq!(action, Action::Spawn("feat-a".into()));
+        assert_eq!(s.mode, Mode::BrowseWorktrees);
+    }
+
+    #[test]
+    fn spawn_with_multiple_agents_asks() {
+        let mut s = state_with_agents();
+        s.selected_index = 1;
+        let action = s.handle_key_browse(&key(BareKey::Enter));
+        assert_eq!(action, Action::None);
+        assert_eq!(s.mode, Mode::SelectAgent);
+        assert_eq!(s.pending_spawn, Some("feat-b".into()));
+        // Browse selection is untouched by the agent picker
+        assert_eq!(s.selected_index, 1);
+    }
+
+    #[test]
+    fn select_agent_enter_launches_highlighted() {
+        let mut s = state_with_agents();
+        s.handle_key_browse(&key(BareKey::Enter));
+        assert_eq!(s.agent_index, 0);
+        s.handle_key_select_agent(&key(BareKey::Char('j')));
+        let action = s.handle_key_select_agent(&key(BareKey::Enter));
+        assert_eq!(
+            action,
+            Action::Spawn(SpawnRequest { branch: "feat-a".into(), base: None, agent: Some("claude".into()), path: None, prompt: None })
+        );
+        assert_eq!(s.mode, Mode::BrowseWorktrees);
+        assert_eq!(s.status_message, "Spawning 'feat-a' with claude...");
+        assert_eq!(s.pending_spawn, None);
+    }
+
+    #[test]
+    fn select_agent_digit_launches_directly() {
+        let mut s = state_with_agents();
+        s.handle_key_browse(&key(BareKey::Enter));
+        let action = s.handle_key_select_agent(&key(BareKey::Char('3')));
+        assert_eq!(
+            action,
+            Action::Spawn(SpawnRequest { branch: "feat-a".into(), base: None, agent: Some("shell".into()), path: None, prompt: None })
+        );
+    }
+
+    #[test]
+    fn select_agent_digit_out_of_range_ignored() {
+        let mut s = state_with_agents();
+        s.handle_key_browse(&key(BareKey::Enter));
+        assert_eq!(s.handle_key_select_agent(&key(BareKey::Char('9'))), Action::None);
+        assert_eq!(s.mode, Mode::SelectAgent);
+        assert!(s.pending_spawn.is_some());
+    }
+
+    #[test]
+    fn select_agent_esc_cancels() {
+        let mut s = state_with_agents();
+        s.handle_key_browse(&key(BareKey::Enter));
+        s.handle_key_select_agent(&key(BareKey::Esc));
+        assert_eq!(s.mode, Mode::BrowseWorktrees);
+        assert_eq!(s.pending_spawn, None);
+    }
+
+    #[test]
+    fn select_agent_keeps_base_from_input_flow() {
+        let mut s = state_with_agents();
+        s.mode = Mode::InputBranch;
+        s.input_buffer = "feat/new".into();
+        s.handle_key_input_branch(&key(BareKey::Enter));
+        s.handle_key_select_base(&key(BareKey::Enter));
+        assert_eq!(s.mode, Mode::SelectAgent);
+        let action = s.handle_key_select_agent(&key(BareKey::Char('1')));
+        assert_eq!(
+            action,
+            Action::Spawn(SpawnRequest {
+                branch: "feat/new".into(),
+                base: Some("main".into()),
+                agent: Some("aider".into()),
+                path: None,
+                prompt: None,
+            })
+        );
+    }
+
+    #[test]
+    fn default_agent_index_precedence() {
+        let mut s = state_with_agents();
+        assert_eq!(s.default_agent_index(), 0);
+        s.default_agent = "shell".into();
+        assert_eq!(s.default_agent_index(), 2);
+        s.repo_default_agent = "claude".into();
+        assert_eq!(s.default_agent_index(), 1);
+        // Unknown repo default falls back to the plugin default
+        s.repo_default_agent = "nope".into();
+        assert_eq!(s.default_agent_index(), 2);
+    }
+
+    #[test]
+    fn spawn_preselects_repo_default_agent() {
+        let mut s = state_with_agents();
+        s.repo_default_agent = "claude".into();
+        s.handle_key_browse(&key(BareKey::Enter));
+        assert_eq!(s.agent_index, 1);
+        let action = s.handle_key_select_agent(&key(BareKey::Enter));
+        assert!(matches!(action, Action::Spawn(SpawnRequest { agent: Some(ref a), .. }) if a == "claude"));
+    }
+
+    // --- Confirming key handler tests ---
+
+    #[test]
+    fn confirm_y_removes() {
+        let mut s = state_with_worktrees();
+        s.mode = Mode::Confirming;
+        s.selected_index = 1;
+        let action = s.handle_key_confirming(&key(BareKey::Char('y')));
+        assert_eq!(action, Action::Remove("feat-b".into()));
+        assert_eq!(s.status_message, "Removing 'feat-b'...");
+    }
+
+    #[test]
+    fn confirm_b_and_f_delete_branch() {
+        let mut s = state_with_worktrees();
+        s.mode = Mode::Confirming;
+        let action = s.handle_key_confirming(&key(BareKey::Char('b')));
+        assert_eq!(
+            action,
+            Action::Remove(RemoveRequest { branch: "feat-a".into(), branch_cleanup: BranchCleanup::DeleteMerged, force: false })
+        );
+        assert_eq!(s.status_message, "Removing 'feat-a' and its branch...");
+        let action = s.handle_key_confirming(&key(BareKey::Char('f')));
+        assert_eq!(
+            action,
+            Action::Remove(RemoveRequest { branch: "feat-a".into(), branch_cleanup: BranchCleanup::ForceDelete, force: false })
+        );
+    }
+
+    #[test]
+    fn confirm_n_cancels() {
+        let mut s = state_with_worktrees();
+        s.mode = Mode::Confirming;
+        s.handle_key_confirming(&key(BareKey::Char('n')));
+        assert_eq!(s.mode, Mode::BrowseWorktrees);
+    }
+
+    #[test]
+    fn confirm_esc_cancels() {
+        let mut s = state_with_worktrees();
+        s.mode = Mode::Confirming;
+        s.handle_key_confirming(&key(BareKey::Esc));
+        assert_eq!(s.mode, Mode::BrowseWorktrees);
+    }
+
+    // --- Command result handler tests ---
+
+    #[test]
+    fn git_toplevel_sets_repo() {
+        let mut s = State::default();
+        let action = s.handle_git_toplevel(
+            Some(0),
+            br#"{"version":1,"repo_root":"/home/user/myrepo","repo_name":"myrepo","base_branch":"trunk","default_agent":"aider"}"#,
+            b"",
+        );
+        assert_eq!(s.repo_root, "/home/user/myrepo");
+        assert_eq!(s.repo_name, "myrepo");
+        assert_eq!(s.base_branch, "trunk");
+        assert_eq!(s.repo_default_agent, "aider");
+        assert_eq!(s.mode, Mode::BrowseWorktrees);
+        assert_eq!(action, Action::FetchWorktreesAndBranches);
+    }
+
+    #[test]
+    fn git_toplevel_parses_by_key() {
+        let mut s = State::default();
+        // Order should not matter
+        let action = s.handle_git_toplevel(Some(0), br#"{"repo_name":"myrepo","version":1,"repo_root":"/home/user/myrepo"}"#, b"");
+        assert_eq!(s.repo_root, "/home/user/myrepo");
+        assert_eq!(s.repo_name, "myrepo");
+        assert_eq!(action, Action::FetchWorktreesAndBranches);
+    }
+
+    #[test]
+    fn git_toplevel_error() {
+        let mut s = State::default();
+        let action = s.handle_git_toplevel(Some(128), b"", b"not a git repo");
+        assert!(s.status_is_error);
+        assert!(s.status_message.contains("not a git repo"));
+        assert_eq!(s.mode, Mode::Loading);
+        assert_eq!(action, Action::None);
+    }
+
+    #[test]
+    fn git_toplevel_missing_fields() {
+        let mut s = State::default();
+        let action = s.handle_git_toplevel(Some(0), br#"{"version":1,"repo_root":"/foo"}"#, b"");
+        assert!(s.status_is_error);
+        assert!(s.status_message.contains("Invalid JSON"));
+        assert_eq!(action, Action::None);
+
+        let action = s.handle_git_toplevel(Some(0), br#"{"version":1,"repo_root":"/foo","repo_name":""}"#, b"");
+        assert!(s.status_message.contains("Failed to parse repo info"));
+        assert_eq!(action, Action::None);
+    }
+
+    #[test]
+    fn git_toplevel_schema_mismatch_stays_loading() {
+        let mut s = State::default();
+        let action = s.handle_git_toplevel(Some(0), br#"{"version":99,"repo_root":"/r","repo_name":"r"}"#, b"");
+        assert!(s.status_is_error);
+        assert!(s.status_message.contains("schema v99"));
+        assert_eq!(s.mode, Mode::Loading);
+        assert_eq!(action, Action::None);
+    }
+
+    const PREPARED: &[u8] = br#"{"version":1,"branch":"feat/a","worktree_path":"/wt/feat/a","repo_root":"/r","new_worktree":true,"setup_script":"","layout_template":""}"#;
+
+    fn spawn_ctx(branch: &str, agent_cmd: &str) -> BTreeMap<String, String> {
+        let mut ctx = State::ctx(CMD_SPAWN);
+        ctx.insert("branch".into(), branch.into());
+        ctx.insert("agent_cmd".into(), agent_cmd.into());
+        ctx
+    }
+
+    #[test]
+    fn spawn_result_success() {
+        let mut s = state_with_worktrees();
+        let action = s.handle_spawn_result(Some(0), PREPARED, b"", &spawn_ctx("feat/a", "claude"));
+        assert_eq!(s.status_message, "Spawned 'feat/a'");
+        assert!(!s.status_is_error);
+        let Action::OpenTab(layout) = action else {
+            panic!("expected OpenTab, got {action:?}");
+        };
+        assert!(layout.contains(r#"tab name="feat/a""#));
+        assert!(layout.contains(r#"cwd="/wt/feat/a""#));
+        assert!(layout.contains(r#""exec claude""#));
+    }
+
+    #[test]
+    fn spawn_result_passes_prompt_file() {
+        let mut s = state_with_worktrees();
+        let prepared = br#"{"version":1,"branch":"feat/a","worktree_path":"/wt/feat/a","repo_root":"/r","prompt_file":"/r/.git/worktrees/a/zelligent-prompt.md"}"#;
+        let action = s.handle_spawn_result(Some(0), prepared, b"", &spawn_ctx("feat/a", "claude"));
+        let Action::OpenTab(layout) = action else {
+            panic!("expected OpenTab, got {action:?}");
+        };
+        assert!(layout.contains(r#""exec claude \"$(cat '/r/.git/worktrees/a/zelligent-prompt.md')\"""#));
+    }
+
+    #[test]
+    fn spawn_result_uses_shell_without_agent() {
+        let mut s = state_with_worktrees();
+        let action = s.handle_spawn_result(Some(0), PREPARED, b"", &spawn_ctx("feat/a", ""));
+        assert!(matches!(action, Action::OpenTab(ref l) if l.contains(r#""exec $SHELL""#)));
+    }
+
+    #[test]
+    fn spawn_result_shell_fallback_skips_prompt() {
+        let mut s = state_with_worktrees();
+        let prepared = br#"{"version":1,"branch":"feat/a","worktree_path":"/wt/feat/a","repo_root":"/r","prompt_file":"/r/.git/worktrees/a/zelligent-prompt.md"}"#;
+        let action = s.handle_spawn_result(Some(0), prepared, b"", &spawn_ctx("feat/a", ""));
+        assert!(matches!(action, Action::OpenTab(ref l) if l.contains(r#""exec $SHELL""#)));
+        assert_eq!(s.status_message, "Spawned 'feat/a' (no agent command; prompt not passed)");
+    }
+
+    #[test]
+    fn spawn_result_runs_setup_preamble() {
+        let mut s = state_with_worktrees();
+        let stdout = br#"{"version":1,"branch":"x","worktree_path":"/wt/x","repo_root":"/r","setup_script":"/r/.zelligent/setup.sh","setup_preamble":"bash '/r/.zelligent/setup.sh' '/r' '/wt/x' || exit 1; "}"#;
+        let action = s.handle_spawn_result(Some(0), stdout, b"", &spawn_ctx("x", "claude"));
+        assert!(matches!(action, Action::OpenTab(ref l) if l.contains("bash '/r/.zelligent/setup.sh' '/r' '/wt/x' ||")));
+    }
+
+    #[test]
+    fn spawn_result_custom_layout_without_preamble_warns() {
+        let mut s = state_with_worktrees();
+        let stdout = br#"{"version":1,"branch":"x","worktree_path":"/wt/x","repo_root":"/r","setup_script":"/r/s.sh","setup_preamble":"bash '/r/s.sh' || exit 1; ","layout_template":"layout {\n  pane command=\"{{agent_cmd}}\" cwd=\"{{cwd}}\"\n}"}"#;
+        let action = s.handle_spawn_result(Some(0), stdout, b"", &spawn_ctx("x", "claude"));
+        assert!(matches!(action, Action::OpenTab(ref l) if l.contains(r#"command="claude""#)));
+        assert_eq!(s.status_message, "Spawned 'x' (layout.kdl has no {{setup_preamble}}; setup.sh skipped)");
+    }
+
+    #[test]
+    fn spawn_result_bad_layout_is_error() {
+        let mut s = state_with_worktrees();
+        let stdout = br#"{"version":1,"branch":"x","worktree_path":"/wt/x","repo_root":"/r","layout_template":"layout { pane cwd=\"{{nope}}\" }"}"#;
+        let action = s.handle_spawn_result(Some(0), stdout, b"", &spawn_ctx("x", "claude"));
+        assert_eq!(action, Action::Refresh);
+        assert!(s.status_is_error);
+        assert_eq!(s.status_message, "Error: unknown placeholder {{nope}} in layout");
+        let mut ctx = spawn_ctx("x", "claude");
+        ctx.insert("pipe_id".into(), "p".into());
+        assert_eq!(
+            s.pipe_reply(Some(0), stdout, b"", &ctx),
+            Some(("p".into(), "error: Error: unknown placeholder {{nope}} in layout".into()))
+        );
+    }
+
+    #[test]
+    fn spawn_result_error() {
+        let mut s = state_with_worktrees();
+        let mut ctx = BTreeMap::new();
+        ctx.insert("branch".into(), "bad".into());
+        let action = s.handle_spawn_result(Some(1), b"", b"something broke", &ctx);
+        assert!(s.status_is_error);
+        assert!(s.status_message.contains("something broke"));
+        assert_eq!(action, Action::Refresh);
+    }
+
+    #[test]
+    fn remove_result_success() {
+        let mut s = state_with_worktrees();
+        s.mode = Mode::Confirming;
+        let mut ctx = BTreeMap::new();
+        ctx.insert("branch".into(), "feat-a".into());
+        let action = s.handle_remove_result(Some(0), b"", &ctx);
+        assert_eq!(s.status_message, "Removed 'feat-a'");
+        assert_eq!(s.mode, Mode::BrowseWorktrees);
+        assert_eq!(action, Action::Refresh);
+
+        ctx.insert("branch_cleanup".into(), "--delete-branch".into());
+        s.handle_remove_result(Some(0), b"", &ctx);
+        assert_eq!(s.status_message, "Removed 'feat-a' and deleted its branch");
+    }
+
+    #[test]
+    fn remove_dirty_offers_force() {
+        let mut s = state_with_worktrees();
+        s.mode = Mode::Confirming;
+        let mut ctx = BTreeMap::new();
+        ctx.insert("branch".into(), "feat-a".into());
+        ctx.insert("branch_cleanup".into(), "--force-delete-branch".into());
+        let action = s.handle_remove_result(Some(2), b"Error: worktree 'feat-a' has uncommitted changes.", &ctx);
+        assert_eq!(action, Action::None);
+        assert_eq!(s.mode, Mode::ConfirmForceRemove);
+        assert!(s.status_message.contains("zelligent/backup/feat-a"));
+
+        let action = s.handle_key_confirm_force_remove(&key(BareKey::Char('y')));
+        assert_eq!(
+            action,
+            Action::Remove(RemoveRequest { branch: "feat-a".into(), branch_cleanup: BranchCleanup::ForceDelete, force: true })
+        );
+        assert_eq!(s.mode, Mode::BrowseWorktrees);
+        assert_eq!(s.pending_remove, None);
+
+        ctx.insert("force".into(), "true".into());
+        s.handle_remove_result(Some(0), b"", &ctx);
+        assert_eq!(
+            s.status_message,
+            "Removed 'feat-a' and deleted its branch; changes saved to 'zelligent/backup/feat-a'"
+        );
     }
 
-    fn update(&mut self, event: Event) -> bool {
-        let action = match event {
-            Event::PermissionRequestResult(PermissionStatus::Granted) => {
-                Action::FetchToplevel
-            }
-            Event::PermissionRequestResult(PermissionStatus::Denied) => {
-                self.status_message = "Permissions denied. Plugin cannot run commands.".to_string();
-                self.status_is_error = true;
-                Action::None
-            }
-            Event::RunCommandResult(exit_code, stdout, stderr, context) => {
-                match context.get("cmd_type").map(|s| s.as_str()) {
-                    Some(CMD_GIT_TOPLEVEL) => self.handle_git_toplevel(exit_code, &stdout, &stderr),
-                    Some(CMD_LIST_WORKTREES) => {
-                        self.handle_list_worktrees(exit_code, &stdout, &stderr);
-                        Action::None
-                    }
-                    Some(CMD_GIT_BRANCHES) => {
-                        self.handle_git_branches(exit_code, &stdout, &stderr);
-                        Action::None
-                    }
-                    Some(CMD_SPAWN) => self.handle_spawn_result(exit_code, &stderr, &context),
-                    Some(CMD_REMOVE) => self.handle_remove_result(exit_code, &stderr, &context),
-                    _ => Action::None,
-                }
-            }
-            Event::TabUpdate(tab_info) => {
-                self.tabs = tab_info;
-                Action::None
-            }
-            Event::Key(key) => {
-                match self.mode {
-                    Mode::Loading => Action::None,
-                    Mode::BrowseWorktrees => self.handle_key_browse(&key),
-                    Mode::SelectBranch => self.handle_key_select_branch(&key),
-                    Mode::InputBranch => self.handle_key_input_branch(&key),
-                    Mode::Confirming => self.handle_key_confirming(&key),
-                }
-            }
-            _ => return false,
-        };
-        self.execute(&action);
-        true
+    #[test]
+    fn remove_dirty_force_declined() {
+        let mut s = state_with_worktrees();
+        let mut ctx = BTreeMap::new();
+        ctx.insert("branch".into(), "feat-a".into());
+        s.handle_remove_result(Some(2), b"", &ctx);
+        assert_eq!(s.handle_key_confirm_force_remove(&key(BareKey::Char('n'))), Action::None);
+        assert_eq!(s.mode, Mode::BrowseWorktrees);
+        assert_eq!(s.pending_remove, None);
+        assert!(!s.status_is_error);
     }
 
-    fn render(&mut self, rows: usize, cols: usize) {
-        match self.mode {
-            Mode::Loading => {
-                ui::render_header("loading...", cols);
-                println!();
-                println!("  Waiting for permissions...");
-            }
-            Mode::BrowseWorktrees => {
-                ui::render_header(&self.repo_name, cols);
-                ui::render_worktree_list(&self.worktrees, self.selected_index, rows);
-                ui::render_status(&self.status_message, self.status_is_error);
-                ui::render_footer(&self.mode);
-            }
-            Mode::SelectBranch => {
-                ui::render_header(&self.repo_name, cols);
-                ui::render_branch_list(&self.filtered_branches, self.selected_index, rows);
-                ui::render_footer(&self.mode);
-            }
-            Mode::InputBranch => {
-                ui::render_header(&self.repo_name, cols);
-                ui::render_input(&self.input_buffer);
-                ui::render_footer(&self.mode);
-            }
-            Mode::Confirming => {
-                ui::render_header(&self.repo_name, cols);
-                if let Some(wt) = self.worktrees.get(self.selected_index) {
-                    ui::render_confirm(&wt.branch);
-                }
-            }
+    #[test]
+    fn remove_dirty_over_pipe_is_an_error() {
+        let mut s = state_with_worktrees();
+        let mut ctx = BTreeMap::new();
+        ctx.insert("branch".into(), "feat-a".into());
+        ctx.insert("pipe_id".into(), "p1".into());
+        s.handle_remove_result(Some(2), b"uncommitted changes", &ctx);
+        assert_eq!(s.mode, Mode::BrowseWorktrees);
+        assert!(s.status_is_error);
+        assert_eq!(s.pending_remove, None);
+    }
+
+    #[test]
+    fn remove_result_error() {
+        let mut s = state_with_worktrees();
+        s.mode = Mode::Confirming;
+        let mut ctx = BTreeMap::new();
+        ctx.insert("branch".into(), "feat-a".into());
+        let action = s.handle_remove_result(Some(1), b"uncommitted changes", &ctx);
+        assert!(s.status_is_error);
+        assert!(s.status_message.contains("uncommitted changes"));
+        assert_eq!(s.mode, Mode::BrowseWorktrees);
+        assert_eq!(action, Action::Refresh);
+    }
+
+    fn make_tab(name: &str, active: bool) -> TabInfo {
+        TabInfo {
+            position: 0,
+            name: name.to_string(),
+            active,
+            panes_to_hide: 0,
+            is_fullscreen_active: false,
+            is_sync_panes_active: false,
+            are_floating_panes_visible: false,
+            other_focused_clients: vec![],
+            active_swap_layout_name: None,
+            is_swap_layout_dirty: false,
+            viewport_rows: 0,
+            viewport_columns: 0,
+            display_area_rows: 0,
+            display_area_columns: 0,
+            selectable_tiled_panes_count: 0,
+            selectable_floating_panes_count: 0,
         }
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::collections::BTreeSet;
+    #[test]
+    fn has_tab_for_branch_found() {
+        let s = State {
+            tabs: vec![make_tab("feature/cool", false), make_tab("fix-bug", false)],
+            ..Default::default()
+        };
+        assert!(s.has_tab_for_branch("feature/cool"));
+        assert!(s.has_tab_for_branch("fix-bug"));
+    }
 
-    fn key(bare: BareKey) -> KeyWithModifier {
-        KeyWithModifier { bare_key: bare, key_modifiers: BTreeSet::new() }
+    #[test]
+    fn has_tab_for_branch_not_found() {
+        let s = State { tabs: vec![make_tab("main", false)], ..Default::default() };
+        assert!(!s.has_tab_for_branch("nonexistent"));
     }
 
-    fn key_shift(bare: BareKey) -> KeyWithModifier {
-        let mut mods = BTreeSet::new();
-        mods.insert(KeyModifier::Shift);
-        KeyWithModifier { bare_key: bare, key_modifiers: mods }
+    #[test]
+    fn has_tab_for_branch_empty_tabs() {
+        let s = State::default();
+        assert!(!s.has_tab_for_branch("anything"));
     }
 
-    fn state_with_worktrees() -> State {
-        let mut s = State::default();
-        s.mode = Mode::BrowseWorktrees;
-        s.worktrees = vec![
-            Worktree { branch: "feat-a".into() },
-            Worktree { branch: "feat-b".into() },
-            Worktree { branch: "feat-c".into() },
-        ];
-        s.branches = vec!["main".into(), "feat-a".into(), "feat-b".into(), "dev".into()];
+    // --- External and detached worktree tests ---
+
+    fn state_with_external() -> State {
+        let mut s = state_with_worktrees();
+        s.worktrees.push(Worktree { path: "/src/ext".into(), managed: false, ..wt("ext") });
+        s.worktrees.push(Worktree {
+            path: "/src/scratch".into(),
+            head: "1a2b3c4d5e6f".into(),
+            managed: false,
+            detached: true,
+            ..Default::default()
+        });
         s
     }
 
-    // --- Parsing tests ---
-
     #[test]
-    fn parse_worktrees_basic() {
-        let output = "feature/cool\nfix-bug\n";
-        let wts = parse_worktrees(output);
-        assert_eq!(wts.len(), 2);
-        assert_eq!(wts[0].branch, "feature/cool");
-        assert_eq!(wts[1].branch, "fix-bug");
+    fn parse_worktrees_source_flags() {
+        let output = r#"{"version":1,"worktrees":[{"branch":"a"},
+            {"branch":"","path":"/x/scratch","head":"1a2b3c4d","managed":false,"detached":true}]}"#;
+        let wts = parse_worktrees(output).unwrap();
+        assert!(wts[0].managed && !wts[0].detached);
+        assert!(!wts[1].managed && wts[1].detached);
+        assert_eq!(wts[1].name(), "scratch@1a2b3c4");
     }
 
     #[test]
-    fn parse_worktrees_empty_output() {
-        let wts = parse_worktrees("");
-        assert!(wts.is_empty());
+    fn open_external_and_detached_by_path() {
+        let mut s = state_with_external();
+        s.selected_index = 3;
+        let action = s.handle_key_browse(&key(BareKey::Enter));
+        assert_eq!(
+            action,
+            Action::Spawn(SpawnRequest { branch: "ext".into(), path: Some("/src/ext".into()), ..Default::default() })
+        );
+        s.selected_index = 4;
+        let action = s.handle_key_browse(&key(BareKey::Char('o')));
+        assert_eq!(
+            action,
+            Action::Spawn(SpawnRequest {
+                branch: "scratch@1a2b3c4".into(),
+                path: Some("/src/scratch".into()),
+                ..Default::default()
+            })
+        );
+        s.tabs = vec![make_tab("scratch@1a2b3c4", false)];
+        assert_eq!(s.handle_key_browse(&key(BareKey::Enter)), Action::Focus("scratch@1a2b3c4".into()));
     }
 
     #[test]
-    fn parse_worktrees_strips_whitespace() {
-        let output = "  feat-a \n\n  feat-b  \n";
-        let wts = parse_worktrees(output);
-        assert_eq!(wts.len(), 2);
-        assert_eq!(wts[0].branch, "feat-a");
-        assert_eq!(wts[1].branch, "feat-b");
+    fn external_worktrees_are_not_removed() {
+        let mut s = state_with_external();
+        s.selected_index = 3;
+        s.handle_key_browse(&key(BareKey::Char('d')));
+        assert_eq!(s.mode, Mode::BrowseWorktrees);
+        assert!(s.status_is_error);
+        assert!(s.status_message.starts_with("Cannot remove ext: "), "{}", s.status_message);
+
+        s.marked = ["feat-a".to_string(), "scratch@1a2b3c4".to_string()].into();
+        s.handle_key_browse(&key(BareKey::Char('d')));
+        assert_eq!(s.mode, Mode::BrowseWorktrees);
+        assert!(s.status_message.starts_with("Cannot remove scratch@1a2b3c4: "));
     }
 
     #[test]
-    fn parse_branches_basic() {
-        let output = "main\nfeature/cool\nfix-bug\n";
-        let branches = parse_branches(output);
-        assert_eq!(branches, vec!["main", "feature/cool", "fix-bug"]);
+    fn external_branch_can_merge_but_detached_cannot() {
+        let mut s = state_with_external();
+        s.selected_index = 3;
+        s.handle_key_browse(&key(BareKey::Char('m')));
+        assert_eq!(s.mode, Mode::ChooseMerge);
+        s.mode = Mode::BrowseWorktrees;
+        s.selected_index = 4;
+        s.handle_key_browse(&key(BareKey::Char('m')));
+        assert_eq!(s.mode, Mode::BrowseWorktrees);
+        assert_eq!(s.status_message, "Cannot merge scratch@1a2b3c4: detached HEAD, no branch to merge");
     }
 
-    #[test]
-    fn parse_branches_strips_whitespace_and_empty() {
-        let output = "  main \n\n  dev  \n";
-        let branches = parse_branches(output);
-        assert_eq!(branches, vec!["main", "dev"]);
+    // --- Multi-select tests ---
+
+    fn marked(s: &State) -> Vec<&str> {
+        s.marked.iter().map(String::as_str).collect()
     }
 
-    // --- BrowseWorktrees key handler tests ---
+    fn result_ctx(cmd_type: &str, branch: &str) -> BTreeMap<String, String> {
+        let mut ctx = State::ctx(cmd_type);
+        ctx.insert("branch".into(), branch.into());
+        ctx
+    }
 
     #[test]
-    fn browse_j_moves_down() {
+    fn space_toggles_mark_and_moves_down() {
         let mut s = state_with_worktrees();
-        s.handle_key_browse(&key(BareKey::Char('j')));
+        s.handle_key_browse(&key(BareKey::Char(' ')));
+        assert_eq!(marked(&s), vec!["feat-a"]);
         assert_eq!(s.selected_index, 1);
-        s.handle_key_browse(&key(BareKey::Down));
-        assert_eq!(s.selected_index, 2);
+        s.selected_index = 0;
+        s.handle_key_browse(&key(BareKey::Char(' ')));
+        assert!(s.marked.is_empty());
     }
 
     #[test]
-    fn browse_j_wraps_around() {
+    fn a_marks_all_then_none() {
         let mut s = state_with_worktrees();
-        s.selected_index = 2;
-        s.handle_key_browse(&key(BareKey::Char('j')));
-        assert_eq!(s.selected_index, 0);
+        s.handle_key_browse(&key(BareKey::Char('a')));
+        assert_eq!(marked(&s), vec!["feat-a", "feat-b", "feat-c"]);
+        s.handle_key_browse(&key(BareKey::Char('a')));
+        assert!(s.marked.is_empty());
     }
 
     #[test]
-    fn browse_k_moves_up() {
+    fn c_confirms_removing_merged_worktrees() {
         let mut s = state_with_worktrees();
-        s.selected_index = 2;
-        s.handle_key_browse(&key(BareKey::Char('k')));
-        assert_eq!(s.selected_index, 1);
-        s.handle_key_browse(&key(BareKey::Up));
-        assert_eq!(s.selected_index, 0);
+        s.worktrees[0].merged = true;
+        s.worktrees[1].merged = true;
+        s.worktrees[1].dirty = 1;
+        s.worktrees[2].merged = true;
+        s.handle_key_browse(&key(BareKey::Char('c')));
+        assert_eq!(marked(&s), vec!["feat-a", "feat-c"]);
+        assert_eq!(s.mode, Mode::Confirming);
+        assert_eq!(s.target_branches(), vec!["feat-a", "feat-c"]);
     }
 
     #[test]
-    fn browse_k_wraps_around() {
+    fn c_without_merged_worktrees() {
         let mut s = state_with_worktrees();
-        s.selected_index = 0;
-        s.handle_key_browse(&key(BareKey::Char('k')));
-        assert_eq!(s.selected_index, 2);
+        s.handle_key_browse(&key(BareKey::Char('c')));
+        assert!(s.marked.is_empty());
+        assert_eq!(s.mode, Mode::BrowseWorktrees);
+        assert_eq!(s.status_message, "No merged worktrees to clean up");
     }
 
     #[test]
-    fn browse_jk_noop_on_empty() {
-        let mut s = State { mode: Mode::BrowseWorktrees, ..Default::default() };
-        s.handle_key_browse(&key(BareKey::Char('j')));
-        assert_eq!(s.selected_index, 0);
-        s.handle_key_browse(&key(BareKey::Char('k')));
-        assert_eq!(s.selected_index, 0);
+    fn stale_after_configured_days() {
+        let now = 1_700_000_000;
+        let s = State { stale_days: 14, ..Default::default() };
+        let old = Worktree { last_commit_time: now - 15 * 86_400, ..wt("old") };
+        assert!(s.is_stale(&old, now));
+        assert!(!s.is_stale(&Worktree { last_commit_time: now - 86_400, ..wt("new") }, now));
+        assert!(!s.is_stale(&Worktree { merged: true, ..old.clone() }, now));
+        assert!(!s.is_stale(&wt("unknown"), now));
+        assert!(!State { stale_days: 0, ..Default::default() }.is_stale(&old, now));
     }
 
     #[test]
-    fn browse_enter_spawns_selected() {
+    fn esc_clears_marks_before_closing() {
         let mut s = state_with_worktrees();
-        s.selected_index = 1;
-        let action = s.handle_key_browse(&key(BareKey::Enter));
-        assert_eq!(action, Action::Spawn("feat-b".into()));
-        assert_eq!(s.status_message, "Spawning 'feat-b'...");
+        s.marked.insert("feat-b".into());
+        assert_eq!(s.handle_key_browse(&key(BareKey::Esc)), Action::None);
+        assert!(s.marked.is_empty());
+        assert_eq!(s.handle_key_browse(&key(BareKey::Esc)), Action::Close);
     }
 
     #[test]
-    fn browse_enter_noop_on_empty() {
-        let mut s = State { mode: Mode::BrowseWorktrees, ..Default::default() };
+    fn marks_pruned_when_worktrees_disappear() {
+        let mut s = state_with_worktrees();
+        s.marked = ["feat-a".to_string(), "gone".to_string()].into();
+        s.handle_list_worktrees(Some(0), br#"{"version":1,"worktrees":[{"branch":"feat-a"}]}"#, b"");
+        assert_eq!(marked(&s), vec!["feat-a"]);
+    }
+
+    #[test]
+    fn enter_opens_marked_without_tabs() {
+        let mut s = state_with_worktrees();
+        s.marked = ["feat-a".to_string(), "feat-c".to_string()].into();
+        s.tabs = vec![make_tab("feat-a", false)];
         let action = s.handle_key_browse(&key(BareKey::Enter));
-        assert_eq!(action, Action::None);
+        assert_eq!(action, Action::Spawn("feat-c".into()));
+        assert_eq!(s.batch.as_ref().unwrap().total, 1);
+        assert_eq!(s.status_message, "Opening 1/1: 'feat-c'...");
+
+        let action = s.handle_key_browse(&key(BareKey::Char('o')));
+        assert_eq!(action, Action::Spawn("feat-a".into()));
+        assert_eq!(s.batch.as_ref().unwrap().total, 2);
     }
 
     #[test]
-    fn browse_n_switches_to_select_branch() {
+    fn batch_remove_runs_one_at_a_time() {
         let mut s = state_with_worktrees();
-        s.selected_index = 2;
-        s.handle_key_browse(&key(BareKey::Char('n')));
-        assert_eq!(s.mode, Mode::SelectBranch);
-        assert_eq!(s.selected_index, 0);
-        assert_eq!(s.filtered_branches, s.branches);
+        s.marked = ["feat-a".to_string(), "feat-b".to_string(), "feat-c".to_string()].into();
+        s.handle_key_browse(&key(BareKey::Char('d')));
+        assert_eq!(s.mode, Mode::Confirming);
+        let action = s.handle_key_confirming(&key(BareKey::Char('b')));
+        assert_eq!(
+            action,
+            Action::Remove(RemoveRequest { branch: "feat-a".into(), branch_cleanup: BranchCleanup::DeleteMerged, force: false })
+        );
+        assert_eq!(s.mode, Mode::BrowseWorktrees);
+        assert_eq!(s.status_message, "Removing 1/3: 'feat-a'...");
+
+        let ctx = result_ctx(CMD_REMOVE, "feat-a");
+        let action = s.handle_remove_result(Some(0), b"", &ctx);
+        let action = s.continue_batch(action, &ctx);
+        assert_eq!(
+            action,
+            Action::Batch(vec![Action::Remove(RemoveRequest {
+                branch: "feat-b".into(),
+                branch_cleanup: BranchCleanup::DeleteMerged,
+                force: false
+            })])
+        );
+        assert_eq!(s.status_message, "Removing 2/3: 'feat-b'...");
+
+        // A dirty worktree is reported instead of asking to force-remove it
+        let ctx = result_ctx(CMD_REMOVE, "feat-b");
+        let action = s.handle_remove_result(Some(2), b"", &ctx);
+        s.continue_batch(action, &ctx);
+        assert_eq!(s.mode, Mode::BrowseWorktrees);
+        assert_eq!(s.pending_remove, None);
+
+        let ctx = result_ctx(CMD_REMOVE, "feat-c");
+        let action = s.handle_remove_result(Some(1), b"Error: boom", &ctx);
+        let action = s.continue_batch(action, &ctx);
+        assert_eq!(action, Action::Batch(vec![Action::Refresh]));
+        assert!(s.batch.is_none());
+        assert!(s.marked.is_empty());
+        assert!(s.status_is_error);
+        assert_eq!(
+            s.status_message,
+            "Removed 1 of 3: ✓ feat-a  ✗ feat-b (uncommitted changes)  ✗ feat-c (Remove failed: Error: boom)"
+        );
     }
 
     #[test]
-    fn browse_i_switches_to_input_branch() {
+    fn batch_merge_keeps_merged_marked() {
         let mut s = state_with_worktrees();
-        s.input_buffer = "leftover".into();
-        s.handle_key_browse(&key(BareKey::Char('i')));
-        assert_eq!(s.mode, Mode::InputBranch);
-        assert!(s.input_buffer.is_empty());
+        s.marked = ["feat-a".to_string(), "feat-c".to_string()].into();
+        s.handle_key_browse(&key(BareKey::Char('m')));
+        let action = s.handle_key_choose_merge(&key(BareKey::Char('s')));
+        assert_eq!(action, Action::Merge(MergeRequest { branch: "feat-a".into(), strategy: MergeStrategy::Squash }));
+
+        let ctx = result_ctx(CMD_MERGE, "feat-a");
+        let out = br#"{"version":1,"result":"merged","branch":"feat-a","base":"main","strategy":"squash"}"#;
+        let action = s.handle_merge_result(Some(0), out, b"", &ctx);
+        let action = s.continue_batch(action, &ctx);
+        assert_eq!(action, Action::Batch(vec![Action::Merge(MergeRequest { branch: "feat-c".into(), strategy: MergeStrategy::Squash })]));
+        assert_eq!(s.mode, Mode::BrowseWorktrees);
+
+        let ctx = result_ctx(CMD_MERGE, "feat-c");
+        let out = br#"{"version":1,"result":"conflict","branch":"feat-c","base":"main","strategy":"squash","conflicts":["x"]}"#;
+        let action = s.handle_merge_result(Some(3), out, b"", &ctx);
+        s.continue_batch(action, &ctx);
+        assert_eq!(marked(&s), vec!["feat-a"]);
+        assert!(s.status_message.starts_with("Merged 1 of 2: ✓ feat-a  ✗ feat-c (Conflicts merging"));
     }
 
     #[test]
-    fn browse_d_switches_to_confirming() {
+    fn batch_ignores_unrelated_results() {
         let mut s = state_with_worktrees();
-        s.handle_key_browse(&key(BareKey::Char('d')));
-        assert_eq!(s.mode, Mode::Confirming);
+        s.marked = ["feat-a".to_string(), "feat-b".to_string()].into();
+        s.mode = Mode::Confirming;
+        s.handle_key_confirming(&key(BareKey::Char('y')));
+        let mut ctx = result_ctx(CMD_REMOVE, "feat-a");
+        ctx.insert("pipe_id".into(), "p1".into());
+        assert_eq!(s.continue_batch(Action::Refresh, &ctx), Action::Refresh);
+        assert_eq!(s.continue_batch(Action::Refresh, &result_ctx(CMD_MERGE, "feat-a")), Action::Refresh);
+        assert_eq!(s.batch.as_ref().unwrap().current, "feat-a");
+    }
+
+    // --- Merge tests ---
+
+    fn merge_ctx(branch: &str) -> BTreeMap<String, String> {
+        let mut ctx = State::ctx(CMD_MERGE);
+        ctx.insert("branch".into(), branch.into());
+        ctx
     }
 
     #[test]
-    fn browse_d_noop_on_empty() {
+    fn browse_m_opens_merge_choice() {
+        let mut s = state_with_worktrees();
+        s.handle_key_browse(&key(BareKey::Char('m')));
+        assert_eq!(s.mode, Mode::ChooseMerge);
+    }
+
+    #[test]
+    fn browse_m_ignored_without_worktrees() {
         let mut s = State { mode: Mode::BrowseWorktrees, ..Default::default() };
-        s.handle_key_browse(&key(BareKey::Char('d')));
+        s.handle_key_browse(&key(BareKey::Char('m')));
+        assert_eq!(s.mode, Mode::BrowseWorktrees);
+    }
+
+    #[test]
+    fn choose_merge_strategies() {
+        for (c, strategy) in [('m', MergeStrategy::Merge), ('s', MergeStrategy::Squash), ('r', MergeStrategy::Rebase)] {
+            let mut s = state_with_worktrees();
+            s.selected_index = 1;
+            s.mode = Mode::ChooseMerge;
+            let action = s.handle_key_choose_merge(&key(BareKey::Char(c)));
+            assert_eq!(action, Action::Merge(MergeRequest { branch: "feat-b".into(), strategy }));
+            assert_eq!(s.mode, Mode::BrowseWorktrees);
+        }
+    }
+
+    #[test]
+    fn choose_merge_esc_cancels() {
+        let mut s = state_with_worktrees();
+        s.mode = Mode::ChooseMerge;
+        assert_eq!(s.handle_key_choose_merge(&key(BareKey::Esc)), Action::None);
         assert_eq!(s.mode, Mode::BrowseWorktrees);
     }
 
     #[test]
-    fn browse_r_returns_refresh() {
+    fn merge_success_offers_removal() {
         let mut s = state_with_worktrees();
-        let action = s.handle_key_browse(&key(BareKey::Char('r')));
+        let out = br#"{"version":1,"result":"merged","branch":"feat-c","base":"main","strategy":"squash","conflicts":[]}"#;
+        let action = s.handle_merge_result(Some(0), out, b"", &merge_ctx("feat-c"));
         assert_eq!(action, Action::Refresh);
-        assert_eq!(s.status_message, "Refreshed");
+        assert_eq!(s.mode, Mode::Confirming);
+        assert_eq!(s.selected_index, 2);
+        assert!(!s.status_is_error);
+        assert_eq!(s.status_message, "Merged 'feat-c' into 'main' (squash). Remove the worktree?");
+        assert_eq!(s.handle_key_confirming(&key(BareKey::Char('y'))), Action::Remove("feat-c".into()));
     }
 
     #[test]
-    fn browse_q_returns_close() {
+    fn merge_conflict_is_reported() {
         let mut s = state_with_worktrees();
-        assert_eq!(s.handle_key_browse(&key(BareKey::Char('q'))), Action::Close);
+        let out = br#"{"version":1,"result":"conflict","branch":"feat-a","base":"main","strategy":"merge","conflicts":["a.rs","b/c.rs"]}"#;
+        let action = s.handle_merge_result(Some(3), out, b"", &merge_ctx("feat-a"));
+        assert_eq!(action, Action::None);
+        assert_eq!(s.mode, Mode::BrowseWorktrees);
+        assert!(s.status_is_error);
+        assert_eq!(s.status_message, "Conflicts merging 'feat-a' into 'main'; nothing was changed: a.rs, b/c.rs");
     }
 
     #[test]
-    fn browse_esc_returns_close() {
+    fn merge_failure_shows_stderr() {
         let mut s = state_with_worktrees();
-        assert_eq!(s.handle_key_browse(&key(BareKey::Esc)), Action::Close);
+        let err = b"Error: worktree 'feat-a' has uncommitted changes; commit them first.\n";
+        s.handle_merge_result(Some(1), b"", err, &merge_ctx("feat-a"));
+        assert!(s.status_is_error);
+        assert_eq!(
+            s.status_message,
+            "Merge of 'feat-a' failed: Error: worktree 'feat-a' has uncommitted changes; commit them first."
+        );
     }
 
-    // --- SelectBranch key handler tests ---
+    // --- Sort and group tests ---
 
-    #[test]
-    fn select_branch_jk_navigates() {
-        let mut s = state_with_worktrees();
-        s.mode = Mode::SelectBranch;
-        s.filtered_branches = s.branches.clone();
-        s.selected_index = 0;
+    fn names(s: &State) -> Vec<String> {
+        s.worktrees.iter().map(Worktree::name).collect()
+    }
 
-        s.handle_key_select_branch(&key(BareKey::Char('j')));
-        assert_eq!(s.selected_index, 1);
-        s.handle_key_select_branch(&key(BareKey::Char('k')));
-        assert_eq!(s.selected_index, 0);
+    fn load_list(s: &mut State, json: &str) {
+        s.handle_list_worktrees(Some(0), json.as_bytes(), b"");
     }
 
     #[test]
-    fn select_branch_wraps() {
-        let mut s = state_with_worktrees();
-        s.mode = Mode::SelectBranch;
-        s.filtered_branches = vec!["a".into(), "b".into()];
+    fn reload_applies_sort_order() {
+        let mut s = State { mode: Mode::BrowseWorktrees, sort_order: SortOrder::Recent, ..Default::default() };
+        load_list(
+            &mut s,
+            r#"{"version":1,"worktrees":[{"branch":"a","last_commit_time":1},{"branch":"b","last_commit_time":3},{"branch":"c","last_commit_time":2}]}"#,
+        );
+        assert_eq!(names(&s), ["b", "c", "a"]);
+    }
+
+    #[test]
+    fn s_cycles_sort_order_keeping_selection() {
+        let mut s = State { mode: Mode::BrowseWorktrees, ..Default::default() };
+        load_list(
+            &mut s,
+            r#"{"version":1,"worktrees":[{"branch":"b","dirty":1},{"branch":"c"},{"branch":"a","dirty":5}]}"#,
+        );
         s.selected_index = 1;
+        s.handle_key_browse(&key(BareKey::Char('s')));
+        assert_eq!(s.sort_order, SortOrder::Name);
+        assert_eq!(names(&s), ["a", "b", "c"]);
+        assert_eq!(s.selected_index, 2);
+        assert_eq!(s.status_message, "Sorted by name");
+        for _ in 0..3 {
+            s.handle_key_browse(&key(BareKey::Char('s')));
+        }
+        assert_eq!(s.sort_order, SortOrder::Dirty);
+        assert_eq!(names(&s), ["a", "b", "c"]);
+        for _ in 0..2 {
+            s.handle_key_browse(&key(BareKey::Char('s')));
+        }
+        // Back to git order.
+        assert_eq!(s.sort_order, SortOrder::Git);
+        assert_eq!(names(&s), ["b", "c", "a"]);
+        assert_eq!(s.worktrees[s.selected_index].branch, "c");
+    }
 
-        s.handle_key_select_branch(&key(BareKey::Char('j')));
-        assert_eq!(s.selected_index, 0);
+    fn grouped_state() -> State {
+        let mut s = State { mode: Mode::BrowseWorktrees, group_by_prefix: true, ..Default::default() };
+        load_list(
+            &mut s,
+            r#"{"version":1,"worktrees":[{"branch":"fix/b"},{"branch":"hotfix"},{"branch":"feature/x"},{"branch":"fix/a"}]}"#,
+        );
+        s
+    }
 
-        s.handle_key_select_branch(&key(BareKey::Char('k')));
-        assert_eq!(s.selected_index, 1);
+    fn row_labels(s: &State) -> Vec<String> {
+        s.list_rows(0)
+            .0
+            .iter()
+            .map(|row| match row {
+                ui::ListRow::Group { name, len, collapsed } => format!("{name}({len}{})", if *collapsed { "+" } else { "" }),
+                ui::ListRow::Worktree(i, _) => s.worktrees[*i].name(),
+            })
+            .collect()
     }
 
     #[test]
-    fn select_branch_enter_spawns() {
-        let mut s = state_with_worktrees();
-        s.mode = Mode::SelectBranch;
-        s.filtered_branches = vec!["dev".into(), "main".into()];
-        s.selected_index = 0;
+    fn grouping_adds_section_headers() {
+        let mut s = grouped_state();
+        assert_eq!(names(&s), ["feature/x", "fix/b", "fix/a", "hotfix"]);
+        assert_eq!(row_labels(&s), ["feature/(1)", "feature/x", "fix/(2)", "fix/b", "fix/a", "(1)", "hotfix"]);
+        assert_eq!(s.list_rows(0).1, 1);
+        s.handle_key_browse(&key(BareKey::Char('g')));
+        assert!(!s.group_by_prefix);
+        assert_eq!(names(&s), ["fix/b", "hotfix", "feature/x", "fix/a"]);
+        assert_eq!(s.worktrees[s.selected_index].branch, "feature/x");
+    }
 
-        let action = s.handle_key_select_branch(&key(BareKey::Enter));
-        assert_eq!(action, Action::Spawn("dev".into()));
-        assert_eq!(s.mode, Mode::BrowseWorktrees);
+    #[test]
+    fn collapsed_group_is_one_stop() {
+        let mut s = grouped_state();
+        s.handle_key_browse(&key(BareKey::Down));
+        s.handle_key_browse(&key(BareKey::Down));
+        assert_eq!(s.selected_worktree().map(Worktree::name).as_deref(), Some("fix/a"));
+        s.handle_key_browse(&key(BareKey::Tab));
+        assert_eq!(row_labels(&s), ["feature/(1)", "feature/x", "fix/(2+)", "(1)", "hotfix"]);
+        // The cursor moves to the header, which has no worktree to act on.
+        assert_eq!(s.list_rows(0).1, 2);
+        assert_eq!(s.selected_worktree(), None);
+        assert_eq!(s.handle_key_browse(&key(BareKey::Char('o'))), Action::None);
+        s.handle_key_browse(&key(BareKey::Down));
+        assert_eq!(s.selected_worktree().map(Worktree::name).as_deref(), Some("hotfix"));
+        s.handle_key_browse(&key(BareKey::Up));
+        s.handle_key_browse(&key(BareKey::Up));
+        assert_eq!(s.selected_worktree().map(Worktree::name).as_deref(), Some("feature/x"));
+        s.handle_key_browse(&key(BareKey::Down));
+        s.handle_key_browse(&key(BareKey::Enter));
+        assert_eq!(row_labels(&s), ["feature/(1)", "feature/x", "fix/(2)", "fix/b", "fix/a", "(1)", "hotfix"]);
+        assert_eq!(s.selected_worktree().map(Worktree::name).as_deref(), Some("fix/b"));
     }
 
     #[test]
-    fn select_branch_esc_goes_back() {
-        let mut s = state_with_worktrees();
-        s.mode = Mode::SelectBranch;
-        s.selected_index = 2;
-        s.handle_key_select_branch(&key(BareKey::Esc));
-        assert_eq!(s.mode, Mode::BrowseWorktrees);
+    fn space_on_collapsed_group_marks_all_of_it() {
+        let mut s = grouped_state();
+        s.handle_key_browse(&key(BareKey::Down));
+        s.handle_key_browse(&key(BareKey::Tab));
+        s.handle_key_browse(&key(BareKey::Char(' ')));
+        assert_eq!(s.marked, BTreeSet::from(["fix/a".to_string(), "fix/b".to_string()]));
+        assert_eq!(s.selected_worktree().map(Worktree::name).as_deref(), Some("hotfix"));
+        s.handle_key_browse(&key(BareKey::Up));
+        s.handle_key_browse(&key(BareKey::Char(' ')));
+        assert!(s.marked.is_empty());
+    }
+
+    #[test]
+    fn collapsed_group_survives_reload() {
+        let mut s = grouped_state();
+        s.handle_key_browse(&key(BareKey::Down));
+        s.handle_key_browse(&key(BareKey::Down));
+        s.handle_key_browse(&key(BareKey::Tab));
+        load_list(
+            &mut s,
+            r#"{"version":1,"worktrees":[{"branch":"fix/c"},{"branch":"fix/b"},{"branch":"fix/a"}]}"#,
+        );
+        assert_eq!(row_labels(&s), ["fix/(3+)"]);
         assert_eq!(s.selected_index, 0);
+        assert_eq!(s.selected_worktree(), None);
     }
 
-    // --- InputBranch key handler tests ---
+    // --- Preview tests ---
 
     #[test]
-    fn input_branch_typing() {
-        let mut s = State { mode: Mode::InputBranch, ..Default::default() };
-        s.handle_key_input_branch(&key(BareKey::Char('f')));
-        s.handle_key_input_branch(&key(BareKey::Char('o')));
-        s.handle_key_input_branch(&key(BareKey::Char('o')));
-        assert_eq!(s.input_buffer, "foo");
+    fn parse_numstat_lines() {
+        let out = "3\t1\tsrc/main.rs\n-\t-\tlogo.png\n10\t0\tdir/with\ttab\n";
+        assert_eq!(
+            parse_numstat(out),
+            vec![
+                FileChange { path: "src/main.rs".into(), added: Some(3), deleted: Some(1) },
+                FileChange { path: "logo.png".into(), added: None, deleted: None },
+                FileChange { path: "dir/with\ttab".into(), added: Some(10), deleted: Some(0) },
+            ]
+        );
+        assert!(parse_numstat("").is_empty());
+    }
+
+    fn state_with_preview() -> State {
+        State { show_preview: true, ..state_with_worktrees() }
+    }
+
+    fn preview_ctx(branch: &str, section: &str) -> BTreeMap<String, String> {
+        let mut ctx = State::ctx(CMD_PREVIEW);
+        ctx.insert("branch".into(), branch.into());
+        ctx.insert("section".into(), section.into());
+        ctx
     }
 
     #[test]
-    fn input_branch_shift_chars() {
-        let mut s = State { mode: Mode::InputBranch, ..Default::default() };
-        s.handle_key_input_branch(&key_shift(BareKey::Char('F')));
-        assert_eq!(s.input_buffer, "F");
+    fn start_preview_follows_selection() {
+        let mut s = state_with_preview();
+        assert_eq!(s.start_preview().map(|wt| wt.branch), Some("feat-a".into()));
+        // Already loading for this worktree
+        assert_eq!(s.start_preview(), None);
+        s.handle_key_browse(&key(BareKey::Char('j')));
+        assert_eq!(s.start_preview().map(|wt| wt.branch), Some("feat-b".into()));
+        assert_eq!(s.preview.as_ref().unwrap().branch, "feat-b");
+        assert_eq!(s.preview.as_ref().unwrap().changes, None);
     }
 
     #[test]
-    fn input_branch_backspace() {
-        let mut s = State { mode: Mode::InputBranch, input_buffer: "ab".into(), ..Default::default() };
-        s.handle_key_input_branch(&key(BareKey::Backspace));
-        assert_eq!(s.input_buffer, "a");
+    fn start_preview_only_when_shown_in_browse() {
+        let mut s = state_with_worktrees();
+        assert_eq!(s.start_preview(), None);
+        s.handle_key_browse(&key(BareKey::Char('p')));
+        assert!(s.show_preview);
+        s.mode = Mode::SelectBranch;
+        assert_eq!(s.start_preview(), None);
+        s.mode = Mode::BrowseWorktrees;
+        assert!(s.start_preview().is_some());
+        s.handle_key_browse(&key(BareKey::Char('p')));
+        assert!(!s.show_preview);
     }
 
     #[test]
-    fn input_branch_enter_spawns() {
-        let mut s = State { mode: Mode::InputBranch, input_buffer: "feat/new".into(), ..Default::default() };
-        let action = s.handle_key_input_branch(&key(BareKey::Enter));
-        assert_eq!(action, Action::Spawn("feat/new".into()));
-        assert_eq!(s.mode, Mode::BrowseWorktrees);
+    fn preview_results_fill_sections() {
+        let mut s = state_with_preview();
+        s.start_preview();
+        s.handle_preview_result(Some(0), b"1\t2\ta.rs\n", b"", &preview_ctx("feat-a", "changes"));
+        s.handle_preview_result(Some(0), b"abc123 Fix it (2 hours ago)\n", b"", &preview_ctx("feat-a", "commits"));
+        let p = s.preview.unwrap();
+        assert_eq!(p.changes, Some(Ok(vec![FileChange { path: "a.rs".into(), added: Some(1), deleted: Some(2) }])));
+        assert_eq!(p.commits, Some(Ok(vec!["abc123 Fix it (2 hours ago)".to_string()])));
     }
 
     #[test]
-    fn input_branch_enter_noop_on_empty() {
-        let mut s = State { mode: Mode::InputBranch, input_buffer: "  ".into(), ..Default::default() };
-        let action = s.handle_key_input_branch(&key(BareKey::Enter));
-        assert_eq!(action, Action::None);
-        assert_eq!(s.mode, Mode::InputBranch);
+    fn preview_error_is_kept() {
+        let mut s = state_with_preview();
+        s.start_preview();
+        s.handle_preview_result(Some(128), b"", b"fatal: bad revision 'main'\n", &preview_ctx("feat-a", "changes"));
+        assert_eq!(s.preview.unwrap().changes, Some(Err("fatal: bad revision 'main'".into())));
     }
 
     #[test]
-    fn input_branch_esc_goes_back() {
-        let mut s = State { mode: Mode::InputBranch, input_buffer: "wip".into(), ..Default::default() };
-        s.handle_key_input_branch(&key(BareKey::Esc));
-        assert_eq!(s.mode, Mode::BrowseWorktrees);
+    fn stale_preview_result_is_dropped() {
+        let mut s = state_with_preview();
+        s.start_preview();
+        s.handle_key_browse(&key(BareKey::Char('j')));
+        s.start_preview();
+        s.handle_preview_result(Some(0), b"1\t1\told.rs\n", b"", &preview_ctx("feat-a", "changes"));
+        assert_eq!(s.preview.unwrap().changes, None);
     }
 
-    // --- Confirming key handler tests ---
+    #[test]
+    fn worktree_reload_resets_changed_preview() {
+        let mut s = state_with_preview();
+        s.start_preview();
+        s.handle_list_worktrees(Some(0), br#"{"version":1,"worktrees":[{"branch":"feat-a","dirty":2}]}"#, b"");
+        assert_eq!(s.preview, None);
+    }
 
     #[test]
-    fn confirm_y_removes() {
+    fn worktree_reload_keeps_unchanged_preview() {
+        let mut s = state_with_preview();
+        s.start_preview();
+        s.handle_list_worktrees(Some(0), br#"{"version":1,"worktrees":[{"branch":"feat-a"}]}"#, b"");
+        assert_eq!(s.preview.map(|p| p.branch), Some("feat-a".into()));
+    }
+
+    #[test]
+    fn list_worktrees_keeps_selection_by_name() {
         let mut s = state_with_worktrees();
-        s.mode = Mode::Confirming;
         s.selected_index = 1;
-        let action = s.handle_key_confirming(&key(BareKey::Char('y')));
-        assert_eq!(action, Action::Remove("feat-b".into()));
-        assert_eq!(s.status_message, "Removing 'feat-b'...");
+        let json = br#"{"version":1,"worktrees":[{"branch":"new"},{"branch":"feat-a"},{"branch":"feat-b"}]}"#;
+        s.handle_list_worktrees(Some(0), json, b"");
+        assert_eq!(s.worktrees[s.selected_index].branch, "feat-b");
     }
 
     #[test]
-    fn confirm_n_cancels() {
-        let mut s = state_with_worktrees();
+    fn timer_refreshes_only_while_browsing() {
+        let mut s = State { repo_root: "/repo".into(), visible: true, timer_armed: true, ..state_with_worktrees() };
+        assert_eq!(s.handle_timer(), Action::ListWorktrees);
+        assert!(!s.timer_armed);
         s.mode = Mode::Confirming;
-        s.handle_key_confirming(&key(BareKey::Char('n')));
-        assert_eq!(s.mode, Mode::BrowseWorktrees);
+        assert_eq!(s.handle_timer(), Action::None);
+        s.mode = Mode::BrowseWorktrees;
+        s.visible = false;
+        assert_eq!(s.handle_timer(), Action::None);
     }
 
     #[test]
-    fn confirm_esc_cancels() {
-        let mut s = state_with_worktrees();
-        s.mode = Mode::Confirming;
-        s.handle_key_confirming(&key(BareKey::Esc));
-        assert_eq!(s.mode, Mode::BrowseWorktrees);
+    fn timer_scheduled_while_visible() {
+        let mut s = State { visible: true, refresh_interval: 5, ..Default::default() };
+        assert!(s.wants_timer());
+        s.timer_armed = true;
+        assert!(!s.wants_timer());
+        s.timer_armed = false;
+        s.visible = false;
+        assert!(!s.wants_timer());
+        s.visible = true;
+        s.refresh_interval = 0;
+        assert!(!s.wants_timer());
     }
 
-    // --- Command result handler tests ---
+    // --- Pipe command tests ---
 
     #[test]
-    fn git_toplevel_sets_repo() {
-        let mut s = State::default();
-        let action = s.handle_git_toplevel(Some(0), b"repo_root=/home/user/myrepo\nrepo_name=myrepo\n", b"");
-        assert_eq!(s.repo_root, "/home/user/myrepo");
-        assert_eq!(s.repo_name, "myrepo");
-        assert_eq!(s.mode, Mode::BrowseWorktrees);
-        assert_eq!(action, Action::FetchWorktreesAndBranches);
+    fn pipe_list_and_remove() {
+        let s = State::default();
+        assert_eq!(s.handle_pipe_command("list"), Ok(Action::ListWorktrees));
+        assert_eq!(s.handle_pipe_command("  remove   feat/x "), Ok(Action::Remove("feat/x".into())));
+        assert_eq!(
+            s.handle_pipe_command("remove --force-delete-branch feat/x"),
+            Ok(Action::Remove(RemoveRequest {
+                branch: "feat/x".into(),
+                branch_cleanup: BranchCleanup::ForceDelete,
+                force: false
+            }))
+        );
+        assert_eq!(
+            s.handle_pipe_command("remove --force --delete-branch feat/x"),
+            Ok(Action::Remove(RemoveRequest {
+                branch: "feat/x".into(),
+                branch_cleanup: BranchCleanup::DeleteMerged,
+                force: true
+            }))
+        );
+        assert!(s.handle_pipe_command("remove").is_err());
+        assert!(s.handle_pipe_command("remove --bogus feat/x").is_err());
     }
 
     #[test]
-    fn git_toplevel_parses_by_key() {
-        let mut s = State::default();
-        // Order should not matter
-        let action = s.handle_git_toplevel(Some(0), b"repo_name=myrepo\nrepo_root=/home/user/myrepo\n", b"");
-        assert_eq!(s.repo_root, "/home/user/myrepo");
-        assert_eq!(s.repo_name, "myrepo");
-        assert_eq!(action, Action::FetchWorktreesAndBranches);
+    fn pipe_spawn_with_options() {
+        let s = State { agents: vec![AgentProfile::new("aider", "aider")], ..Default::default() };
+        assert_eq!(s.handle_pipe_command("spawn feature/x"), Ok(Action::Spawn("feature/x".into())));
+        assert_eq!(
+            s.handle_pipe_command("spawn --base v1.0 feature/x --agent aider"),
+            Ok(Action::Spawn(SpawnRequest {
+                branch: "feature/x".into(),
+                base: Some("v1.0".into()),
+                agent: Some("aider".into()),
+                path: None,
+                prompt: None,
+            }))
+        );
     }
 
     #[test]
-    fn git_toplevel_error() {
-        let mut s = State::default();
-        let action = s.handle_git_toplevel(Some(128), b"", b"not a git repo");
-        assert!(s.status_is_error);
-        assert!(s.status_message.contains("not a git repo"));
-        assert_eq!(s.mode, Mode::Loading);
-        assert_eq!(action, Action::None);
+    fn pipe_spawn_rejects_bad_input() {
+        let s = State::default();
+        assert_eq!(s.handle_pipe_command("spawn"), Err(PIPE_USAGE.to_string()));
+        assert_eq!(s.handle_pipe_command("spawn a b"), Err(PIPE_USAGE.to_string()));
+        assert_eq!(s.handle_pipe_command("spawn --agent ghost a"), Err("unknown agent 'ghost'".to_string()));
     }
 
     #[test]
-    fn git_toplevel_missing_fields() {
-        let mut s = State::default();
-        let action = s.handle_git_toplevel(Some(0), b"repo_root=/foo\n", b"");
-        assert!(s.status_is_error);
-        assert!(s.status_message.contains("Failed to parse repo info"));
-        assert_eq!(action, Action::None);
+    fn pipe_focus_requires_tab() {
+        let s = State { tabs: vec![make_tab("feature/x", false)], ..Default::default() };
+        assert_eq!(s.handle_pipe_command("focus feature/x"), Ok(Action::Focus("feature/x".into())));
+        assert_eq!(s.handle_pipe_command("focus other"), Err("no tab open for 'other'".to_string()));
     }
 
     #[test]
-    fn spawn_result_success() {
-        let mut s = state_with_worktrees();
-        let mut ctx = BTreeMap::new();
-        ctx.insert("branch".into(), "feat-a".into());
-        let action = s.handle_spawn_result(Some(0), b"", &ctx);
-        assert_eq!(s.status_message, "Spawned 'feat-a'");
-        assert!(!s.status_is_error);
-        assert_eq!(action, Action::Refresh);
+    fn pipe_unknown_command() {
+        let s = State::default();
+        assert_eq!(s.handle_pipe_command(""), Err(PIPE_USAGE.to_string()));
+        assert_eq!(s.handle_pipe_command("list extra"), Err(PIPE_USAGE.to_string()));
+        assert_eq!(s.handle_pipe_command("explode"), Err(PIPE_USAGE.to_string()));
     }
 
     #[test]
-    fn spawn_result_error() {
-        let mut s = state_with_worktrees();
-        let mut ctx = BTreeMap::new();
-        ctx.insert("branch".into(), "bad".into());
-        let action = s.handle_spawn_result(Some(1), b"something broke", &ctx);
-        assert!(s.status_is_error);
-        assert!(s.status_message.contains("something broke"));
-        assert_eq!(action, Action::Refresh);
+    fn pipe_rejects_quotes() {
+        let s = State::default();
+        let err = Err("quotes are not supported; arguments are separated by spaces".to_string());
+        assert_eq!(s.handle_pipe_command(r#"spawn "feature/x""#), err);
+        assert_eq!(s.handle_pipe_command("remove 'my branch'"), err);
     }
 
     #[test]
-    fn remove_result_success() {
-        let mut s = state_with_worktrees();
-        s.mode = Mode::Confirming;
-        let mut ctx = BTreeMap::new();
-        ctx.insert("branch".into(), "feat-a".into());
-        let action = s.handle_remove_result(Some(0), b"", &ctx);
-        assert_eq!(s.status_message, "Removed 'feat-a'");
-        assert_eq!(s.mode, Mode::BrowseWorktrees);
-        assert_eq!(action, Action::Refresh);
+    fn pipe_reply_only_for_pipe_commands() {
+        let ctx = State::ctx(CMD_SPAWN);
+        assert_eq!(State::default().pipe_reply(Some(0), b"", b"", &ctx), None);
     }
 
     #[test]
-    fn remove_result_error() {
-        let mut s = state_with_worktrees();
-        s.mode = Mode::Confirming;
-        let mut ctx = BTreeMap::new();
-        ctx.insert("branch".into(), "feat-a".into());
-        let action = s.handle_remove_result(Some(1), b"uncommitted changes", &ctx);
-        assert!(s.status_is_error);
-        assert!(s.status_message.contains("uncommitted changes"));
-        assert_eq!(s.mode, Mode::BrowseWorktrees);
-        assert_eq!(action, Action::Refresh);
+    fn pipe_reply_messages() {
+        let s = State::default();
+        let mut ctx = State::pipe_ctx(CMD_SPAWN, Some("p1"));
+        ctx.insert("branch".into(), "feat/x".into());
+        assert_eq!(s.pipe_reply(Some(0), b"", b"", &ctx), Some(("p1".into(), "spawned 'feat/x'".into())));
+        assert_eq!(
+            s.pipe_reply(Some(1), b"", b"Error: boom\n", &ctx),
+            Some(("p1".into(), "error: Error: boom".into()))
+        );
+        let ctx = State::pipe_ctx(CMD_LIST_WORKTREES, Some("p2"));
+        let json = br#"{"version":1,"worktrees":[]}"#;
+        assert_eq!(
+            s.pipe_reply(Some(0), json, b"", &ctx),
+            Some(("p2".into(), r#"{"version":1,"worktrees":[]}"#.into()))
+        );
     }
 
-    fn make_tab(name: &str, active: bool) -> TabInfo {
-        TabInfo {
-            position: 0,
-            name: name.to_string(),
-            active,
-            panes_to_hide: 0,
-            is_fullscreen_active: false,
-            is_sync_panes_active: false,
-            are_floating_panes_visible: false,
-            other_focused_clients: vec![],
-            active_swap_layout_name: None,
-            is_swap_layout_dirty: false,
-            viewport_rows: 0,
-            viewport_columns: 0,
-            display_area_rows: 0,
-            display_area_columns: 0,
-            selectable_tiled_panes_count: 0,
-            selectable_floating_panes_count: 0,
+    fn make_pane(command: Option<&str>, exited: bool, exit_status: Option<i32>) -> PaneInfo {
+        PaneInfo {
+            terminal_command: command.map(str::to_string),
+            exited,
+            exit_status,
+            ..Default::default()
+        }
+    }
+
+    fn state_with_panes(panes: Vec<PaneInfo>) -> State {
+        let mut tab = make_tab("feature/cool", false);
+        tab.position = 2;
+        State {
+            tabs: vec![make_tab("main", true), tab],
+            panes: PaneManifest { panes: [(2, panes)].into_iter().collect() },
+            ..Default::default()
         }
     }
 
     #[test]
-    fn tab_name_for_branch_replaces_slashes() {
-        assert_eq!(State::tab_name_for_branch("feature/cool"), "feature-cool");
-        assert_eq!(State::tab_name_for_branch("a/b/c"), "a-b-c");
-        assert_eq!(State::tab_name_for_branch("fix-bug"), "fix-bug");
+    fn agent_status_no_tab() {
+        let s = state_with_panes(vec![]);
+        assert_eq!(s.agent_status("fix-bug"), AgentStatus::NoTab);
     }
 
     #[test]
-    fn has_tab_for_branch_found() {
-        let mut s = State::default();
-        s.tabs = vec![make_tab("feature-cool", false), make_tab("fix-bug", false)];
-        assert!(s.has_tab_for_branch("feature/cool"));
-        assert!(s.has_tab_for_branch("fix-bug"));
+    fn agent_status_running() {
+        let s = state_with_panes(vec![
+            make_pane(Some("bash -c exec claude"), false, None),
+            make_pane(Some("lazygit"), false, None),
+        ]);
+        assert_eq!(s.agent_status("feature/cool"), AgentStatus::Running);
     }
 
     #[test]
-    fn has_tab_for_branch_not_found() {
-        let mut s = State::default();
-        s.tabs = vec![make_tab("main", false)];
-        assert!(!s.has_tab_for_branch("nonexistent"));
+    fn agent_status_exited_with_code() {
+        let s = state_with_panes(vec![
+            make_pane(Some("bash -c exec claude"), true, Some(1)),
+            make_pane(Some("lazygit"), false, None),
+        ]);
+        assert_eq!(s.agent_status("feature/cool"), AgentStatus::Exited(Some(1)));
     }
 
     #[test]
-    fn has_tab_for_branch_empty_tabs() {
-        let s = State::default();
-        assert!(!s.has_tab_for_branch("anything"));
+    fn agent_status_ignores_plugins_and_shells() {
+        let mut plugin = make_pane(None, false, None);
+        plugin.is_plugin = true;
+        let s = state_with_panes(vec![plugin, make_pane(None, false, None), make_pane(Some("lazygit"), true, Some(0))]);
+        assert_eq!(s.agent_status("feature/cool"), AgentStatus::Unknown);
+    }
+
+    #[test]
+    fn agent_status_any_running_tab_wins() {
+        let mut s = state_with_panes(vec![make_pane(Some("bash -c exec claude"), true, Some(0))]);
+        let mut second = make_tab("feature/cool", false);
+        second.position = 3;
+        s.tabs.push(second);
+        s.panes.panes.insert(3, vec![make_pane(Some("bash -c exec claude"), false, None)]);
+        assert_eq!(s.agent_status("feature/cool"), AgentStatus::Running);
+    }
+
+    #[test]
+    fn similar_branches_get_distinct_tabs() {
+        let s = State { tabs: vec![make_tab("feature-a", false)], ..Default::default() };
+        assert!(s.has_tab_for_branch("feature-a"));
+        assert!(!s.has_tab_for_branch("feature/a"));
+    }
+
+    #[test]
+    fn renamed_tab_found_by_pane_marker() {
+        let mut s = state_with_panes(vec![make_pane(
+            Some("bash -c exec claude zelligent-worktree /wt/feature/cool"),
+            false,
+            None,
+        )]);
+        s.tabs[1].name = "my review".into();
+        s.worktrees = vec![Worktree { branch: "feature/cool".into(), path: "/wt/feature/cool".into(), ..Default::default() }];
+        let tabs = s.tabs_for_branch("feature/cool");
+        assert_eq!(tabs.len(), 1);
+        assert_eq!(tabs[0].name, "my review");
+        assert_eq!(s.agent_status("feature/cool"), AgentStatus::Running);
+    }
+
+    #[test]
+    fn marked_tab_not_claimed_by_name() {
+        // A tab renamed to another branch's name still belongs to its worktree.
+        let mut s = state_with_panes(vec![make_pane(
+            Some("bash -c exec claude zelligent-worktree /wt/other"),
+            false,
+            None,
+        )]);
+        s.worktrees = vec![
+            Worktree { branch: "feature/cool".into(), path: "/wt/feature/cool".into(), ..Default::default() },
+            Worktree { branch: "other".into(), path: "/wt/other".into(), ..Default::default() },
+        ];
+        assert!(!s.has_tab_for_branch("feature/cool"));
+        assert!(s.has_tab_for_branch("other"));
+    }
+
+    #[test]
+    fn agent_sort_finds_renamed_tabs() {
+        let mut s = state_with_panes(vec![make_pane(Some("bash -c exec claude zelligent-worktree /wt/b"), false, None)]);
+        s.tabs[1].name = "my review".into();
+        s.sort_order = SortOrder::Agent;
+        s.worktrees = ["a", "b", "c"]
+            .iter()
+            .map(|b| Worktree { branch: b.to_string(), path: format!("/wt/{b}"), ..Default::default() })
+            .collect();
+        s.listed_order = vec!["a".into(), "b".into(), "c".into()];
+        s.sort_worktrees();
+        assert_eq!(names(&s), ["b", "a", "c"]);
     }
 
     #[test]
     fn list_worktrees_clamps_selected_index() {
-        let mut s = State::default();
-        s.selected_index = 5;
-        s.handle_list_worktrees(Some(0), b"feat-a\n", b"");
+        let mut s = State { selected_index: 5, ..Default::default() };
+        s.handle_list_worktrees(Some(0), br#"{"version":1,"worktrees":[{"branch":"feat-a"}]}"#, b"");
         assert_eq!(s.selected_index, 0);
     }
 
@@ -979,6 +4479,15 @@ mod tests {
         assert_eq!(s.worktrees.len(), original_len);
     }
 
+    #[test]
+    fn list_worktrees_schema_mismatch_preserves_worktrees() {
+        let mut s = state_with_worktrees();
+        s.handle_list_worktrees(Some(0), br#"{"version":2,"worktrees":[]}"#, b"");
+        assert_eq!(s.worktrees.len(), 3);
+        assert!(s.status_is_error);
+        assert!(s.status_message.contains("schema v2"));
+    }
+
     #[test]
     fn git_branches_error_sets_status() {
         let mut s = State::default();
@@ -0,0 +1,188 @@
+//! Sort orders and branch-prefix groups for the worktree list.
+//!
+//! Sorting is stable and starts from `git worktree list` order, so ties (and
+//! [`SortOrder::Git`]) keep the order git reports.
+
+use std::cmp::Reverse;
+use std::ops::Range;
+
+use crate::{AgentStatus, Worktree};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SortOrder {
+    /// As `git worktree list` reports them.
+    #[default]
+    Git,
+    Name,
+    /// Most recent commit first.
+    Recent,
+    /// Newest worktree first.
+    Created,
+    /// Most uncommitted files first.
+    Dirty,
+    /// Running agents first, then finished ones, then worktrees without a tab.
+    Agent,
+}
+
+const ORDERS: [SortOrder; 6] =
+    [SortOrder::Git, SortOrder::Name, SortOrder::Recent, SortOrder::Created, SortOrder::Dirty, SortOrder::Agent];
+
+impl SortOrder {
+    /// Parse a `sort` configuration value (the names [`Self::label`] gives).
+    pub fn parse(s: &str) -> Option<Self> {
+        ORDERS.into_iter().find(|order| order.label() == s)
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            SortOrder::Git => "git",
+            SortOrder::Name => "name",
+            SortOrder::Recent => "recent",
+            SortOrder::Created => "created",
+            SortOrder::Dirty => "dirty",
+            SortOrder::Agent => "agent",
+        }
+    }
+
+    /// The order `s` switches to.
+    pub fn next(self) -> Self {
+        let index = ORDERS.iter().position(|&order| order == self).unwrap_or_default();
+        ORDERS[(index + 1) % ORDERS.len()]
+    }
+}
+
+/// Group of a worktree: its branch up to and including the first `/`
+/// (`feature/login` → `feature/`). Branches without one, and detached
+/// worktrees, share the unnamed group `""`.
+pub fn group_of(wt: &Worktree) -> &str {
+    if wt.detached {
+        return "";
+    }
+    wt.branch.find('/').map_or("", |i| &wt.branch[..=i])
+}
+
+/// Sort `worktrees`, given each one's position in git order and its agent
+/// status. With `grouped`, worktrees are gathered by [`group_of`] first:
+/// named groups alphabetically, then the unnamed one.
+pub fn sort(
+    worktrees: &mut [Worktree],
+    order: SortOrder,
+    grouped: bool,
+    position: impl Fn(&Worktree) -> usize,
+    agent: impl Fn(&Worktree) -> AgentStatus,
+) {
+    worktrees.sort_by_cached_key(|wt| position(wt));
+    match order {
+        SortOrder::Git => {}
+        SortOrder::Name => worktrees.sort_by_cached_key(Worktree::name),
+        SortOrder::Recent => worktrees.sort_by_key(|wt| Reverse(wt.last_commit_time)),
+        SortOrder::Created => worktrees.sort_by_key(|wt| Reverse(wt.created_time)),
+        SortOrder::Dirty => worktrees.sort_by_key(|wt| Reverse(wt.dirty)),
+        SortOrder::Agent => worktrees.sort_by_cached_key(|wt| match agent(wt) {
+            AgentStatus::Running => 0,
+            AgentStatus::Exited(_) => 1,
+            AgentStatus::Unknown => 2,
+            AgentStatus::NoTab => 3,
+        }),
+    }
+    if grouped {
+        worktrees.sort_by(|a, b| {
+            let (a, b) = (group_of(a), group_of(b));
+            (a.is_empty(), a).cmp(&(b.is_empty(), b))
+        });
+    }
+}
+
+/// Runs of consecutive worktrees in the same group, as left by a grouped
+/// [`sort`].
+pub fn groups(worktrees: &[Worktree]) -> Vec<(&str, Range<usize>)> {
+    let mut groups: Vec<(&str, Range<usize>)> = Vec::new();
+    for (i, wt) in worktrees.iter().enumerate() {
+        match groups.last_mut() {
+            Some((name, range)) if *name == group_of(wt) => range.end = i + 1,
+            _ => groups.push((group_of(wt), i..i + 1)),
+        }
+    }
+    groups
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wt(branch: &str) -> Worktree {
+        Worktree { branch: branch.to_string(), ..Default::default() }
+    }
+
+    fn names(worktrees: &[Worktree]) -> Vec<String> {
+        worktrees.iter().map(Worktree::name).collect()
+    }
+
+    fn position_in<'a>(listed: &'a [&'a str]) -> impl Fn(&Worktree) -> usize + 'a {
+        move |wt| listed.iter().position(|name| *name == wt.branch).unwrap_or(usize::MAX)
+    }
+
+    #[test]
+    fn parse_and_cycle() {
+        assert_eq!(SortOrder::parse("recent"), Some(SortOrder::Recent));
+        assert_eq!(SortOrder::parse("bogus"), None);
+        let mut order = SortOrder::Git;
+        for _ in 0..ORDERS.len() {
+            order = order.next();
+            assert_eq!(SortOrder::parse(order.label()), Some(order));
+        }
+        assert_eq!(order, SortOrder::Git);
+    }
+
+    #[test]
+    fn group_is_first_path_component() {
+        assert_eq!(group_of(&wt("feature/login/form")), "feature/");
+        assert_eq!(group_of(&wt("hotfix")), "");
+        assert_eq!(group_of(&Worktree { branch: "fix/x".into(), detached: true, ..Default::default() }), "");
+    }
+
+    #[test]
+    fn sort_orders() {
+        let listed = ["b", "c", "a"];
+        let mut list = vec![
+            Worktree { last_commit_time: 100, created_time: 300, dirty: 0, ..wt("a") },
+            Worktree { last_commit_time: 300, created_time: 100, dirty: 2, ..wt("b") },
+            Worktree { last_commit_time: 200, created_time: 200, dirty: 0, ..wt("c") },
+        ];
+        let no_tab = |_: &Worktree| AgentStatus::NoTab;
+        sort(&mut list, SortOrder::Git, false, position_in(&listed), no_tab);
+        assert_eq!(names(&list), ["b", "c", "a"]);
+        sort(&mut list, SortOrder::Name, false, position_in(&listed), no_tab);
+        assert_eq!(names(&list), ["a", "b", "c"]);
+        sort(&mut list, SortOrder::Recent, false, position_in(&listed), no_tab);
+        assert_eq!(names(&list), ["b", "c", "a"]);
+        sort(&mut list, SortOrder::Created, false, position_in(&listed), no_tab);
+        assert_eq!(names(&list), ["a", "c", "b"]);
+        // Ties keep git order.
+        sort(&mut list, SortOrder::Dirty, false, position_in(&listed), no_tab);
+        assert_eq!(names(&list), ["b", "c", "a"]);
+    }
+
+    #[test]
+    fn sort_by_agent_status() {
+        let listed = ["idle", "done", "busy"];
+        let mut list = vec![wt("idle"), wt("done"), wt("busy")];
+        let agent = |wt: &Worktree| match wt.branch.as_str() {
+            "busy" => AgentStatus::Running,
+            "done" => AgentStatus::Exited(Some(0)),
+            _ => AgentStatus::NoTab,
+        };
+        sort(&mut list, SortOrder::Agent, false, position_in(&listed), agent);
+        assert_eq!(names(&list), ["busy", "done", "idle"]);
+    }
+
+    #[test]
+    fn grouped_sort_and_runs() {
+        let listed = ["main-fix", "fix/b", "feature/x", "fix/a"];
+        let mut list: Vec<Worktree> = listed.iter().map(|b| wt(b)).collect();
+        sort(&mut list, SortOrder::Name, true, position_in(&listed), |_| AgentStatus::NoTab);
+        assert_eq!(names(&list), ["feature/x", "fix/a", "fix/b", "main-fix"]);
+        assert_eq!(groups(&list), vec![("feature/", 0..1), ("fix/", 1..3), ("", 3..4)]);
+        assert!(groups(&[]).is_empty());
+    }
+}
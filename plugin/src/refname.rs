@@ -0,0 +1,132 @@
+//! Branch name checks for the new-branch input.
+//!
+//! [`validate`] follows `git check-ref-format --branch`, so a name it accepts
+//! won't fail later inside `git worktree add`. [`slugify`] turns free text
+//! such as a task title into a name it accepts.
+
+/// Characters git never allows in a ref name.
+const FORBIDDEN: &[char] = &[' ', '~', '^', ':', '?', '*', '[', '\\'];
+
+/// Why `name` is not a valid branch name, if it isn't.
+pub fn validate(name: &str) -> Result<(), String> {
+    if name.is_empty() {
+        return Err("name is empty".to_string());
+    }
+    if name == "@" || name == "HEAD" {
+        return Err(format!("'{name}' is reserved"));
+    }
+    if name.starts_with('-') {
+        return Err("can't start with '-'".to_string());
+    }
+    if let Some(c) = name.chars().find(|c| c.is_ascii_control()) {
+        return Err(format!("contains control character {c:?}"));
+    }
+    if let Some(c) = name.chars().find(|c| FORBIDDEN.contains(c)) {
+        return Err(match c {
+            ' ' => "contains a space".to_string(),
+            c => format!("contains '{c}'"),
+        });
+    }
+    for bad in ["..", "@{", "//"] {
+        if name.contains(bad) {
+            return Err(format!("contains '{bad}'"));
+        }
+    }
+    if name.starts_with('/') || name.ends_with('/') {
+        return Err("can't start or end with '/'".to_string());
+    }
+    if name.ends_with('.') {
+        return Err("can't end with '.'".to_string());
+    }
+    for part in name.split('/') {
+        if part.starts_with('.') {
+            return Err(format!("'{part}' starts with '.'"));
+        }
+        if part.ends_with(".lock") {
+            return Err(format!("'{part}' ends with '.lock'"));
+        }
+    }
+    Ok(())
+}
+
+/// Lower-case `text` and join its words with `-`, keeping `/` as a path
+/// separator: "Fix login timeout bug" → `fix-login-timeout-bug`. `prefix`
+/// (e.g. `agent/`) is prepended unless already there. Returns an empty
+/// string when nothing usable is left.
+pub fn slugify(text: &str, prefix: &str) -> String {
+    let parts: Vec<String> = text
+        .split('/')
+        .map(|part| {
+            let mut slug = String::new();
+            for c in part.chars().flat_map(char::to_lowercase) {
+                if c.is_alphanumeric() || c == '_' {
+                    slug.push(c);
+                } else if !slug.is_empty() && !slug.ends_with('-') {
+                    slug.push('-');
+                }
+            }
+            slug.trim_end_matches('-').to_string()
+        })
+        .filter(|part| !part.is_empty())
+        .collect();
+    if parts.is_empty() {
+        return String::new();
+    }
+    let slug = parts.join("/");
+    if prefix.is_empty() || slug.starts_with(prefix) {
+        slug
+    } else {
+        format!("{prefix}{slug}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn valid_names() {
+        for name in ["main", "feature/login", "fix-1.2", "agent/a_b", "v1.0-rc", "ü/ñ"] {
+            assert_eq!(validate(name), Ok(()), "{name}");
+        }
+    }
+
+    #[test]
+    fn invalid_names_explain_why() {
+        assert_eq!(validate("fix login"), Err("contains a space".into()));
+        assert_eq!(validate("a..b"), Err("contains '..'".into()));
+        assert_eq!(validate("a~1"), Err("contains '~'".into()));
+        assert_eq!(validate("feat.lock"), Err("'feat.lock' ends with '.lock'".into()));
+        assert_eq!(validate("a/.hidden"), Err("'.hidden' starts with '.'".into()));
+        assert_eq!(validate("a//b"), Err("contains '//'".into()));
+        assert_eq!(validate("/a"), Err("can't start or end with '/'".into()));
+        assert_eq!(validate("a."), Err("can't end with '.'".into()));
+        assert_eq!(validate("a@{1}"), Err("contains '@{'".into()));
+        assert_eq!(validate("-x"), Err("can't start with '-'".into()));
+        assert_eq!(validate("@"), Err("'@' is reserved".into()));
+        assert_eq!(validate("a\tb"), Err("contains control character '\\t'".into()));
+    }
+
+    #[test]
+    fn slugify_task_titles() {
+        assert_eq!(slugify("Fix login timeout bug", ""), "fix-login-timeout-bug");
+        assert_eq!(slugify("  Add: retries (v2)! ", ""), "add-retries-v2");
+        assert_eq!(slugify("feature / New  Thing..", ""), "feature/new-thing");
+        assert_eq!(slugify("...", ""), "");
+    }
+
+    #[test]
+    fn slugify_adds_prefix_once() {
+        assert_eq!(slugify("Fix login", "agent/"), "agent/fix-login");
+        assert_eq!(slugify("agent/fix login", "agent/"), "agent/fix-login");
+        assert_eq!(slugify("!!!", "agent/"), "");
+    }
+
+    #[test]
+    fn slugs_are_valid() {
+        for text in ["Fix login timeout bug", "a..b~c^d", "über straße", ".hidden/x.lock", "-x-"] {
+            let slug = slugify(text, "agent/");
+            assert_eq!(validate(&slug), Ok(()), "{text} -> {slug}");
+        }
+    }
+}
@@ -8,7 +8,9 @@ pub const RED: &str = "\x1b[31m";
 pub const CYAN: &str = "\x1b[36m";
 pub const YELLOW: &str = "\x1b[33m";
 
-use crate::{Mode, Worktree};
+use crate::agent::AgentProfile;
+use crate::input::TextInput;
+use crate::{fuzzy, AgentStatus, Branch, FileChange, Mode, Preview, Worktree};
 
 pub fn render_header(repo_name: &str, cols: usize) {
     let title = format!(" zelligent: {} ", repo_name);
@@ -16,12 +18,135 @@ pub fn render_header(repo_name: &str, cols: usize) {
     println!("{BOLD}{CYAN}{title}{}{RESET}", "─".repeat(pad));
 }
 
-pub fn render_worktree_list(worktrees: &[Worktree], selected: usize, rows: usize) {
-    if worktrees.is_empty() {
-        println!();
-        println!("  {DIM}No worktrees found.{RESET}");
-        println!("  {DIM}Press {RESET}n{DIM} to pick a branch or {RESET}i{DIM} to type one.{RESET}");
+/// Compact relative age ("now", "5m", "3h", "2d", "6w", "1y") of a unix timestamp.
+pub fn format_age(timestamp: u64, now: u64) -> String {
+    if timestamp == 0 {
+        return "-".to_string();
+    }
+    let secs = now.saturating_sub(timestamp);
+    match secs {
+        0..=59 => "now".to_string(),
+        60..=3_599 => format!("{}m", secs / 60),
+        3_600..=86_399 => format!("{}h", secs / 3_600),
+        86_400..=1_209_599 => format!("{}d", secs / 86_400),
+        1_209_600..=31_535_999 => format!("{}w", secs / 604_800),
+        _ => format!("{}y", secs / 31_536_000),
+    }
+}
+
+/// Truncate `s` to at most `width` characters, marking the cut with `…`.
+fn truncate(s: &str, width: usize) -> String {
+    if s.chars().count() <= width {
+        return s.to_string();
+    }
+    if width == 0 {
+        return String::new();
+    }
+    let mut out: String = s.chars().take(width - 1).collect();
+    out.push('…');
+    out
+}
+
+/// The input's text with the character under the cursor (a space at the end
+/// of a line) shown inverted.
+fn with_cursor(input: &TextInput) -> String {
+    let (before, after) = input.split();
+    let mut rest = after.chars();
+    match rest.next() {
+        Some('\n') => format!("{before}{INVERSE} {RESET}\n{}", rest.as_str()),
+        Some(c) => format!("{before}{INVERSE}{c}{RESET}{}", rest.as_str()),
+        None => format!("{before}{INVERSE} {RESET}"),
+    }
+}
+
+/// Badge text and color for an agent status column.
+fn agent_badge(status: AgentStatus) -> (String, &'static str) {
+    match status {
+        AgentStatus::NoTab => ("○ no tab".to_string(), DIM),
+        AgentStatus::Unknown => ("? tab".to_string(), DIM),
+        AgentStatus::Running => ("▶ running".to_string(), GREEN),
+        AgentStatus::Exited(Some(0)) => ("✓ done".to_string(), GREEN),
+        AgentStatus::Exited(Some(code)) => (format!("✗ exit {code}"), RED),
+        AgentStatus::Exited(None) => ("■ exited".to_string(), YELLOW),
+    }
+}
+
+/// Panes at least this wide show the preview beside the list; narrower
+/// ones stack it below.
+const SPLIT_MIN_COLS: usize = 100;
+
+/// Per-worktree state that is not part of the git metadata.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RowState {
+    pub agent: AgentStatus,
+    pub marked: bool,
+    /// Unmerged, and no commits for a while.
+    pub stale: bool,
+}
+
+/// One line of the worktree list.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ListRow {
+    /// Header of a branch-prefix group (`""` for branches without a prefix)
+    /// with the number of worktrees in it.
+    Group { name: String, len: usize, collapsed: bool },
+    /// Index into the worktrees, and their state.
+    Worktree(usize, RowState),
+}
+
+/// `list` holds the rows to show and `selected` the one under the cursor.
+/// With a preview (and the base branch it diffs against), the pane is split.
+pub fn render_worktree_list(
+    worktrees: &[Worktree],
+    list: &[ListRow],
+    selected: usize,
+    preview: Option<(&Preview, &str)>,
+    rows: usize,
+    cols: usize,
+    now: u64,
+) {
+    let Some((preview, base)) = preview.filter(|_| !worktrees.is_empty()) else {
+        for line in worktree_lines(worktrees, list, selected, rows, cols, now) {
+            println!("{line}");
+        }
         return;
+    };
+
+    if cols >= SPLIT_MIN_COLS {
+        let list_width = cols * 11 / 20;
+        let left = worktree_lines(worktrees, list, selected, rows, list_width, now);
+        let right = preview_lines(preview, base, cols - list_width - 3, rows.saturating_sub(5));
+        for i in 0..left.len().max(right.len()) {
+            let l = left.get(i).map(String::as_str).unwrap_or_default();
+            let r = right.get(i).map(String::as_str).unwrap_or_default();
+            let pad = list_width.saturating_sub(visible_width(l));
+            println!("{l}{}{DIM}│{RESET} {r}", " ".repeat(pad));
+        }
+    } else {
+        // Stacked: the list takes what it needs, up to half the rows.
+        let list_rows = (list.len() + 5).min((rows / 2).max(6));
+        for line in worktree_lines(worktrees, list, selected, list_rows, cols, now) {
+            println!("{line}");
+        }
+        for line in preview_lines(preview, base, cols.saturating_sub(2), rows.saturating_sub(list_rows + 5)) {
+            println!("  {line}");
+        }
+    }
+}
+
+fn worktree_lines(
+    worktrees: &[Worktree],
+    list: &[ListRow],
+    selected: usize,
+    rows: usize,
+    cols: usize,
+    now: u64,
+) -> Vec<String> {
+    let mut lines = vec![String::new()];
+    if worktrees.is_empty() {
+        lines.push(format!("  {DIM}No worktrees found.{RESET}"));
+        lines.push(format!("  {DIM}Press {RESET}n{DIM} to pick a branch or {RESET}i{DIM} to type one.{RESET}"));
+        return lines;
     }
 
     let max_visible = rows.saturating_sub(5).max(1); // header + footer + margins
@@ -31,51 +156,351 @@ pub fn render_worktree_list(worktrees: &[Worktree], selected: usize, rows: usize
         0
     };
 
-    println!();
-    for (idx, wt) in worktrees.iter().enumerate().skip(start).take(max_visible) {
-        let cursor = if idx == selected { INVERSE } else { "" };
-        let branch_display = &wt.branch;
-        println!("  {cursor} {branch_display} {RESET}");
+    // Columns: branch | agent | dirty | ahead/behind | age | subject
+    let branch_width = worktrees
+        .iter()
+        .map(|wt| worktree_label(wt).chars().count())
+        .max()
+        .unwrap_or(0)
+        .min(cols / 3)
+        .max(6);
+
+    for (idx, row) in list.iter().enumerate().skip(start).take(max_visible) {
+        let selected = idx == selected;
+        let cursor = if selected { INVERSE } else { "" };
+        let (wt, state) = match row {
+            ListRow::Worktree(i, state) => (&worktrees[*i], *state),
+            ListRow::Group { name, len, collapsed } => {
+                lines.push(group_line(name, *len, *collapsed, cursor));
+                continue;
+            }
+        };
+        let mark = if state.marked { "*" } else { " " };
+        let branch = format!("{:<branch_width$}", truncate(&worktree_label(wt), branch_width));
+        let dirty = if wt.dirty > 0 { format!("●{}", wt.dirty) } else { String::new() };
+        let sync = match (wt.ahead, wt.behind) {
+            (0, 0) => String::new(),
+            (a, 0) => format!("↑{a}"),
+            (0, b) => format!("↓{b}"),
+            (a, b) => format!("↑{a}↓{b}"),
+        };
+        let age = format_age(wt.last_commit_time, now);
+        let (badge, badge_color) = agent_badge(state.agent);
+        // 2 indent + cursor padding + branch + 4 fixed columns with separators
+        let used = 2 + 2 + branch_width + 1 + 10 + 1 + 5 + 1 + 9 + 1 + 4 + 2;
+        let (tag, tag_color) = match (wt.merged, state.stale) {
+            (true, _) => ("merged ", GREEN),
+            (false, true) => ("stale ", YELLOW),
+            _ => ("", ""),
+        };
+        let subject = truncate(&wt.last_commit_subject, cols.saturating_sub(used + tag.len()));
+
+        // Colors are suppressed on the selected row so the inverse bar stays intact.
+        let (yellow, cyan, dim, badge_color, tag_color, reset) = if selected {
+            ("", "", "", "", "", "")
+        } else {
+            (YELLOW, CYAN, DIM, badge_color, tag_color, RESET)
+        };
+        lines.push(format!(
+            "  {cursor}{mark}{branch} {badge_color}{badge:<10}{reset} {yellow}{dirty:<5}{reset} {cyan}{sync:<9}{reset} \
+             {dim}{age:>4}{reset}  {tag_color}{tag}{reset}{dim}{subject}{reset} {RESET}"
+        ));
     }
+    lines
 }
 
-pub fn render_branch_list(branches: &[String], selected: usize, rows: usize) {
-    if branches.is_empty() {
-        println!();
-        println!("  {DIM}No branches found.{RESET}");
-        return;
+/// `▾ feature/ (3)`, or `▸` when collapsed. Branches without a prefix are
+/// listed under `other`.
+fn group_line(name: &str, len: usize, collapsed: bool, cursor: &str) -> String {
+    let arrow = if collapsed { "▸" } else { "▾" };
+    let name = if name.is_empty() { "other" } else { name };
+    let (bold, dim) = if cursor.is_empty() { (BOLD, DIM) } else { ("", "") };
+    format!("  {cursor}{arrow}{bold}{name}{RESET}{cursor} {dim}({len}){RESET}{cursor} {RESET}")
+}
+
+/// Name shown in the list: worktrees zelligent did not create are marked `↗`.
+fn worktree_label(wt: &Worktree) -> String {
+    if wt.managed {
+        wt.name()
+    } else {
+        format!("↗ {}", wt.name())
+    }
+}
+
+/// Preview text for the highlighted worktree, at most `height` lines of
+/// `width` visible characters.
+fn preview_lines(preview: &Preview, base: &str, width: usize, height: usize) -> Vec<String> {
+    let mut lines = vec![String::new()];
+    lines.push(format!("{BOLD}Changes vs {}{RESET}", truncate(base, width.saturating_sub(11))));
+    match &preview.changes {
+        None => lines.push(format!("{DIM}loading...{RESET}")),
+        Some(Err(err)) => lines.push(format!("{RED}{}{RESET}", truncate(err, width))),
+        Some(Ok(changes)) if changes.is_empty() => lines.push(format!("{DIM}no changes{RESET}")),
+        Some(Ok(changes)) => {
+            let added: usize = changes.iter().filter_map(|c| c.added).sum();
+            let deleted: usize = changes.iter().filter_map(|c| c.deleted).sum();
+            let count_width = changes
+                .iter()
+                .map(|c| change_counts(c).chars().count())
+                .max()
+                .unwrap_or(0);
+            // Keep room for the headings, the summary and the commits.
+            let commit_lines = match &preview.commits {
+                Some(Ok(commits)) => commits.len().max(1),
+                _ => 1,
+            };
+            let avail = height.saturating_sub(5 + commit_lines);
+            let room = if changes.len() <= avail { changes.len() } else { avail.saturating_sub(1).max(1) };
+            for change in changes.iter().take(room) {
+                let counts = change_counts(change);
+                let pad = count_width - counts.chars().count();
+                let path = truncate(&change.path, width.saturating_sub(count_width + 1));
+                lines.push(format!("{}{} {path}", " ".repeat(pad), color_counts(change)));
+            }
+            if changes.len() > room {
+                lines.push(format!("{DIM}… {} more{RESET}", changes.len() - room));
+            }
+            lines.push(format!(
+                "{DIM}{} file{} {GREEN}+{added}{RESET} {RED}-{deleted}{RESET}",
+                changes.len(),
+                if changes.len() == 1 { "" } else { "s" }
+            ));
+        }
+    }
+    lines.push(String::new());
+    lines.push(format!("{BOLD}Recent commits{RESET}"));
+    match &preview.commits {
+        None => lines.push(format!("{DIM}loading...{RESET}")),
+        Some(Err(err)) => lines.push(format!("{RED}{}{RESET}", truncate(err, width))),
+        Some(Ok(commits)) if commits.is_empty() => lines.push(format!("{DIM}no commits{RESET}")),
+        Some(Ok(commits)) => {
+            for commit in commits {
+                lines.push(match commit.split_once(' ') {
+                    Some((sha, rest)) => format!(
+                        "{YELLOW}{sha}{RESET} {}",
+                        truncate(rest, width.saturating_sub(sha.chars().count() + 1))
+                    ),
+                    None => truncate(commit, width),
+                });
+            }
+        }
+    }
+    lines.truncate(height.max(1));
+    lines
+}
+
+/// `+3 -1` (or `bin` for binary files) without colors, for width math.
+fn change_counts(change: &FileChange) -> String {
+    match (change.added, change.deleted) {
+        (Some(a), Some(d)) => format!("+{a} -{d}"),
+        _ => "bin".to_string(),
+    }
+}
+
+fn color_counts(change: &FileChange) -> String {
+    match (change.added, change.deleted) {
+        (Some(a), Some(d)) => format!("{GREEN}+{a}{RESET} {RED}-{d}{RESET}"),
+        _ => format!("{DIM}bin{RESET}"),
     }
+}
+
+/// Number of characters `s` occupies on screen, ignoring ANSI escapes.
+fn visible_width(s: &str) -> usize {
+    let mut width = 0;
+    let mut in_escape = false;
+    for c in s.chars() {
+        match (in_escape, c) {
+            (false, '\x1b') => in_escape = true,
+            (true, 'm') => in_escape = false,
+            (true, _) => {}
+            (false, _) => width += 1,
+        }
+    }
+    width
+}
+
+/// What the picker knows about a branch besides its git metadata.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct BranchState {
+    /// Has a worktree (zelligent's or not).
+    pub worktree: bool,
+    /// Has an open tab in this session.
+    pub tab: bool,
+    /// The repo's base branch.
+    pub base: bool,
+}
 
-    let title = format!("  {BOLD}Select a branch:{RESET}");
+/// `states` holds the state of each entry in `branches`.
+pub fn render_branch_list(
+    title: &str,
+    branches: &[Branch],
+    states: &[BranchState],
+    query: &str,
+    selected: usize,
+    rows: usize,
+    now: u64,
+) {
     println!();
-    println!("{title}");
+    println!("  {BOLD}{title}{RESET}");
     println!();
+    println!("  / {query}{INVERSE} {RESET}");
+    println!();
+
+    if branches.is_empty() {
+        println!("  {DIM}No matching branches.{RESET}");
+        return;
+    }
 
-    let max_visible = rows.saturating_sub(7).max(1);
+    let max_visible = rows.saturating_sub(9).max(1);
     let start = if selected >= max_visible {
         selected - max_visible + 1
     } else {
         0
     };
+    let name_width = branches
+        .iter()
+        .skip(start)
+        .take(max_visible)
+        .map(|b| b.name.chars().count())
+        .max()
+        .unwrap_or(0);
 
     for (idx, branch) in branches.iter().enumerate().skip(start).take(max_visible) {
+        let selected = idx == selected;
+        let cursor = if selected { INVERSE } else { "" };
+        let (green, cyan, yellow, dim, reset) = if selected {
+            ("", "", "", "", "")
+        } else {
+            (GREEN, CYAN, YELLOW, DIM, RESET)
+        };
+        let state = states.get(idx).copied().unwrap_or_default();
+        let name = highlight_matches(&branch.name, query, cursor);
+        let pad = " ".repeat(name_width - branch.name.chars().count());
+        let age = format_age(branch.last_commit_time, now);
+        let mut tags = String::new();
+        if let Some(remote) = &branch.remote {
+            tags.push_str(&format!("  {dim}{cyan}⇣ {remote}{reset}"));
+        }
+        if state.worktree {
+            tags.push_str(&format!("  {green}worktree{reset}"));
+        }
+        if state.tab {
+            tags.push_str(&format!("  {cyan}tab{reset}"));
+        }
+        if state.base {
+            tags.push_str(&format!("  {dim}base{reset}"));
+        }
+        if branch.main_checkout {
+            tags.push_str(&format!("  {yellow}checked out in repo{reset}"));
+        }
+        println!("  {cursor} {name}{pad} {dim}{age:>4}{reset}{tags} {RESET}");
+    }
+}
+
+/// Render `name` with the characters matched by `query` highlighted. `base`
+/// is the row style to restore after each highlighted character.
+fn highlight_matches(name: &str, query: &str, base: &str) -> String {
+    let positions = match fuzzy::fuzzy_match(query, name) {
+        Some(m) if !m.positions.is_empty() => m.positions,
+        _ => return name.to_string(),
+    };
+    let mut out = String::new();
+    for (i, c) in name.chars().enumerate() {
+        if positions.contains(&i) {
+            out.push_str(&format!("{BOLD}{YELLOW}{c}{RESET}{base}"));
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+pub fn render_agent_list(agents: &[AgentProfile], branch: &str, selected: usize, default: usize) {
+    println!();
+    println!("  {BOLD}Launch which agent in '{branch}'?{RESET}");
+    println!();
+    let name_width = agents.iter().map(|a| a.name.chars().count()).max().unwrap_or(0);
+    for (idx, agent) in agents.iter().enumerate() {
         let cursor = if idx == selected { INVERSE } else { "" };
-        println!("  {cursor} {branch} {RESET}");
+        let key = if idx < 9 { format!("{}", idx + 1) } else { " ".to_string() };
+        let default_marker = if idx == default { " (default)" } else { "" };
+        println!(
+            "  {cursor} {key}  {:<name_width$}  {}{default_marker} {RESET}",
+            agent.name,
+            agent.command_line()
+        );
     }
 }
 
-pub fn render_input(input: &str) {
+/// `hint` is the rest of a branch name completing the input, drawn dimmed
+/// after the cursor. `problem` is why the input isn't a valid branch name,
+/// with the slug Enter would replace it with (empty if there is none).
+pub fn render_input(input: &TextInput, hint: Option<&str>, problem: Option<&(String, String)>, warning: Option<&str>) {
     println!();
     println!("  {BOLD}New branch name:{RESET}");
     println!();
-    println!("  > {input}{INVERSE} {RESET}");
+    let ghost = hint.and_then(|h| h.chars().next().map(|c| (c, &h[c.len_utf8()..])));
+    let line = match ghost {
+        Some((first, rest)) => format!("{}{INVERSE}{DIM}{first}{RESET}{DIM}{rest}{RESET}", input.text()),
+        None => with_cursor(input),
+    };
+    println!("  > {line}");
+    if let Some(warning) = warning {
+        println!();
+        println!("  {YELLOW}⚠ {warning}{RESET}");
+    }
+    if let Some((err, slug)) = problem {
+        println!();
+        println!("  {RED}✗ {err}{RESET}");
+        if !slug.is_empty() {
+            println!("  {DIM}Enter{RESET} use '{slug}'");
+        }
+    }
 }
 
-pub fn render_confirm(branch: &str) {
+pub fn render_prompt_input(branch: &str, prompt: &TextInput) {
     println!();
-    println!("  {YELLOW}{BOLD}Remove worktree for '{branch}'?{RESET}");
+    println!("  {BOLD}Task for the agent on '{branch}'{RESET} {DIM}(optional){RESET}");
     println!();
-    println!("  {DIM}y{RESET} confirm   {DIM}n/Esc{RESET} cancel");
+    for (i, line) in with_cursor(prompt).split('\n').enumerate() {
+        let lead = if i == 0 { ">" } else { " " };
+        println!("  {lead} {line}");
+    }
+}
+
+pub fn render_confirm(branches: &[String]) {
+    println!();
+    match branches {
+        [branch] => println!("  {YELLOW}{BOLD}Remove worktree for '{branch}'?{RESET}"),
+        _ => {
+            println!("  {YELLOW}{BOLD}Remove {} worktrees?{RESET}", branches.len());
+            for branch in branches {
+                println!("    {branch}");
+            }
+        }
+    }
+    println!();
+    println!("  {DIM}y{RESET} keep branch   {DIM}b{RESET} delete branch if merged   {DIM}f{RESET} force-delete branch");
+    println!("  {DIM}n/Esc{RESET} cancel");
+}
+
+pub fn render_confirm_force() {
+    println!();
+    println!("  {DIM}y{RESET} force remove   {DIM}n/Esc{RESET} cancel");
+}
+
+pub fn render_merge_prompt(branches: &[String], base: &str) {
+    println!();
+    match branches {
+        [branch] => println!("  {BOLD}Merge '{branch}' into '{base}':{RESET}"),
+        _ => println!("  {BOLD}Merge {} worktrees into '{base}', one at a time:{RESET}", branches.len()),
+    }
+    println!();
+    println!("  {DIM}m{RESET} merge commit");
+    println!("  {DIM}s{RESET} squash into one commit");
+    println!("  {DIM}r{RESET} rebase onto '{base}', then fast-forward");
+    println!();
+    println!("  {DIM}Esc{RESET} cancel");
 }
 
 pub fn render_footer(mode: &Mode) {
@@ -84,20 +509,38 @@ pub fn render_footer(mode: &Mode) {
         Mode::Loading => {}
         Mode::BrowseWorktrees => {
             println!(
-                "  {DIM}↑/k{RESET} up  {DIM}↓/j{RESET} down  {DIM}Enter{RESET} open  \
-                 {DIM}n{RESET} branch  {DIM}i{RESET} new  {DIM}d{RESET} remove  \
+                "  {DIM}↑/k{RESET} up  {DIM}↓/j{RESET} down  {DIM}Space{RESET} mark  {DIM}a{RESET} all  {DIM}c{RESET} merged  \
+                 {DIM}Enter{RESET} open  \
+                 {DIM}o{RESET} new tab  {DIM}n{RESET} branch  {DIM}i{RESET} new  {DIM}m{RESET} merge  {DIM}d{RESET} remove  \
+                 {DIM}p{RESET} preview  {DIM}s{RESET} sort  {DIM}g{RESET} group  {DIM}Tab{RESET} fold  \
                  {DIM}r{RESET} refresh  {DIM}q{RESET} quit"
             );
         }
         Mode::SelectBranch => {
             println!(
-                "  {DIM}↑/k{RESET} up  {DIM}↓/j{RESET} down  \
+                "  {DIM}type{RESET} filter  {DIM}↑/C-p{RESET} up  {DIM}↓/C-n{RESET} down  \
                  {DIM}Enter{RESET} create  {DIM}Esc{RESET} back"
             );
         }
         Mode::InputBranch => {
-            println!("  {DIM}Enter{RESET} create  {DIM}Esc{RESET} back");
+            println!("  {DIM}Tab{RESET} complete  {DIM}Enter{RESET} continue  {DIM}Esc{RESET} back");
         }
+        Mode::SelectBase => {
+            println!(
+                "  {DIM}type{RESET} filter  {DIM}↑/C-p{RESET} up  {DIM}↓/C-n{RESET} down  \
+                 {DIM}Enter{RESET} use as base  {DIM}Esc{RESET} back"
+            );
+        }
+        Mode::InputPrompt => {
+            println!("  {DIM}Enter{RESET} spawn  {DIM}Alt-Enter/C-j{RESET} new line  {DIM}Esc{RESET} cancel");
+        }
+        Mode::SelectAgent => {
+            println!(
+                "  {DIM}↑/k{RESET} up  {DIM}↓/j{RESET} down  {DIM}1-9{RESET} launch  \
+                 {DIM}Enter{RESET} launch  {DIM}Esc{RESET} cancel"
+            );
+        }
+        Mode::ChooseMerge | Mode::ConfirmForceRemove => {}
         Mode::Confirming => {}
     }
 }
@@ -110,3 +553,131 @@ pub fn render_status(message: &str, is_error: bool) {
     println!();
     println!("  {color}{message}{RESET}");
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use zellij_tile::prelude::{BareKey, KeyWithModifier};
+
+    #[test]
+    fn cursor_is_drawn_in_place() {
+        let mut input = TextInput::from("añb");
+        assert_eq!(with_cursor(&input), format!("añb{INVERSE} {RESET}"));
+        input.handle_key(&KeyWithModifier::new(BareKey::Left));
+        input.handle_key(&KeyWithModifier::new(BareKey::Left));
+        assert_eq!(with_cursor(&input), format!("a{INVERSE}ñ{RESET}b"));
+        let mut multi = TextInput::from("a\nb");
+        multi.handle_key(&KeyWithModifier::new(BareKey::Home));
+        multi.handle_key(&KeyWithModifier::new(BareKey::Right));
+        assert_eq!(with_cursor(&multi), format!("a{INVERSE} {RESET}\nb"));
+    }
+
+    #[test]
+    fn format_age_buckets() {
+        let now = 1_700_000_000;
+        assert_eq!(format_age(0, now), "-");
+        assert_eq!(format_age(now - 30, now), "now");
+        assert_eq!(format_age(now - 5 * 60, now), "5m");
+        assert_eq!(format_age(now - 3 * 3_600, now), "3h");
+        assert_eq!(format_age(now - 2 * 86_400, now), "2d");
+        assert_eq!(format_age(now - 21 * 86_400, now), "3w");
+        assert_eq!(format_age(now - 400 * 86_400, now), "1y");
+        // Clock skew: commit in the future
+        assert_eq!(format_age(now + 100, now), "now");
+    }
+
+    #[test]
+    fn worktree_label_marks_external() {
+        let managed = Worktree { branch: "feat".into(), ..Default::default() };
+        assert_eq!(worktree_label(&managed), "feat");
+        let external = Worktree { managed: false, ..managed };
+        assert_eq!(worktree_label(&external), "↗ feat");
+    }
+
+    #[test]
+    fn agent_badge_per_status() {
+        assert_eq!(agent_badge(AgentStatus::Running).0, "▶ running");
+        assert_eq!(agent_badge(AgentStatus::Exited(Some(0))).0, "✓ done");
+        assert_eq!(agent_badge(AgentStatus::Exited(Some(130))), ("✗ exit 130".to_string(), RED));
+        assert_eq!(agent_badge(AgentStatus::NoTab).0, "○ no tab");
+    }
+
+    #[test]
+    fn visible_width_skips_ansi() {
+        assert_eq!(visible_width(&format!("{GREEN}+3{RESET} {RED}-1{RESET}")), 5);
+        assert_eq!(visible_width("äb"), 2);
+    }
+
+    fn preview(changes: Vec<FileChange>) -> Preview {
+        Preview {
+            branch: "feat".into(),
+            changes: Some(Ok(changes)),
+            commits: Some(Ok(vec!["abc1234 Add thing (2 days ago)".into()])),
+        }
+    }
+
+    #[test]
+    fn preview_lines_summarize_changes() {
+        let p = preview(vec![
+            FileChange { path: "src/main.rs".into(), added: Some(12), deleted: Some(3) },
+            FileChange { path: "logo.png".into(), added: None, deleted: None },
+        ]);
+        let lines = preview_lines(&p, "main", 40, 30);
+        let text: Vec<String> = lines.iter().map(|l| strip(l)).collect();
+        assert_eq!(
+            text,
+            vec!["", "Changes vs main", "+12 -3 src/main.rs", "   bin logo.png", "2 files +12 -3", "", "Recent commits", "abc1234 Add thing (2 days ago)"]
+        );
+    }
+
+    #[test]
+    fn preview_lines_fit_height_and_width() {
+        let changes = (0..50)
+            .map(|i| FileChange { path: format!("some/long/path/file{i}.rs"), added: Some(1), deleted: Some(0) })
+            .collect();
+        let lines = preview_lines(&preview(changes), "main", 20, 15);
+        assert_eq!(lines.len(), 15);
+        assert!(lines.iter().all(|l| visible_width(l) <= 20), "{lines:?}");
+        assert!(lines.iter().any(|l| strip(l) == "… 42 more"));
+    }
+
+    #[test]
+    fn preview_lines_loading_and_errors() {
+        let p = Preview { branch: "x".into(), changes: Some(Err("fatal: bad revision".into())), commits: None };
+        let text: Vec<String> = preview_lines(&p, "main", 40, 30).iter().map(|l| strip(l)).collect();
+        assert!(text.contains(&"fatal: bad revision".to_string()));
+        assert!(text.contains(&"loading...".to_string()));
+    }
+
+    fn strip(s: &str) -> String {
+        let mut out = String::new();
+        let mut in_escape = false;
+        for c in s.chars() {
+            match (in_escape, c) {
+                (false, '\x1b') => in_escape = true,
+                (true, 'm') => in_escape = false,
+                (true, _) => {}
+                (false, c) => out.push(c),
+            }
+        }
+        out
+    }
+
+    #[test]
+    fn highlight_matches_wraps_matched_chars() {
+        assert_eq!(highlight_matches("dev", "", ""), "dev");
+        assert_eq!(highlight_matches("dev", "x", ""), "dev");
+        assert_eq!(
+            highlight_matches("dev", "dv", INVERSE),
+            format!("{BOLD}{YELLOW}d{RESET}{INVERSE}e{BOLD}{YELLOW}v{RESET}{INVERSE}")
+        );
+    }
+
+    #[test]
+    fn truncate_marks_cut() {
+        assert_eq!(truncate("short", 10), "short");
+        assert_eq!(truncate("feature/long-name", 8), "feature…");
+        assert_eq!(truncate("äöüß", 3), "äö…");
+        assert_eq!(truncate("abc", 0), "");
+    }
+}
@@ -24,7 +24,7 @@ check() {
 
 contains() {
   local desc="$1" needle="$2" haystack="$3"
-  if echo "$haystack" | grep -qF "$needle"; then
+  if echo "$haystack" | grep -qF -- "$needle"; then
     pass "$desc"
   else
     fail "$desc (expected to contain: '$needle')"
@@ -33,28 +33,13 @@ contains() {
 
 excludes() {
   local desc="$1" needle="$2" haystack="$3"
-  if echo "$haystack" | grep -qF "$needle"; then
+  if echo "$haystack" | grep -qF -- "$needle"; then
     fail "$desc (must not contain: '$needle')"
   else
     pass "$desc"
   fi
 }
 
-# ── Session name generation ────────────────────────────────────────────────────
-echo "Session name generation:"
-
-check "simple branch" \
-  "mybranch" \
-  "$(bash -c 'BRANCH_NAME=mybranch; echo "${BRANCH_NAME//\//-}"')"
-
-check "feature/ prefix becomes dash" \
-  "feature-fiddlesticks" \
-  "$(bash -c 'BRANCH_NAME=feature/fiddlesticks; echo "${BRANCH_NAME//\//-}"')"
-
-check "nested slashes all replaced" \
-  "a-b-c" \
-  "$(bash -c 'BRANCH_NAME=a/b/c; echo "${BRANCH_NAME//\//-}"')"
-
 # ── Layout file generation (via the script with mock zellij) ──────────────────
 echo "Layout file generation:"
 
@@ -89,6 +74,17 @@ excludes "inside zellij layout: no tab{} wrapper" 'tab name='        "$out"
 contains "new worktree: setup.sh runs as preamble" 'setup.sh'        "$out"
 contains "new worktree: agent starts via exec"     'exec claude'     "$out"
 excludes "new worktree: no invalid KDL \\$ escape" '"\$'             "$out"
+contains "tab named after branch"          "--name test-layout-branch" "$out"
+contains "agent pane carries worktree marker" "\"zelligent-worktree\" \"$EXPECTED_CWD\"" "$out"
+
+# Tab names keep slashes, so feature/x and feature-x never share a tab
+out_slash=$(ZELLIJ=1 ZELLIJ_SESSION_NAME=fake PATH="$MOCK_BIN_LAYOUT:$PATH" \
+  "$SCRIPT" spawn feature/tab-name claude 2>&1)
+git -C "$REPO_ROOT" worktree remove --force \
+  "$HOME/.zelligent/worktrees/$REPO_NAME/feature/tab-name" &>/dev/null || true
+git -C "$REPO_ROOT" branch -D feature/tab-name &>/dev/null || true
+contains "slash branch: tab name is verbatim" "--name feature/tab-name" "$out_slash"
+excludes "slash branch: no dash substitution" "--name feature-tab-name" "$out_slash"
 
 # Test: existing worktree should NOT include setup.sh preamble
 # Re-create the worktree so it already exists, then run the script again
@@ -134,8 +130,403 @@ contains "multi-word cmd: contains full command" 'claude \"pls fix the bug\" --m
 contains "multi-word cmd: contains exec"         'exec claude'   "$out_multi"
 contains "multi-word cmd: contains model flag"   'claude-sonnet-4-6' "$out_multi"
 
+# Test: custom layout.kdl with placeholders and hostile values
+LAYOUT_KDL="$REPO_ROOT/.zelligent/layout.kdl"
+cat > "$LAYOUT_KDL" <<'KDL'
+layout {
+    pane command="bash" cwd="{{cwd}}" name="{{branch}}" {
+        args "-c" "{{setup_preamble}}exec {{agent_cmd}}"
+    }
+}
+KDL
+out_custom=$(ZELLIJ=1 ZELLIJ_SESSION_NAME=fake PATH="$MOCK_BIN_LAYOUT:$PATH" \
+  "$SCRIPT" spawn test-custom-layout 'claude "a|b" & echo \\done' 2>&1)
+git -C "$REPO_ROOT" worktree remove --force \
+  "$HOME/.zelligent/worktrees/$REPO_NAME/test-custom-layout" &>/dev/null || true
+git -C "$REPO_ROOT" branch -D test-custom-layout &>/dev/null || true
+
+contains "custom layout: substitutes branch"           'name="test-custom-layout"'                   "$out_custom"
+contains "custom layout: escapes agent command"        'exec claude \"a|b\" & echo \\\\done"'         "$out_custom"
+contains "custom layout: setup preamble placeholder"   "bash '$REPO_ROOT/.zelligent/setup.sh'"       "$out_custom"
+excludes "custom layout: no placeholders left"         '{{'                                          "$out_custom"
+
+# Without {{setup_preamble}} the user is told setup.sh is skipped
+sed -i.bak 's/{{setup_preamble}}//' "$LAYOUT_KDL"
+out_custom=$(ZELLIJ=1 ZELLIJ_SESSION_NAME=fake PATH="$MOCK_BIN_LAYOUT:$PATH" \
+  "$SCRIPT" spawn test-custom-layout claude 2>&1)
+git -C "$REPO_ROOT" worktree remove --force \
+  "$HOME/.zelligent/worktrees/$REPO_NAME/test-custom-layout" &>/dev/null || true
+git -C "$REPO_ROOT" branch -D test-custom-layout &>/dev/null || true
+rm -f "$LAYOUT_KDL" "$LAYOUT_KDL.bak"
+
+contains "custom layout without preamble: warns" 'has no {{setup_preamble}}' "$out_custom"
+
+# Same checks as layout.rs: unknown placeholders are errors, padded names work
+cat > "$LAYOUT_KDL" <<'KDL'
+layout {
+    pane cwd="{{ cwd }}" name="{{home}}"
+}
+KDL
+out_custom=$(ZELLIJ=1 ZELLIJ_SESSION_NAME=fake PATH="$MOCK_BIN_LAYOUT:$PATH" \
+  "$SCRIPT" spawn test-custom-layout claude 2>&1); code=$?
+git -C "$REPO_ROOT" worktree remove --force \
+  "$HOME/.zelligent/worktrees/$REPO_NAME/test-custom-layout" &>/dev/null || true
+git -C "$REPO_ROOT" branch -D test-custom-layout &>/dev/null || true
+check    "custom layout unknown placeholder: exits 1"  "1" "$code"
+contains "custom layout unknown placeholder: explains" "unknown placeholder {{home}} in layout" "$out_custom"
+excludes "custom layout unknown placeholder: no tab"   "layout {" "$out_custom"
+printf 'layout {\n    pane cwd="{{ cwd }}"\n}\n' > "$LAYOUT_KDL"
+out_custom=$(ZELLIJ=1 ZELLIJ_SESSION_NAME=fake PATH="$MOCK_BIN_LAYOUT:$PATH" \
+  "$SCRIPT" spawn test-custom-layout claude 2>&1)
+git -C "$REPO_ROOT" worktree remove --force \
+  "$HOME/.zelligent/worktrees/$REPO_NAME/test-custom-layout" &>/dev/null || true
+git -C "$REPO_ROOT" branch -D test-custom-layout &>/dev/null || true
+contains "custom layout: padded placeholder" "cwd=\"$HOME/.zelligent/worktrees/$REPO_NAME/test-custom-layout\"" "$out_custom"
+printf 'layout {\n    pane command="less" {\n        args "{{prompt_file}}"\n    }\n}\n' > "$LAYOUT_KDL"
+out_custom=$(ZELLIJ=1 ZELLIJ_SESSION_NAME=fake PATH="$MOCK_BIN_LAYOUT:$PATH" \
+  "$SCRIPT" spawn --prompt "review it" test-custom-layout claude 2>&1); code=$?
+CUSTOM_PROMPT="$(git -C "$HOME/.zelligent/worktrees/$REPO_NAME/test-custom-layout" rev-parse --absolute-git-dir 2>/dev/null)/zelligent-prompt.md"
+git -C "$REPO_ROOT" worktree remove --force \
+  "$HOME/.zelligent/worktrees/$REPO_NAME/test-custom-layout" &>/dev/null || true
+git -C "$REPO_ROOT" branch -D test-custom-layout &>/dev/null || true
+check    "custom layout {{prompt_file}}: exits 0"    "0" "$code"
+contains "custom layout {{prompt_file}}: path"       "args \"$CUSTOM_PROMPT\"" "$out_custom"
+printf 'layout {\n    pane cwd="{{cwd"\n}\n' > "$LAYOUT_KDL"
+out_custom=$(ZELLIJ=1 ZELLIJ_SESSION_NAME=fake PATH="$MOCK_BIN_LAYOUT:$PATH" \
+  "$SCRIPT" spawn test-custom-layout claude 2>&1); code=$?
+git -C "$REPO_ROOT" worktree remove --force \
+  "$HOME/.zelligent/worktrees/$REPO_NAME/test-custom-layout" &>/dev/null || true
+git -C "$REPO_ROOT" branch -D test-custom-layout &>/dev/null || true
+check    "custom layout unterminated placeholder: exits 1" "1" "$code"
+contains "custom layout unterminated placeholder: explains" "unterminated {{ placeholder" "$out_custom"
+printf 'layout {
+    pane cwd="{{cwd}}" {
+}
+' > "$LAYOUT_KDL"
+out_custom=$(ZELLIJ=1 ZELLIJ_SESSION_NAME=fake PATH="$MOCK_BIN_LAYOUT:$PATH" \
+  "$SCRIPT" spawn test-custom-layout claude 2>&1); code=$?
+git -C "$REPO_ROOT" worktree remove --force \
+  "$HOME/.zelligent/worktrees/$REPO_NAME/test-custom-layout" &>/dev/null || true
+git -C "$REPO_ROOT" branch -D test-custom-layout &>/dev/null || true
+rm -f "$LAYOUT_KDL"
+check    "custom layout invalid KDL: exits 1"   "1" "$code"
+contains "custom layout invalid KDL: explains"  "invalid layout" "$out_custom"
+
+# A hostile agent command is escaped exactly like layout::kdl_escape does it
+NASTY_CMD=$'claude "fix | this & that" --note \'it\\\'s\'\nsecond line\e'
+out_nasty=$(ZELLIJ=1 ZELLIJ_SESSION_NAME=fake PATH="$MOCK_BIN_LAYOUT:$PATH" \
+  "$SCRIPT" spawn test-nasty-cmd "$NASTY_CMD" 2>&1)
+git -C "$REPO_ROOT" worktree remove --force \
+  "$HOME/.zelligent/worktrees/$REPO_NAME/test-nasty-cmd" &>/dev/null || true
+git -C "$REPO_ROOT" branch -D test-nasty-cmd &>/dev/null || true
+contains "nasty agent command: escaped" \
+  'exec claude \"fix | this & that\" --note '"'"'it\\'"'"'s'"'"'\nsecond line\u{1b}"' "$out_nasty"
+
 rm -rf "$MOCK_BIN_LAYOUT"
 
+# ── prepare (used by the plugin to render layouts itself) ─────────────────────
+echo "Prepare:"
+
+out=$("$SCRIPT" prepare test-prepare-branch --json 2>/dev/null); code=$?
+PREPARE_WT="$HOME/.zelligent/worktrees/$REPO_NAME/test-prepare-branch"
+[ -d "$PREPARE_WT" ] && created=yes || created=no
+out_again=$("$SCRIPT" prepare test-prepare-branch --json 2>/dev/null)
+git -C "$REPO_ROOT" worktree remove --force "$PREPARE_WT" &>/dev/null || true
+git -C "$REPO_ROOT" branch -D test-prepare-branch &>/dev/null || true
+
+check    "prepare exits 0"                   "0"   "$code"
+check    "prepare creates the worktree"      "yes" "$created"
+contains "prepare --json has schema version" '{"version":1,'                                 "$out"
+contains "prepare --json has worktree path"  "\"worktree_path\":\"$PREPARE_WT\""               "$out"
+contains "prepare --json new worktree"       '"new_worktree":true'                           "$out"
+contains "prepare --json setup script"       "\"setup_script\":\"$REPO_ROOT/.zelligent/setup.sh\"" "$out"
+contains "prepare --json existing worktree"  '"new_worktree":false'                          "$out_again"
+contains "prepare --json no setup if exists" '"setup_script":""'                             "$out_again"
+excludes "prepare keeps progress off stdout" 'Creating workspace'                            "$out"
+
+out=$("$SCRIPT" prepare "fix login..bug" --json 2>&1); code=$?
+check    "prepare invalid name: exits 1"     "1" "$code"
+contains "prepare invalid name: explains"    "is not a valid branch name" "$out"
+check    "prepare invalid name: no worktree" "no" "$([ -d "$HOME/.zelligent/worktrees/$REPO_NAME/fix login..bug" ] && echo yes || echo no)"
+
+# ── Task prompt ───────────────────────────────────────────────────────────────
+echo "Task prompt:"
+
+PROMPT_WT="$HOME/.zelligent/worktrees/$REPO_NAME/test-prompt-branch"
+out=$("$SCRIPT" prepare --prompt $'Fix the "login" bug\nthen add tests' test-prompt-branch --json 2>/dev/null); code=$?
+PROMPT_FILE="$(git -C "$PROMPT_WT" rev-parse --absolute-git-dir)/zelligent-prompt.md"
+check    "prompt: prepare exits 0"               "0" "$code"
+contains "prompt: --json reports the file"       "\"prompt_file\":\"$PROMPT_FILE\"" "$out"
+check    "prompt: file holds the text"           $'Fix the "login" bug\nthen add tests' "$(cat "$PROMPT_FILE" 2>/dev/null)"
+check    "prompt: worktree stays clean"          "" "$(git -C "$PROMPT_WT" status --porcelain)"
+out=$("$SCRIPT" prepare test-prompt-branch --json 2>/dev/null)
+contains "prompt: none means empty prompt_file"  '"prompt_file":""' "$out"
+
+MOCK_BIN_PROMPT=$(mktemp -d)
+cat > "$MOCK_BIN_PROMPT/zellij" <<'MOCK'
+#!/bin/bash
+for arg in "$@"; do
+  if [ -f "$arg" ]; then cat "$arg"; fi
+done
+MOCK
+chmod +x "$MOCK_BIN_PROMPT/zellij"
+out=$(ZELLIJ=1 ZELLIJ_SESSION_NAME=fake PATH="$MOCK_BIN_PROMPT:$PATH" \
+  "$SCRIPT" spawn --prompt "do it" test-prompt-branch claude 2>&1)
+contains "prompt: passed as last agent argument" "exec claude \\\"\$(cat '$PROMPT_FILE')\\\"" "$out"
+out=$(ZELLIJ=1 ZELLIJ_SESSION_NAME=fake PATH="$MOCK_BIN_PROMPT:$PATH" \
+  "$SCRIPT" spawn --prompt "do it" test-prompt-branch 'aider --message-file {{prompt_file}}' 2>&1)
+contains "prompt: {{prompt_file}} placeholder"  "exec aider --message-file '$PROMPT_FILE'" "$out"
+out=$(ZELLIJ=1 ZELLIJ_SESSION_NAME=fake PATH="$MOCK_BIN_PROMPT:$PATH" \
+  "$SCRIPT" spawn test-prompt-branch 'aider --message-file {{prompt_file}}' 2>&1)
+contains "prompt: placeholder without prompt"   "exec aider --message-file '/dev/null'" "$out"
+out=$(ZELLIJ=1 ZELLIJ_SESSION_NAME=fake SHELL=/bin/bash PATH="$MOCK_BIN_PROMPT:$PATH" \
+  "$SCRIPT" spawn --prompt "do it" test-prompt-branch 2>&1)
+contains "prompt: \$SHELL fallback warns"        "prompt is not passed" "$out"
+contains "prompt: \$SHELL fallback runs shell"   '"exec /bin/bash"' "$out"
+rm -rf "$MOCK_BIN_PROMPT"
+git -C "$REPO_ROOT" worktree remove --force "$PROMPT_WT" &>/dev/null || true
+git -C "$REPO_ROOT" branch -D test-prompt-branch &>/dev/null || true
+
+# ── merge (in a throwaway repo so the real base branch is untouched) ─────────
+echo "Merge:"
+
+MERGE_TMP=$(mktemp -d)
+MERGE_REPO="$MERGE_TMP/zelligent-merge-test-$$"
+MERGE_WT_DIR="$HOME/.zelligent/worktrees/$(basename "$MERGE_REPO")"
+export GIT_AUTHOR_NAME=test GIT_AUTHOR_EMAIL=test@example.com
+export GIT_COMMITTER_NAME=test GIT_COMMITTER_EMAIL=test@example.com
+git init -q -b main "$MERGE_REPO"
+echo base > "$MERGE_REPO/shared.txt"
+git -C "$MERGE_REPO" add shared.txt && git -C "$MERGE_REPO" commit -qm base
+
+# merge_branch <branch> <file> <content>: worktree with one commit on top of main
+merge_branch() {
+  (cd "$MERGE_REPO" && "$SCRIPT" prepare "$1" &>/dev/null)
+  echo "$3" > "$MERGE_WT_DIR/$1/$2"
+  git -C "$MERGE_WT_DIR/$1" add "$2" && git -C "$MERGE_WT_DIR/$1" commit -qm "change $2 on $1"
+}
+run_merge() {
+  (cd "$MERGE_REPO" && "$SCRIPT" merge "$@" 2>&1)
+}
+
+merge_branch feat-merge a.txt a
+out=$(run_merge feat-merge --json); code=$?
+check    "merge: exits 0"             "0" "$code"
+contains "merge: reports merged"      '"result":"merged","branch":"feat-merge","base":"main","strategy":"merge","conflicts":[]' "$out"
+check    "merge: file landed on main" "a" "$(git -C "$MERGE_REPO" show main:a.txt 2>/dev/null)"
+check    "merge: creates merge commit" "2" "$(git -C "$MERGE_REPO" log -1 --format=%p | wc -w | tr -d ' ')"
+
+merge_branch feat-squash b.txt b
+out=$(run_merge --strategy squash feat-squash); code=$?
+check    "squash: exits 0"             "0" "$code"
+contains "squash: text output"         "Merged 'feat-squash' into 'main' (squash)" "$out"
+check    "squash: file landed on main" "b" "$(git -C "$MERGE_REPO" show main:b.txt 2>/dev/null)"
+check    "squash: single-parent commit" "1" "$(git -C "$MERGE_REPO" log -1 --format=%p | wc -w | tr -d ' ')"
+
+merge_branch feat-rebase c.txt c
+echo moved > "$MERGE_REPO/main-only.txt"
+git -C "$MERGE_REPO" add main-only.txt && git -C "$MERGE_REPO" commit -qm "main moves on"
+out=$(run_merge --strategy=rebase feat-rebase --json); code=$?
+check "rebase: exits 0"               "0" "$code"
+check "rebase: main fast-forwarded"   "$(git -C "$MERGE_REPO" rev-parse feat-rebase)" "$(git -C "$MERGE_REPO" rev-parse main)"
+check "rebase: history stays linear"  "1" "$(git -C "$MERGE_REPO" log -1 --format=%p | wc -w | tr -d ' ')"
+
+merge_branch feat-conflict shared.txt theirs
+echo ours > "$MERGE_REPO/shared.txt"
+git -C "$MERGE_REPO" commit -qam "main edits shared"
+BEFORE=$(git -C "$MERGE_REPO" rev-parse HEAD)
+out=$(run_merge feat-conflict --json); code=$?
+check    "conflict: exits 3"            "3" "$code"
+contains "conflict: lists files"        '"result":"conflict","branch":"feat-conflict","base":"main","strategy":"merge","conflicts":["shared.txt"]' "$out"
+check    "conflict: main unchanged"     "$BEFORE" "$(git -C "$MERGE_REPO" rev-parse HEAD)"
+check    "conflict: merge aborted"      "" "$(git -C "$MERGE_REPO" status --porcelain)"
+out=$(run_merge --strategy rebase feat-conflict); code=$?
+check    "rebase conflict: exits 3"     "3" "$code"
+contains "rebase conflict: text output" "shared.txt" "$out"
+check    "rebase conflict: aborted"     "" "$(git -C "$MERGE_WT_DIR/feat-conflict" status --porcelain)"
+
+echo dirty > "$MERGE_WT_DIR/feat-conflict/dirty.txt"
+out=$(run_merge feat-conflict); code=$?
+check    "dirty worktree: exits 1"      "1" "$code"
+contains "dirty worktree: explains"     "uncommitted changes" "$out"
+
+git -C "$MERGE_REPO" checkout -q feat-merge 2>/dev/null || git -C "$MERGE_REPO" checkout -q -b other
+out=$(run_merge feat-squash); code=$?
+check    "wrong checkout: exits 1"      "1" "$code"
+contains "wrong checkout: explains"     "check out 'main'" "$out"
+
+out=$(run_merge --strategy octopus feat-squash); code=$?
+check    "bad strategy: exits 1"        "1" "$code"
+
+# ── merged detection (reuses the merge repo) ─────────────────────────────────
+echo "Merged detection:"
+
+(cd "$MERGE_REPO" && "$SCRIPT" prepare feat-fresh &>/dev/null)
+LIST=$(cd "$MERGE_REPO" && "$SCRIPT" list-worktrees)
+merged_flag() {
+  echo "$LIST" | awk -F'\t' -v b="$1" '$1 == b { print $9 }'
+}
+check    "merged: merge commit"          "true"  "$(merged_flag feat-merge)"
+check    "merged: squash"                "true"  "$(merged_flag feat-squash)"
+check    "merged: rebase + fast-forward" "true"  "$(merged_flag feat-rebase)"
+check    "merged: conflicting branch"    "false" "$(merged_flag feat-conflict)"
+check    "merged: branch without commits" "false" "$(merged_flag feat-fresh)"
+out=$(cd "$MERGE_REPO" && "$SCRIPT" list-worktrees --json)
+contains "merged: in --json output"      '"branch":"feat-squash",' "$out"
+contains "merged: --json field"          '"merged":true,' "$out"
+loose_objects() {
+  git -C "$MERGE_REPO" count-objects | cut -d' ' -f1
+}
+BEFORE_OBJECTS=$(loose_objects)
+(cd "$MERGE_REPO" && "$SCRIPT" list-worktrees >/dev/null)
+(cd "$MERGE_REPO" && "$SCRIPT" list-worktrees >/dev/null)
+check    "merged: listing writes no objects" "$BEFORE_OBJECTS" "$(loose_objects)"
+check    "merged: squash result cached"  "true" \
+  "$(awk -v t="$(git -C "$MERGE_REPO" rev-parse feat-squash)" '$1 == t { print $3 }' "$MERGE_REPO/.git/zelligent-squash-cache")"
+
+out=$(cd "$MERGE_REPO" && "$SCRIPT" clean-merged --dry-run 2>&1); code=$?
+check    "clean-merged --dry-run: exits 0"      "0" "$code"
+contains "clean-merged --dry-run: lists merged" "feat-squash" "$out"
+excludes "clean-merged --dry-run: skips fresh"  "feat-fresh" "$out"
+excludes "clean-merged --dry-run: skips dirty"  "feat-conflict" "$out"
+check    "clean-merged --dry-run: removes nothing" "yes" "$([ -d "$MERGE_WT_DIR/feat-squash" ] && echo yes || echo no)"
+
+# ── remove --delete-branch (reuses the merge repo) ───────────────────────────
+echo "Remove with branch deletion:"
+
+run_remove() {
+  (cd "$MERGE_REPO" && "$SCRIPT" remove "$@" 2>&1)
+}
+branch_exists() {
+  git -C "$MERGE_REPO" show-ref --verify --quiet "refs/heads/$1" && echo yes || echo no
+}
+
+out=$(run_remove feat-rebase); code=$?
+check    "remove: exits 0"                   "0"   "$code"
+check    "remove: keeps branch by default"   "yes" "$(branch_exists feat-rebase)"
+contains "remove: says branch was kept"      "was not deleted" "$out"
+
+out=$(run_remove --delete-branch feat-merge); code=$?
+check    "delete merged: exits 0"            "0"   "$code"
+check    "delete merged: worktree removed"   "no"  "$([ -d "$MERGE_WT_DIR/feat-merge" ] && echo yes || echo no)"
+check    "delete merged: branch deleted"     "no"  "$(branch_exists feat-merge)"
+
+# squash-merged: the branch itself is not an ancestor of main, but the list
+# tags it merged, so it may be deleted
+out=$(run_remove --delete-branch feat-squash); code=$?
+check    "delete squashed: exits 0"          "0"   "$code"
+check    "delete squashed: worktree removed" "no"  "$([ -d "$MERGE_WT_DIR/feat-squash" ] && echo yes || echo no)"
+check    "delete squashed: branch deleted"   "no"  "$(branch_exists feat-squash)"
+
+merge_branch feat-unmerged e.txt e
+out=$(run_remove --delete-branch feat-unmerged); code=$?
+check    "delete unmerged: exits 1"          "1"   "$code"
+contains "delete unmerged: explains"         "not merged into 'main'" "$out"
+check    "delete unmerged: worktree kept"    "yes" "$([ -d "$MERGE_WT_DIR/feat-unmerged" ] && echo yes || echo no)"
+check    "delete unmerged: branch kept"      "yes" "$(branch_exists feat-unmerged)"
+
+out=$(run_remove --force-delete-branch feat-unmerged); code=$?
+check    "force delete: exits 0"             "0"   "$code"
+check    "force delete: branch deleted"      "no"  "$(branch_exists feat-unmerged)"
+
+# feat-conflict was left with an untracked dirty.txt by the merge tests
+echo edited > "$MERGE_WT_DIR/feat-conflict/shared.txt"
+out=$(run_remove feat-conflict); code=$?
+check    "dirty remove: exits 2"             "2"   "$code"
+contains "dirty remove: explains"            "uncommitted changes" "$out"
+check    "dirty remove: worktree kept"       "yes" "$([ -d "$MERGE_WT_DIR/feat-conflict" ] && echo yes || echo no)"
+
+CONFLICT_HEAD=$(git -C "$MERGE_REPO" rev-parse feat-conflict)
+out=$(run_remove --force feat-conflict); code=$?
+check    "force remove: exits 0"             "0"   "$code"
+contains "force remove: names backup"        "zelligent/backup/feat-conflict" "$out"
+check    "force remove: worktree removed"    "no"  "$([ -d "$MERGE_WT_DIR/feat-conflict" ] && echo yes || echo no)"
+check    "force remove: branch untouched"    "$CONFLICT_HEAD" "$(git -C "$MERGE_REPO" rev-parse feat-conflict)"
+check    "force remove: backup on branch"    "$CONFLICT_HEAD" "$(git -C "$MERGE_REPO" rev-parse zelligent/backup/feat-conflict^)"
+check    "force remove: saves edits"         "edited" "$(git -C "$MERGE_REPO" show zelligent/backup/feat-conflict:shared.txt)"
+check    "force remove: saves untracked"     "dirty"  "$(git -C "$MERGE_REPO" show zelligent/backup/feat-conflict:dirty.txt)"
+FIRST_BACKUP=$(git -C "$MERGE_REPO" rev-parse zelligent/backup/feat-conflict)
+(cd "$MERGE_REPO" && "$SCRIPT" prepare feat-conflict &>/dev/null)
+echo again > "$MERGE_WT_DIR/feat-conflict/shared.txt"
+out=$(run_remove --force feat-conflict); code=$?
+check    "second force remove: exits 0"      "0"   "$code"
+contains "second force remove: names backup" "zelligent/backup/feat-conflict^2" "$out"
+check    "second force remove: saves edits"  "again" "$(git -C "$MERGE_REPO" show zelligent/backup/feat-conflict:shared.txt)"
+check    "second force remove: on branch"    "$CONFLICT_HEAD" "$(git -C "$MERGE_REPO" rev-parse zelligent/backup/feat-conflict^1)"
+check    "second force remove: keeps first"  "$FIRST_BACKUP" "$(git -C "$MERGE_REPO" rev-parse zelligent/backup/feat-conflict^2)"
+out=$(cd "$MERGE_REPO" && "$SCRIPT" list-branches)
+excludes "list-branches hides backups"       "zelligent/backup" "$out"
+
+git -C "$MERGE_REPO" checkout -q main
+merge_branch feat-clean d.txt d
+run_merge feat-clean >/dev/null
+# Empty and tab-holding commit subjects must not shift the other columns
+(cd "$MERGE_REPO" && "$SCRIPT" prepare feat-blank &>/dev/null)
+git -C "$MERGE_WT_DIR/feat-blank" commit -q --allow-empty --allow-empty-message -m ''
+run_merge feat-blank >/dev/null
+(cd "$MERGE_REPO" && "$SCRIPT" prepare feat-tabbed &>/dev/null)
+git -C "$MERGE_WT_DIR/feat-tabbed" commit -q --allow-empty -m $'x\ttrue\ttrue\t0'
+out=$(cd "$MERGE_REPO" && "$SCRIPT" clean-merged --dry-run 2>&1)
+contains "clean-merged: empty subject listed"   "feat-blank"  "$out"
+excludes "clean-merged: tab in subject skipped" "feat-tabbed" "$out"
+out=$(cd "$MERGE_REPO" && "$SCRIPT" clean-merged 2>&1); code=$?
+check    "clean-merged: exits 0"              "0"   "$code"
+contains "clean-merged: reports count"        "Removed 2 merged worktree(s)" "$out"
+check    "clean-merged: removes merged"       "no"  "$([ -d "$MERGE_WT_DIR/feat-clean" ] && echo yes || echo no)"
+check    "clean-merged: removes empty subject" "no" "$([ -d "$MERGE_WT_DIR/feat-blank" ] && echo yes || echo no)"
+check    "clean-merged: keeps unmerged"       "yes" "$([ -d "$MERGE_WT_DIR/feat-fresh" ] && echo yes || echo no)"
+check    "clean-merged: keeps tab subject"    "yes" "$([ -d "$MERGE_WT_DIR/feat-tabbed" ] && echo yes || echo no)"
+check    "clean-merged: keeps the branch"     "yes" "$(branch_exists feat-clean)"
+
+# ── worktrees not created by zelligent (reuses the merge repo) ───────────────
+echo "External worktrees:"
+
+EXT_WT="$MERGE_TMP/ext-branch"
+DETACHED_WT="$MERGE_TMP/ext-detached"
+git -C "$MERGE_REPO" worktree add -q -b ext "$EXT_WT" main
+git -C "$MERGE_REPO" worktree add -q --detach "$DETACHED_WT" main
+DETACHED_HEAD=$(git -C "$MERGE_REPO" rev-parse main)
+out=$(cd "$MERGE_REPO" && "$SCRIPT" list-worktrees --json)
+contains "external: listed with branch"  "{\"branch\":\"ext\",\"path\":\"$EXT_WT\"," "$out"
+contains "external: marked unmanaged"    '"merged":false,"managed":false,"detached":false,' "$out"
+contains "detached: listed without branch" "{\"branch\":\"\",\"path\":\"$DETACHED_WT\",\"head\":\"$DETACHED_HEAD\"," "$out"
+contains "detached: flagged"             '"managed":false,"detached":true,' "$out"
+contains "managed: flagged"              '"managed":true,"detached":false,' "$out"
+contains "worktrees: creation time"      '"created_time":' "$out"
+excludes "worktrees: creation time known" '"created_time":0}' "$out"
+excludes "main checkout not listed"      "\"path\":\"$MERGE_REPO\"" "$out"
+out=$(cd "$MERGE_REPO" && "$SCRIPT" list-worktrees)
+check    "detached: text columns"        "$DETACHED_WT	false	false	true" \
+  "$(echo "$out" | awk -F'\t' -v p="$DETACHED_WT" '$2 == p { print $2 "\t" $9 "\t" $10 "\t" $11 }')"
+
+out=$(cd "$MERGE_REPO" && "$SCRIPT" prepare ext --json 2>/dev/null); code=$?
+check    "prepare external branch: exits 0"     "0" "$code"
+contains "prepare external branch: reuses it"   "\"worktree_path\":\"$EXT_WT\"" "$out"
+contains "prepare external branch: not new"     '"new_worktree":false' "$out"
+check    "prepare external branch: no copy"     "no" "$([ -d "$MERGE_WT_DIR/ext" ] && echo yes || echo no)"
+out=$(cd "$MERGE_REPO" && "$SCRIPT" prepare main --json 2>&1); code=$?
+check    "prepare main checkout: exits 1"       "1" "$code"
+contains "prepare main checkout: explains"      "is checked out in the main repo" "$out"
+check    "prepare main checkout: no worktree"   "no" "$([ -d "$MERGE_WT_DIR/main" ] && echo yes || echo no)"
+out=$(cd "$MERGE_REPO" && "$SCRIPT" list-branches --json)
+contains "list-branches: flags main checkout"   '{"name":"main","last_commit_time":' "$out"
+contains "list-branches: main checkout true"    '"main_checkout":true}' "$out"
+contains "list-branches: worktree branch false" '{"name":"ext","last_commit_time":' "$out"
+out=$(cd "$MERGE_REPO" && "$SCRIPT" prepare --path "$DETACHED_WT" ext-detached --json 2>/dev/null); code=$?
+check    "prepare --path: exits 0"              "0" "$code"
+contains "prepare --path: uses the worktree"    "\"branch\":\"ext-detached\",\"worktree_path\":\"$DETACHED_WT\"" "$out"
+out=$(cd "$MERGE_REPO" && "$SCRIPT" prepare --path /nonexistent x --json 2>&1); code=$?
+check    "prepare --path elsewhere: exits 1"    "1" "$code"
+contains "prepare --path elsewhere: explains"   "is not a worktree" "$out"
+
+git -C "$MERGE_REPO" merge -q ext 2>/dev/null
+out=$(cd "$MERGE_REPO" && "$SCRIPT" clean-merged --dry-run)
+excludes "clean-merged skips external"   "ext" "$out"
+
+for wt in "$MERGE_WT_DIR"/*; do git -C "$MERGE_REPO" worktree remove --force "$wt" &>/dev/null || true; done
+rm -rf "$MERGE_TMP" "$MERGE_WT_DIR"
+unset GIT_AUTHOR_NAME GIT_AUTHOR_EMAIL GIT_COMMITTER_NAME GIT_COMMITTER_EMAIL
+
 # ── Quoted agent command ─────────────────────────────────────────────────────
 echo "Quoted agent command:"
 
@@ -162,6 +553,57 @@ contains "quoted cmd: quotes are escaped" 'exec claude -p \"Sag Hallo auf Deutsc
 
 rm -rf "$MOCK_BIN_QUOTE"
 
+# ── Base ref ──────────────────────────────────────────────────────────────────
+echo "Base ref:"
+
+MOCK_BIN_BASE=$(mktemp -d)
+cat > "$MOCK_BIN_BASE/zellij" <<'MOCK'
+#!/bin/bash
+echo "zellij $*"
+MOCK
+cat > "$MOCK_BIN_BASE/lazygit" <<'MOCK'
+#!/bin/bash
+MOCK
+chmod +x "$MOCK_BIN_BASE/zellij" "$MOCK_BIN_BASE/lazygit"
+
+# A base branch one commit ahead of HEAD
+BASE_SHA=$(git -C "$REPO_ROOT" -c user.name=test -c user.email=test@example.com \
+  commit-tree -p HEAD -m "base commit" "$(git -C "$REPO_ROOT" rev-parse 'HEAD^{tree}')")
+git -C "$REPO_ROOT" branch test-base-release "$BASE_SHA" &>/dev/null
+
+out=$(ZELLIJ=1 ZELLIJ_SESSION_NAME=fake PATH="$MOCK_BIN_BASE:$PATH" \
+  "$SCRIPT" spawn --base test-base-release test-based-branch claude 2>&1); code=$?
+BASED_SHA=$(git -C "$REPO_ROOT" rev-parse test-based-branch 2>/dev/null)
+git -C "$REPO_ROOT" worktree remove --force \
+  "$HOME/.zelligent/worktrees/$REPO_NAME/test-based-branch" &>/dev/null || true
+git -C "$REPO_ROOT" branch -D test-based-branch &>/dev/null || true
+check    "spawn --base exits 0"                 "0"                    "$code"
+contains "spawn --base reports base"            "from 'test-base-release'" "$out"
+check    "spawn --base branches off base ref"   "$BASE_SHA"            "$BASED_SHA"
+
+out=$(ZELLIJ=1 ZELLIJ_SESSION_NAME=fake PATH="$MOCK_BIN_BASE:$PATH" \
+  "$SCRIPT" spawn test-based-commit --base="$BASE_SHA" 2>&1)
+BASED_SHA=$(git -C "$REPO_ROOT" rev-parse test-based-commit 2>/dev/null)
+git -C "$REPO_ROOT" worktree remove --force \
+  "$HOME/.zelligent/worktrees/$REPO_NAME/test-based-commit" &>/dev/null || true
+git -C "$REPO_ROOT" branch -D test-based-commit &>/dev/null || true
+check "spawn --base=<sha> after branch name" "$BASE_SHA" "$BASED_SHA"
+
+out=$("$SCRIPT" spawn test-bad-base --base 2>/dev/null); code=$?
+check    "spawn --base without a ref exits 1"     "1"                 "$code"
+check    "spawn --base without a ref: error on stderr" ""             "$out"
+
+out=$("$SCRIPT" spawn --base no-such-ref-xyz test-bad-base 2>&1); code=$?
+check    "spawn with unknown base exits non-zero" "1"                 "$code"
+contains "spawn with unknown base prints error"   "base ref 'no-such-ref-xyz' not found" "$out"
+excludes "spawn with unknown base creates nothing" "Creating"         "$out"
+
+out=$("$SCRIPT" spawn --base 2>&1); code=$?
+check "spawn --base without value exits non-zero" "1" "$code"
+
+git -C "$REPO_ROOT" branch -D test-base-release &>/dev/null || true
+rm -rf "$MOCK_BIN_BASE"
+
 # ── Argument validation ────────────────────────────────────────────────────────
 echo "Argument validation:"
 
@@ -198,10 +640,12 @@ out=$("$SCRIPT" show-repo 2>&1); code=$?
 check "show-repo exits 0" "0" "$code"
 contains "show-repo outputs repo_root" "repo_root=" "$out"
 contains "show-repo outputs repo_name" "repo_name=" "$out"
+contains "show-repo outputs base_branch" "base_branch=" "$out"
 # Verify repo_name matches the basename of the repo
 EXPECTED_NAME=$(basename "$(echo "$out" | grep '^repo_root=' | cut -d= -f2-)")
 ACTUAL_NAME=$(echo "$out" | grep '^repo_name=' | cut -d= -f2-)
 check "show-repo name matches root basename" "$EXPECTED_NAME" "$ACTUAL_NAME"
+BASE_BRANCH=$(echo "$out" | grep '^base_branch=' | cut -d= -f2-)
 
 # show-repo from non-git dir
 NONGIT2=$(mktemp -d)
@@ -213,10 +657,59 @@ rm -rf "$NONGIT2"
 out=$("$SCRIPT" list-worktrees 2>&1); code=$?
 check "list-worktrees exits 0" "0" "$code"
 
+# list-worktrees metadata: one commit ahead, one dirty file
+META_WT="$HOME/.zelligent/worktrees/$REPO_NAME/test-meta-branch"
+git -C "$REPO_ROOT" worktree add -b test-meta-branch "$META_WT" HEAD &>/dev/null
+git -C "$META_WT" -c user.name=test -c user.email=test@example.com \
+  commit -q --allow-empty -m "meta test commit" &>/dev/null
+touch "$META_WT/untracked-file"
+out=$("$SCRIPT" list-worktrees 2>&1)
+git -C "$REPO_ROOT" worktree remove --force "$META_WT" &>/dev/null || true
+git -C "$REPO_ROOT" branch -D test-meta-branch &>/dev/null || true
+META_LINE=$(echo "$out" | grep "^test-meta-branch	")
+check "list-worktrees: path field"    "$META_WT"           "$(echo "$META_LINE" | cut -f2)"
+check "list-worktrees: dirty count"   "1"                  "$(echo "$META_LINE" | cut -f4)"
+check "list-worktrees: ahead count"   "1"                  "$(echo "$META_LINE" | cut -f5)"
+check "list-worktrees: subject field" "meta test commit"   "$(echo "$META_LINE" | cut -f8)"
+
 # list-branches
 out=$("$SCRIPT" list-branches 2>&1); code=$?
 check "list-branches exits 0" "0" "$code"
-contains "list-branches includes main or master" "main" "$out"
+contains "list-branches includes the base branch" "$BASE_BRANCH" "$out"
+
+# --json output
+out=$("$SCRIPT" show-repo --json 2>&1); code=$?
+check "show-repo --json exits 0" "0" "$code"
+contains "show-repo --json has schema version" '"version":1' "$out"
+contains "show-repo --json has repo_root"      "\"repo_root\":\"$REPO_ROOT\"" "$out"
+contains "show-repo --json has repo_name"      "\"repo_name\":\"$REPO_NAME\"" "$out"
+contains "show-repo --json has empty default_agent" '"default_agent":""' "$out"
+
+git -C "$REPO_ROOT" config zelligent.agent aider
+out=$("$SCRIPT" show-repo --json 2>&1)
+contains "show-repo --json reports zelligent.agent" '"default_agent":"aider"' "$out"
+out=$("$SCRIPT" show-repo 2>&1)
+contains "show-repo reports zelligent.agent"        "default_agent=aider"     "$out"
+git -C "$REPO_ROOT" config --unset zelligent.agent
+
+out=$("$SCRIPT" list-branches --json 2>&1); code=$?
+check "list-branches --json exits 0" "0" "$code"
+contains "list-branches --json has schema version" '{"version":1,"branches":[' "$out"
+contains "list-branches --json includes the base branch" "{\"name\":\"$BASE_BRANCH\"," "$out"
+
+out=$("$SCRIPT" list-worktrees --json 2>&1); code=$?
+check "list-worktrees --json exits 0" "0" "$code"
+contains "list-worktrees --json has schema version" '{"version":1,"worktrees":[' "$out"
+
+JSON_WT="$HOME/.zelligent/worktrees/$REPO_NAME/test-json-branch"
+git -C "$REPO_ROOT" worktree add -b test-json-branch "$JSON_WT" HEAD &>/dev/null
+git -C "$JSON_WT" -c user.name=test -c user.email=test@example.com \
+  commit -q --allow-empty -m 'say "hi"	tab\back' &>/dev/null
+out=$("$SCRIPT" list-worktrees --json 2>&1)
+git -C "$REPO_ROOT" worktree remove --force "$JSON_WT" &>/dev/null || true
+git -C "$REPO_ROOT" branch -D test-json-branch &>/dev/null || true
+contains "list-worktrees --json has branch"          '"branch":"test-json-branch"'                      "$out"
+contains "list-worktrees --json escapes subject"     '"last_commit_subject":"say \"hi\"\ttab\\back"' "$out"
 
 # ── Launch mode selection ─────────────────────────────────────────────────────
 echo "Launch mode:"
@@ -278,7 +771,44 @@ contains "outside zellij (existing): attaches to repo session" "Attaching to ses
 contains "outside zellij (existing): calls action new-tab"     "action new-tab"                   "$out"
 contains "outside zellij (existing): calls attach"             "zellij attach $REPO_NAME"         "$out"
 
-rm -rf "$MOCK_BIN" "$MOCK_BIN2"
+rm -rf "$MOCK_BIN2"
+
+# ── Remote branches ───────────────────────────────────────────────────────────
+echo "Remote branches:"
+
+# A fake remote with one remote-only branch (the URL is never contacted)
+git -C "$REPO_ROOT" remote add zelligent-test /nonexistent &>/dev/null
+git -C "$REPO_ROOT" update-ref refs/remotes/zelligent-test/test-remote-branch HEAD
+git -C "$REPO_ROOT" update-ref refs/remotes/zelligent-test/HEAD HEAD
+
+out=$("$SCRIPT" list-branches 2>&1)
+contains "list-branches includes remote-only branch" "zelligent-test/test-remote-branch" "$out"
+excludes "list-branches skips remote HEAD"            "zelligent-test/HEAD"               "$out"
+
+out=$("$SCRIPT" list-branches --json 2>&1)
+contains "list-branches --json marks remote branch" \
+  '{"name":"test-remote-branch","remote":"zelligent-test","last_commit_time":' "$out"
+
+out=$(ZELLIJ=1 ZELLIJ_SESSION_NAME=fake PATH="$MOCK_BIN:$PATH" \
+  "$SCRIPT" spawn zelligent-test/test-remote-branch 2>&1)
+UPSTREAM=$(git -C "$REPO_ROOT" rev-parse --abbrev-ref test-remote-branch@{upstream} 2>/dev/null)
+git -C "$REPO_ROOT" worktree remove --force \
+  "$HOME/.zelligent/worktrees/$REPO_NAME/test-remote-branch" &>/dev/null || true
+git -C "$REPO_ROOT" branch -D test-remote-branch &>/dev/null || true
+contains "spawn remote branch: creates tracking branch" "tracking 'zelligent-test/test-remote-branch'" "$out"
+check    "spawn remote branch: upstream is set"          "zelligent-test/test-remote-branch"          "$UPSTREAM"
+
+# A local branch shadows the remote one
+git -C "$REPO_ROOT" branch test-remote-branch HEAD &>/dev/null
+out=$("$SCRIPT" list-branches 2>&1)
+git -C "$REPO_ROOT" branch -D test-remote-branch &>/dev/null || true
+excludes "list-branches hides remote branch with local counterpart" "zelligent-test/test-remote-branch" "$out"
+
+git -C "$REPO_ROOT" remote remove zelligent-test &>/dev/null || true
+git -C "$REPO_ROOT" update-ref -d refs/remotes/zelligent-test/test-remote-branch &>/dev/null || true
+git -C "$REPO_ROOT" update-ref -d refs/remotes/zelligent-test/HEAD &>/dev/null || true
+
+rm -rf "$MOCK_BIN"
 
 # ── Integration: layout loading via background session ────────────────────────
 echo "Integration (requires Zellij):"
@@ -5,12 +5,16 @@
 set -e
 
 if [ -z "$1" ]; then
-  echo "Usage: zelligent spawn <branch-name> [agent-command]"
-  echo "       zelligent remove <branch-name>"
+  echo "Usage: zelligent spawn [--base <ref>] [--prompt <text>] <branch-name> [agent-command]"
+  echo "       zelligent prepare [--base <ref>] [--prompt <text>] <branch-name> [--json]"
+  echo "       zelligent prepare --path <worktree> <tab-name> [--json]"
+  echo "       zelligent remove [--force] [--delete-branch|--force-delete-branch] <branch-name>"
+  echo "       zelligent merge [--strategy merge|squash|rebase] <branch-name> [--json]"
+  echo "       zelligent clean-merged [--dry-run]"
   echo "       zelligent init"
-  echo "       zelligent show-repo"
-  echo "       zelligent list-worktrees"
-  echo "       zelligent list-branches"
+  echo "       zelligent show-repo [--json]"
+  echo "       zelligent list-worktrees [--json]"
+  echo "       zelligent list-branches [--json]"
   exit 1
 fi
 
@@ -24,33 +28,298 @@ REPO_ROOT="${GIT_COMMON_DIR%/.git}"
 REPO_NAME=$(basename "$REPO_ROOT")
 WORKTREES_DIR="$HOME/.zelligent/worktrees/$REPO_NAME"
 
+# Detect the default base branch: origin/HEAD if set, otherwise main.
+detect_base_branch() {
+  local base_ref
+  if base_ref=$(git -C "$REPO_ROOT" symbolic-ref refs/remotes/origin/HEAD 2>/dev/null); then
+    echo "${base_ref#refs/remotes/origin/}"
+  else
+    echo "main"
+  fi
+}
+
+# Print the remote-tracking ref ("<remote>/<branch>") for a branch name,
+# preferring origin. Fails if no remote has the branch.
+find_remote_branch() {
+  local ref match=""
+  while IFS= read -r ref; do
+    [ "${ref#*/}" = "$1" ] || continue
+    if [ "${ref%%/*}" = "origin" ]; then
+      echo "$ref"
+      return 0
+    fi
+    [ -n "$match" ] || match=$ref
+  done < <(git -C "$REPO_ROOT" for-each-ref --format='%(refname:lstrip=2)' refs/remotes/)
+  [ -n "$match" ] && echo "$match"
+}
+
+# Commit everything in worktree $1 (tracked changes and untracked files, not
+# ignored ones) on top of its HEAD and point $2 at it. The worktree, its index
+# and its branch are left as they are. An earlier backup in $2 becomes the
+# commit's second parent rather than being overwritten.
+backup_worktree() {
+  local index_dir tree commit previous
+  local parents=(-p HEAD)
+  if previous=$(git -C "$1" rev-parse --verify --quiet "$2^{commit}"); then
+    parents+=(-p "$previous")
+  fi
+  index_dir=$(mktemp -d)
+  if tree=$(GIT_INDEX_FILE="$index_dir/index" git -C "$1" read-tree HEAD &&
+            GIT_INDEX_FILE="$index_dir/index" git -C "$1" add -A &&
+            GIT_INDEX_FILE="$index_dir/index" git -C "$1" write-tree) &&
+     commit=$(git -C "$1" commit-tree "$tree" "${parents[@]}" -m "zelligent: uncommitted changes saved by remove --force") &&
+     git -C "$1" update-ref -m "zelligent remove --force" "$2" "$commit" "${previous:-}"; then
+    rm -rf "$index_dir"
+    return 0
+  fi
+  rm -rf "$index_dir"
+  return 1
+}
+
+# Print the path of the worktree that has branch $1 checked out, if any.
+worktree_for_branch() {
+  git -C "$REPO_ROOT" worktree list --porcelain | awk -v ref="branch refs/heads/$1" '
+    /^worktree / { path = substr($0, 10) }
+    $0 == ref { print path; exit }'
+}
+
+# Whether branch $1 has landed on $BASE_BRANCH: its commits are on the base
+# (merge, rebase + fast-forward) or its changes are (squash). A branch that
+# never got a commit of its own since it was created does not count.
+branch_merged() {
+  local tip created
+  tip=$(git -C "$REPO_ROOT" rev-parse --verify --quiet "refs/heads/$1") || return 1
+  created=$(git -C "$REPO_ROOT" reflog show --format=%H "refs/heads/$1" -- 2>/dev/null | tail -n 1)
+  [ "$tip" != "$created" ] || return 1
+  git -C "$REPO_ROOT" merge-base --is-ancestor "$tip" "$BASE_BRANCH" 2>/dev/null && return 0
+  branch_squashed "$tip"
+}
+
+# Squash-merge results per "<tip> <base> <true|false>" line. The answer for
+# a pair never changes, and the plugin lists worktrees every few seconds.
+SQUASH_CACHE="$GIT_COMMON_DIR/zelligent-squash-cache"
+
+# Whether commit $1's changes since it forked from $BASE_BRANCH landed there
+# as a single commit: the patch-id of the whole diff matches one of the
+# base's commits. Read-only apart from the cache file.
+branch_squashed() {
+  local base fork patch_id result
+  base=$(git -C "$REPO_ROOT" rev-parse --verify --quiet "$BASE_BRANCH^{commit}") || return 1
+  result=$(awk -v tip="$1" -v base="$base" '$1 == tip && $2 == base { print $3; exit }' "$SQUASH_CACHE" 2>/dev/null || true)
+  if [ -z "$result" ]; then
+    result=false
+    if fork=$(git -C "$REPO_ROOT" merge-base "$base" "$1" 2>/dev/null) &&
+       patch_id=$(git -C "$REPO_ROOT" diff --no-color --no-ext-diff "$fork" "$1" | git -C "$REPO_ROOT" patch-id --stable | cut -d' ' -f1) &&
+       [ -n "$patch_id" ] &&
+       git -C "$REPO_ROOT" log -p --no-color --no-ext-diff "$fork..$base" | git -C "$REPO_ROOT" patch-id --stable |
+         cut -d' ' -f1 | grep -qxF "$patch_id"; then
+      result=true
+    fi
+    # Keep the cache small; old pairs are for tips and bases long gone.
+    { tail -n 499 "$SQUASH_CACHE" 2>/dev/null || true; echo "$1 $base $result"; } > "$SQUASH_CACHE.$$" &&
+      mv "$SQUASH_CACHE.$$" "$SQUASH_CACHE" || rm -f "$SQUASH_CACHE.$$"
+  fi
+  [ "$result" = true ]
+}
+
+# Modification time of file $1 as a unix timestamp, 0 if it can't be read.
+# GNU stat takes -c, BSD/macOS stat -f.
+file_mtime() {
+  stat -c %Y "$1" 2>/dev/null || stat -f %m "$1" 2>/dev/null || echo 0
+}
+
+# Escape a string for embedding in a KDL string literal, the same way as
+# layout::kdl_escape in the plugin.
+kdl_escape() {
+  local s=$1 out="" c i
+  s=${s//\\/\\\\}
+  s=${s//\"/\\\"}
+  s=${s//$'\n'/\\n}
+  s=${s//$'\r'/\\r}
+  s=${s//$'\t'/\\t}
+  # Other control characters become \u{hex} escapes
+  if [[ $s == *[[:cntrl:]]* ]]; then
+    for ((i = 0; i < ${#s}; i++)); do
+      c=${s:i:1}
+      if [[ $c == [[:cntrl:]] ]]; then
+        out+=$(printf '\\u{%x}' "'$c")
+      else
+        out+=$c
+      fi
+    done
+    s=$out
+  fi
+  printf '%s' "$s"
+}
+
+# Single-quote a string for a POSIX shell command line.
+shell_quote() {
+  printf "'%s'" "${1//\'/\'\\\'\'}"
+}
+
+# In bash >= 5.2, `&` in a ${var//pat/rep} replacement expands to the match;
+# values substituted into layouts must be inserted literally.
+shopt -u patsub_replacement 2>/dev/null || true
+
 # --- Query subcommands (no zellij/lazygit needed) ---
 
+# Version of the --json output schema. Bump on incompatible changes; the
+# plugin refuses output whose version it does not know.
+JSON_SCHEMA_VERSION=1
+
+JSON=false
+if [ "$2" = "--json" ]; then
+  JSON=true
+fi
+
+# Escape a string for embedding in a JSON string literal.
+json_escape() {
+  local s=$1 c u
+  s=${s//\\/\\\\}
+  s=${s//\"/\\\"}
+  s=${s//$'\n'/\\n}
+  s=${s//$'\r'/\\r}
+  s=${s//$'\t'/\\t}
+  while [[ $s =~ [[:cntrl:]] ]]; do
+    c=${BASH_REMATCH[0]}
+    printf -v u '\\u%04x' "'$c"
+    s=${s//"$c"/$u}
+  done
+  printf '%s' "$s"
+}
+
 if [ "$1" = "show-repo" ]; then
-  echo "repo_root=$REPO_ROOT"
-  echo "repo_name=$REPO_NAME"
+  BASE_BRANCH=$(detect_base_branch)
+  # Per-repo agent profile preference for the plugin: git config zelligent.agent <name>
+  DEFAULT_AGENT=$(git -C "$REPO_ROOT" config --get zelligent.agent || true)
+  if [ "$JSON" = true ]; then
+    printf '{"version":%d,"repo_root":"%s","repo_name":"%s","base_branch":"%s","default_agent":"%s"}\n' \
+      "$JSON_SCHEMA_VERSION" "$(json_escape "$REPO_ROOT")" "$(json_escape "$REPO_NAME")" \
+      "$(json_escape "$BASE_BRANCH")" "$(json_escape "$DEFAULT_AGENT")"
+  else
+    echo "repo_root=$REPO_ROOT"
+    echo "repo_name=$REPO_NAME"
+    echo "base_branch=$BASE_BRANCH"
+    echo "default_agent=$DEFAULT_AGENT"
+  fi
   exit 0
 fi
 
+# One worktree per line, tab-separated:
+#   branch, path, HEAD sha, dirty file count, ahead, behind (relative to the
+#   base branch), last commit time (unix), last commit subject, merged
+#   ("true" if the branch has landed on the base branch, see branch_merged),
+#   managed ("true" if zelligent created it under ~/.zelligent/worktrees),
+#   detached ("true" for a detached HEAD; branch is then empty), created
+#   time (unix; when `git worktree add` ran, 0 if unknown)
+# Every worktree except the main checkout is listed, including ones created
+# with plain `git worktree add`.
+# With --json: {"version":N,"worktrees":[{"branch":...,"path":...,...}]}
+# With --cleanable (internal, for clean-merged): only the branches of merged,
+# managed worktrees without uncommitted changes, one per line.
 if [ "$1" = "list-worktrees" ]; then
   SPAWN_PREFIX="$WORKTREES_DIR/"
-  git -C "$REPO_ROOT" worktree list --porcelain | while IFS= read -r line; do
+  BASE_BRANCH=$(detect_base_branch)
+  CLEANABLE=false
+  [ "$2" = --cleanable ] && CLEANABLE=true
+  [ "$JSON" = true ] && printf '{"version":%d,"worktrees":[' "$JSON_SCHEMA_VERSION"
+  sep=""
+  # print_worktree: report the entry collected from the porcelain block
+  print_worktree() {
+    [ -n "$current_path" ] && [ -n "$current_head" ] || return 0
+    [ "$current_path" != "$REPO_ROOT" ] || return 0
+    local managed=false detached=false merged=false dirty counts behind ahead last_commit created
+    [[ "$current_path" == "$SPAWN_PREFIX"* ]] && managed=true
+    [ -z "$branch" ] && detached=true
+    dirty=$(git -C "$current_path" status --porcelain 2>/dev/null | wc -l | tr -d ' ')
+    if counts=$(git -C "$REPO_ROOT" rev-list --left-right --count "$BASE_BRANCH...$current_head" 2>/dev/null); then
+      read -r behind ahead <<< "$counts"
+    else
+      behind=0
+      ahead=0
+    fi
+    last_commit=$(git -C "$current_path" log -1 --format='%ct%x09%s' 2>/dev/null || true)
+    if [ -n "$branch" ] && branch_merged "$branch"; then
+      merged=true
+    fi
+    # The worktree's admin dir gets its commondir file once, at creation.
+    created=$(file_mtime "$(git -C "$current_path" rev-parse --absolute-git-dir 2>/dev/null)/commondir")
+    if [ "$CLEANABLE" = true ]; then
+      [ "$merged" = true ] && [ "$managed" = true ] && [ "$dirty" = 0 ] && echo "$branch"
+      return 0
+    elif [ "$JSON" = true ]; then
+      local last_commit_time=${last_commit%%$'\t'*}
+      local last_commit_subject=${last_commit#*$'\t'}
+      printf '%s{"branch":"%s","path":"%s","head":"%s","dirty":%d,"ahead":%d,"behind":%d,"last_commit_time":%d,"last_commit_subject":"%s","merged":%s,"managed":%s,"detached":%s,"created_time":%d}' \
+        "$sep" "$(json_escape "$branch")" "$(json_escape "$current_path")" "$current_head" \
+        "$dirty" "$ahead" "$behind" "${last_commit_time:-0}" "$(json_escape "$last_commit_subject")" \
+        "$merged" "$managed" "$detached" "$created"
+      sep=","
+    else
+      printf '%s\t%s\t%s\t%s\t%s\t%s\t%s\t%s\t%s\t%s\t%s\n' \
+        "$branch" "$current_path" "$current_head" "$dirty" "$ahead" "$behind" "$last_commit" \
+        "$merged" "$managed" "$detached" "$created"
+    fi
+  }
+  current_path=""
+  current_head=""
+  branch=""
+  while IFS= read -r line; do
     case "$line" in
-      "worktree "*)
-        current_path="${line#worktree }"
-        ;;
-      "branch "*)
-        if [[ "$current_path" == "$SPAWN_PREFIX"* ]]; then
-          echo "${line#branch refs/heads/}"
-        fi
+      "worktree "*) current_path="${line#worktree }" ;;
+      "HEAD "*) current_head="${line#HEAD }" ;;
+      "branch "*) branch="${line#branch refs/heads/}" ;;
+      "")
+        print_worktree
+        current_path=""
+        current_head=""
+        branch=""
         ;;
     esac
-  done
+  done < <(git -C "$REPO_ROOT" worktree list --porcelain; echo)
+  [ "$JSON" = true ] && printf ']}\n'
   exit 0
 fi
 
+# One branch per line: local branches, then remote branches that have no
+# local counterpart as "<remote>/<branch>".
+# With --json: {"version":N,"branches":[{"name":...,"remote":...}]}, where
+# "remote" is only present for remote-only branches.
 if [ "$1" = "list-branches" ]; then
-  git -C "$REPO_ROOT" branch --format='%(refname:short)'
+  # Tab-separated: name, last commit time, and (local branches) the path of
+  # the worktree it is checked out in.
+  # zelligent/backup/* hold changes saved by `remove --force`, not work to spawn
+  LOCAL_INFO=$(git -C "$REPO_ROOT" for-each-ref --format='%(refname:lstrip=2)%09%(committerdate:unix)%09%(worktreepath)' refs/heads/ |
+    grep -v '^zelligent/backup/' || true)
+  LOCAL_BRANCHES=$(cut -f1 <<< "$LOCAL_INFO")
+  REMOTE_INFO=$(git -C "$REPO_ROOT" for-each-ref --format='%(refname:lstrip=2)%09%(committerdate:unix)' refs/remotes/ |
+    awk -F '\t' 'NR == FNR { local[$0] = 1; next }
+         { name = $1; sub(/^[^\/]*\//, "", name) }
+         name != "HEAD" && !(name in local)' <(printf '%s\n' "$LOCAL_BRANCHES") -)
+  REMOTE_BRANCHES=$(cut -f1 <<< "$REMOTE_INFO")
+  if [ "$JSON" = true ]; then
+    printf '{"version":%d,"branches":[' "$JSON_SCHEMA_VERSION"
+    sep=""
+    # main_checkout: checked out in the main repo, so it can't get a worktree
+    while IFS=$'\t' read -r branch time path; do
+      [ -n "$branch" ] || continue
+      main_checkout=false
+      [ "$path" = "$REPO_ROOT" ] && main_checkout=true
+      printf '%s{"name":"%s","last_commit_time":%s,"main_checkout":%s}' \
+        "$sep" "$(json_escape "$branch")" "${time:-0}" "$main_checkout"
+      sep=","
+    done <<< "$LOCAL_INFO"
+    while IFS=$'\t' read -r ref time; do
+      [ -n "$ref" ] || continue
+      printf '%s{"name":"%s","remote":"%s","last_commit_time":%s}' \
+        "$sep" "$(json_escape "${ref#*/}")" "$(json_escape "${ref%%/*}")" "${time:-0}"
+      sep=","
+    done <<< "$REMOTE_INFO"
+    printf ']}\n'
+  else
+    [ -n "$LOCAL_BRANCHES" ] && echo "$LOCAL_BRANCHES"
+    [ -n "$REMOTE_BRANCHES" ] && echo "$REMOTE_BRANCHES"
+  fi
   exit 0
 fi
 
@@ -75,70 +344,361 @@ EOF
 fi
 
 # Handle remove subcommand
+# --delete-branch also deletes the local branch, but only if it is merged into
+# the base branch as the list's `merged` flag sees it (squash and rebase count)
+# or has no commits of its own. This is checked before anything is removed.
+# --force-delete-branch deletes it regardless.
+#
+# A worktree with uncommitted changes is refused with exit status 2. --force
+# removes it anyway after committing the changes to zelligent/backup/<branch>.
 if [ "$1" = "remove" ]; then
-  if [ -z "$2" ]; then
-    echo "Usage: zelligent remove <branch-name>"
+  shift
+  DELETE_BRANCH=""
+  FORCE=false
+  BRANCH_NAME=""
+  while [ $# -gt 0 ]; do
+    case "$1" in
+      --force) FORCE=true ;;
+      --delete-branch) DELETE_BRANCH=merged ;;
+      --force-delete-branch) DELETE_BRANCH=force ;;
+      *) BRANCH_NAME=$1 ;;
+    esac
+    shift
+  done
+  if [ -z "$BRANCH_NAME" ]; then
+    echo "Usage: zelligent remove [--force] [--delete-branch|--force-delete-branch] <branch-name>"
     exit 1
   fi
-  BRANCH_NAME=$2
-  SESSION_NAME="${BRANCH_NAME//\//-}"
   WORKTREE_PATH="$WORKTREES_DIR/$BRANCH_NAME"
   if [ ! -d "$WORKTREE_PATH" ]; then
-    echo "Error: worktree '$WORKTREE_PATH' does not exist."
+    echo "Error: worktree '$WORKTREE_PATH' does not exist." >&2
     exit 1
   fi
+  BACKUP_REF=""
+  if [ -n "$(git -C "$WORKTREE_PATH" status --porcelain)" ]; then
+    if [ "$FORCE" != true ]; then
+      echo "Error: worktree '$BRANCH_NAME' has uncommitted changes. Commit or stash them, or use --force to remove it anyway." >&2
+      exit 2
+    fi
+    BACKUP_REF="refs/heads/zelligent/backup/$BRANCH_NAME"
+  fi
+  if [ "$DELETE_BRANCH" = "merged" ]; then
+    BASE_BRANCH=$(detect_base_branch)
+    if ! git -C "$REPO_ROOT" merge-base --is-ancestor "refs/heads/$BRANCH_NAME" "$BASE_BRANCH" 2>/dev/null &&
+       ! branch_merged "$BRANCH_NAME"; then
+      echo "Error: branch '$BRANCH_NAME' is not merged into '$BASE_BRANCH'. Worktree was NOT removed." >&2
+      exit 1
+    fi
+  fi
+  if [ -n "$BACKUP_REF" ]; then
+    if ! backup_worktree "$WORKTREE_PATH" "$BACKUP_REF"; then
+      echo "Error: could not save uncommitted changes. Worktree was NOT removed." >&2
+      exit 1
+    fi
+    echo "💾 Saved uncommitted changes to '${BACKUP_REF#refs/heads/}'"
+    if git -C "$REPO_ROOT" rev-parse --verify --quiet "$BACKUP_REF^2" >/dev/null; then
+      echo "ℹ️  The earlier backup is kept as '${BACKUP_REF#refs/heads/}^2'"
+    fi
+  fi
   if [ -f "$REPO_ROOT/.zelligent/teardown.sh" ]; then
     echo "⚙️  Running .zelligent/teardown.sh..."
     if ! bash "$REPO_ROOT/.zelligent/teardown.sh" "$REPO_ROOT" "$WORKTREE_PATH"; then
-      echo "Error: teardown.sh failed. Worktree was NOT removed."
+      echo "Error: teardown.sh failed. Worktree was NOT removed." >&2
       exit 1
     fi
   fi
-  if ! git worktree remove "$WORKTREE_PATH" 2>/dev/null; then
-    echo "Error: could not remove worktree. It may have uncommitted changes."
-    echo "Commit or stash your changes, then try again."
+  REMOVE_ARGS=()
+  [ -z "$BACKUP_REF" ] || REMOVE_ARGS=(--force)
+  if ! git worktree remove "${REMOVE_ARGS[@]}" "$WORKTREE_PATH" 2>/dev/null; then
+    echo "Error: could not remove worktree '$WORKTREE_PATH'." >&2
     exit 1
   fi
   echo "✅ Removed worktree for '$BRANCH_NAME'"
-  echo "ℹ️  Close the '$SESSION_NAME' tab manually if still open."
-  echo "ℹ️  Local branch '$BRANCH_NAME' was not deleted."
+  echo "ℹ️  Close the '$BRANCH_NAME' tab manually if still open."
+  if [ -z "$DELETE_BRANCH" ]; then
+    echo "ℹ️  Local branch '$BRANCH_NAME' was not deleted."
+  elif git -C "$REPO_ROOT" branch -D "$BRANCH_NAME" >/dev/null; then
+    echo "🗑️  Deleted local branch '$BRANCH_NAME'"
+  else
+    echo "Error: worktree was removed but branch '$BRANCH_NAME' could not be deleted." >&2
+    exit 1
+  fi
+  exit 0
+fi
+
+# Remove every worktree whose branch has landed on the base branch and that
+# has no uncommitted changes. Each goes through `remove`, so teardown.sh runs.
+if [ "$1" = "clean-merged" ]; then
+  DRY_RUN=false
+  case "$2" in
+    "") ;;
+    --dry-run) DRY_RUN=true ;;
+    *)
+      echo "Usage: zelligent clean-merged [--dry-run]" >&2
+      exit 1
+      ;;
+  esac
+  CLEANED=0
+  FAILED=0
+  while IFS= read -r branch; do
+    if [ "$DRY_RUN" = true ]; then
+      echo "$branch"
+    elif bash "${BASH_SOURCE[0]}" remove "$branch"; then
+      CLEANED=$((CLEANED + 1))
+    else
+      FAILED=$((FAILED + 1))
+    fi
+  done < <(bash "${BASH_SOURCE[0]}" list-worktrees --cleanable)
+  if [ "$DRY_RUN" = false ]; then
+    echo "🧹 Removed $CLEANED merged worktree(s)"
+    [ "$FAILED" -eq 0 ] || { echo "Error: $FAILED worktree(s) could not be removed." >&2; exit 1; }
+  fi
+  exit 0
+fi
+
+# Handle merge subcommand: land a worktree's branch on the base branch in the
+# main checkout. Exit status 3 means the merge conflicted and was aborted, so
+# nothing changed; the conflicting files are reported.
+if [ "$1" = "merge" ]; then
+  shift
+  STRATEGY=merge
+  POSITIONAL=()
+  while [ $# -gt 0 ]; do
+    case "$1" in
+      --strategy)
+        STRATEGY=$2
+        shift 2 || shift
+        ;;
+      --strategy=*)
+        STRATEGY=${1#--strategy=}
+        shift
+        ;;
+      --json)
+        JSON=true
+        shift
+        ;;
+      *)
+        POSITIONAL+=("$1")
+        shift
+        ;;
+    esac
+  done
+  if [ -z "${POSITIONAL[0]}" ]; then
+    echo "Usage: zelligent merge [--strategy merge|squash|rebase] <branch-name> [--json]" >&2
+    exit 1
+  fi
+  case "$STRATEGY" in
+    merge|squash|rebase) ;;
+    *)
+      echo "Error: unknown merge strategy '$STRATEGY' (use merge, squash or rebase)." >&2
+      exit 1
+      ;;
+  esac
+  BRANCH_NAME=${POSITIONAL[0]}
+  WORKTREE_PATH=$(worktree_for_branch "$BRANCH_NAME")
+  WORKTREE_PATH=${WORKTREE_PATH:-"$WORKTREES_DIR/$BRANCH_NAME"}
+  BASE_BRANCH=$(detect_base_branch)
+
+  if ! git -C "$REPO_ROOT" show-ref --verify --quiet "refs/heads/$BRANCH_NAME"; then
+    echo "Error: branch '$BRANCH_NAME' does not exist." >&2
+    exit 1
+  fi
+  CURRENT=$(git -C "$REPO_ROOT" symbolic-ref --quiet --short HEAD || true)
+  if [ "$CURRENT" != "$BASE_BRANCH" ]; then
+    echo "Error: $REPO_ROOT has '${CURRENT:-detached HEAD}' checked out; check out '$BASE_BRANCH' to merge into it." >&2
+    exit 1
+  fi
+  if [ -n "$(git -C "$REPO_ROOT" status --porcelain --untracked-files=no)" ]; then
+    echo "Error: $REPO_ROOT has uncommitted changes; commit or stash them first." >&2
+    exit 1
+  fi
+  if [ -d "$WORKTREE_PATH" ] && [ -n "$(git -C "$WORKTREE_PATH" status --porcelain)" ]; then
+    echo "Error: worktree '$BRANCH_NAME' has uncommitted changes; commit them first." >&2
+    exit 1
+  fi
+
+  # Print the outcome; remaining arguments are the conflicting files.
+  merge_report() {
+    local result=$1 sep="" file
+    shift
+    if [ "$JSON" = true ]; then
+      printf '{"version":%d,"result":"%s","branch":"%s","base":"%s","strategy":"%s","conflicts":[' \
+        "$JSON_SCHEMA_VERSION" "$result" "$(json_escape "$BRANCH_NAME")" "$(json_escape "$BASE_BRANCH")" "$STRATEGY"
+      for file in "$@"; do
+        printf '%s"%s"' "$sep" "$(json_escape "$file")"
+        sep=","
+      done
+      printf ']}\n'
+    elif [ "$result" = merged ]; then
+      echo "✅ Merged '$BRANCH_NAME' into '$BASE_BRANCH' ($STRATEGY)"
+    else
+      echo "❌ Conflicts merging '$BRANCH_NAME' into '$BASE_BRANCH'; nothing was changed:"
+      printf '   %s\n' "$@"
+    fi
+  }
+
+  # Collect conflicting files in $1's checkout, then undo with "${@:2}".
+  abort_with_conflicts() {
+    local dir=$1 files=()
+    shift
+    mapfile -t files < <(git -C "$dir" diff --name-only --diff-filter=U)
+    git -C "$dir" "$@" &>/dev/null || true
+    if [ ${#files[@]} -eq 0 ]; then
+      echo "Error: $MERGE_OUTPUT" >&2
+      exit 1
+    fi
+    merge_report conflict "${files[@]}"
+    exit 3
+  }
+
+  case "$STRATEGY" in
+    merge)
+      if ! MERGE_OUTPUT=$(git -C "$REPO_ROOT" merge --no-ff --no-edit "$BRANCH_NAME" 2>&1); then
+        abort_with_conflicts "$REPO_ROOT" merge --abort
+      fi
+      ;;
+    squash)
+      if ! MERGE_OUTPUT=$(git -C "$REPO_ROOT" merge --squash "$BRANCH_NAME" 2>&1); then
+        abort_with_conflicts "$REPO_ROOT" reset --merge
+      fi
+      if git -C "$REPO_ROOT" diff --cached --quiet; then
+        echo "Error: '$BRANCH_NAME' has nothing to merge into '$BASE_BRANCH'." >&2
+        exit 1
+      fi
+      git -C "$REPO_ROOT" commit --quiet --no-edit
+      ;;
+    rebase)
+      # Rebase in the branch's own checkout, then fast-forward the base.
+      REBASE_DIR=$WORKTREE_PATH
+      if [ ! -d "$REBASE_DIR" ]; then
+        echo "Error: rebase needs the worktree '$WORKTREE_PATH'." >&2
+        exit 1
+      fi
+      if ! MERGE_OUTPUT=$(git -C "$REBASE_DIR" rebase "$BASE_BRANCH" 2>&1); then
+        abort_with_conflicts "$REBASE_DIR" rebase --abort
+      fi
+      if ! MERGE_OUTPUT=$(git -C "$REPO_ROOT" merge --ff-only "$BRANCH_NAME" 2>&1); then
+        echo "Error: $MERGE_OUTPUT" >&2
+        exit 1
+      fi
+      ;;
+  esac
+  merge_report merged
   exit 0
 fi
 
-# Handle spawn subcommand
-if [ "$1" = "spawn" ]; then
-  if [ -z "$2" ]; then
-    echo "Usage: zelligent spawn <branch-name> [agent-command]"
+# Handle spawn and prepare subcommands. prepare creates the worktree like
+# spawn but, instead of opening a tab, reports what the plugin needs to
+# render the layout itself.
+if [ "$1" = "spawn" ] || [ "$1" = "prepare" ]; then
+  COMMAND=$1
+  shift
+  BASE_REF=""
+  EXISTING_PATH=""
+  PROMPT=""
+  POSITIONAL=()
+  while [ $# -gt 0 ]; do
+    case "$1" in
+      --json)
+        JSON=true
+        shift
+        ;;
+      --base)
+        if [ -z "$2" ]; then
+          echo "Error: --base requires a branch, tag or commit." >&2
+          exit 1
+        fi
+        BASE_REF=$2
+        shift 2
+        ;;
+      --base=*)
+        BASE_REF=${1#--base=}
+        shift
+        ;;
+      --path)
+        # An existing worktree of this repo, e.g. one created with plain
+        # `git worktree add` or with a detached HEAD; the name is the tab's.
+        EXISTING_PATH=$2
+        shift 2 || shift
+        ;;
+      --prompt)
+        # Task for the agent; see {{prompt_file}} below.
+        PROMPT=$2
+        shift 2 || shift
+        ;;
+      *)
+        POSITIONAL+=("$1")
+        shift
+        ;;
+    esac
+  done
+  if [ -z "${POSITIONAL[0]}" ]; then
+    echo "Usage: zelligent $COMMAND [--base <ref>] [--prompt <text>] <branch-name> [agent-command]"
+    exit 1
+  fi
+  BRANCH_NAME=${POSITIONAL[0]}
+  AGENT_CMD=${POSITIONAL[1]:-"$SHELL"}
+  if [ -n "$EXISTING_PATH" ]; then
+    if ! git -C "$REPO_ROOT" worktree list --porcelain | grep -qxF "worktree $EXISTING_PATH"; then
+      echo "Error: '$EXISTING_PATH' is not a worktree of $REPO_ROOT." >&2
+      exit 1
+    fi
+  # "<remote>/<branch>" for a remote-only branch means the local branch name
+  elif ! git -C "$REPO_ROOT" show-ref --verify --quiet "refs/heads/$BRANCH_NAME" \
+    && git -C "$REPO_ROOT" show-ref --verify --quiet "refs/remotes/$BRANCH_NAME"; then
+    BRANCH_NAME="${BRANCH_NAME#*/}"
+  fi
+  if [ -z "$EXISTING_PATH" ] && ! git check-ref-format --branch "$BRANCH_NAME" >/dev/null 2>&1; then
+    echo "Error: '$BRANCH_NAME' is not a valid branch name (see git check-ref-format)." >&2
     exit 1
   fi
-  BRANCH_NAME=$2
-  AGENT_CMD=${3:-"$SHELL"}
 else
   echo "Unknown command: $1"
-  echo "Usage: zelligent spawn <branch-name> [agent-command]"
-  echo "       zelligent remove <branch-name>"
+  echo "Usage: zelligent spawn [--base <ref>] [--prompt <text>] <branch-name> [agent-command]"
+  echo "       zelligent prepare [--base <ref>] [--prompt <text>] <branch-name> [--json]"
+  echo "       zelligent prepare --path <worktree> <tab-name> [--json]"
+  echo "       zelligent remove [--force] [--delete-branch|--force-delete-branch] <branch-name>"
+  echo "       zelligent merge [--strategy merge|squash|rebase] <branch-name> [--json]"
+  echo "       zelligent clean-merged [--dry-run]"
   echo "       zelligent init"
-  echo "       zelligent show-repo"
-  echo "       zelligent list-worktrees"
-  echo "       zelligent list-branches"
+  echo "       zelligent show-repo [--json]"
+  echo "       zelligent list-worktrees [--json]"
+  echo "       zelligent list-branches [--json]"
   exit 1
 fi
 
-SESSION_NAME="${BRANCH_NAME//\//-}"
+# Tabs are named after the branch verbatim, so distinct branches never share a
+# tab name (the plugin relies on this).
+TAB_NAME=$BRANCH_NAME
 
-# Escape backslashes and double quotes for KDL string embedding
-AGENT_CMD_KDL="${AGENT_CMD//\\/\\\\}"
-AGENT_CMD_KDL="${AGENT_CMD_KDL//\"/\\\"}"
+# prepare keeps stdout for its report; progress goes to stderr.
+if [ "$COMMAND" = "prepare" ]; then
+  exec 3>&1 1>&2
+fi
 
-# Detect default base branch
-if BASE_REF=$(git symbolic-ref refs/remotes/origin/HEAD 2>/dev/null); then
-  BASE_BRANCH="${BASE_REF#refs/remotes/origin/}"
+if [ -n "$BASE_REF" ]; then
+  if ! git -C "$REPO_ROOT" rev-parse --verify --quiet "$BASE_REF^{commit}" >/dev/null; then
+    echo "Error: base ref '$BASE_REF' not found."
+    exit 1
+  fi
+  BASE_BRANCH=$BASE_REF
 else
-  BASE_BRANCH="main"
+  BASE_BRANCH=$(detect_base_branch)
 fi
 
-# Define the new centralized worktree path
+# Define the new centralized worktree path, unless the branch is already
+# checked out somewhere else
 WORKTREE_PATH="$WORKTREES_DIR/$BRANCH_NAME"
+if [ -n "$EXISTING_PATH" ]; then
+  WORKTREE_PATH=$EXISTING_PATH
+elif [ ! -d "$WORKTREE_PATH" ]; then
+  EXISTING_PATH=$(worktree_for_branch "$BRANCH_NAME")
+  if [ "$EXISTING_PATH" = "$REPO_ROOT" ]; then
+    echo "Error: '$BRANCH_NAME' is checked out in the main repo ($REPO_ROOT); switch it to another branch first." >&2
+    exit 1
+  fi
+  WORKTREE_PATH=${EXISTING_PATH:-$WORKTREE_PATH}
+fi
 
 NEW_WORKTREE=false
 
@@ -153,7 +713,13 @@ else
   # Handle existing vs new branches
   if git show-ref --verify --quiet "refs/heads/$BRANCH_NAME"; then
     echo "🌿 Branch '$BRANCH_NAME' exists. Attaching worktree..."
+    if [ -n "$BASE_REF" ]; then
+      echo "⚠️  Ignoring --base '$BASE_REF' for existing branch."
+    fi
     git worktree add "$WORKTREE_PATH" "$BRANCH_NAME"
+  elif REMOTE_BRANCH=$(find_remote_branch "$BRANCH_NAME"); then
+    echo "📡 Creating local branch '$BRANCH_NAME' tracking '$REMOTE_BRANCH'..."
+    git worktree add --track -b "$BRANCH_NAME" "$WORKTREE_PATH" "$REMOTE_BRANCH"
   else
     echo "🌱 Creating new branch '$BRANCH_NAME' from '$BASE_BRANCH'..."
     git worktree add -b "$BRANCH_NAME" "$WORKTREE_PATH" "$BASE_BRANCH"
@@ -161,6 +727,14 @@ else
 
 fi
 
+# The task prompt lives in the worktree's git directory, so it never shows up
+# as an uncommitted change.
+PROMPT_FILE=""
+if [ -n "$PROMPT" ]; then
+  PROMPT_FILE="$(git -C "$WORKTREE_PATH" rev-parse --absolute-git-dir)/zelligent-prompt.md"
+  printf '%s\n' "$PROMPT" > "$PROMPT_FILE"
+fi
+
 # Use repo-level layout if present, otherwise use built-in default
 if [ -f "$REPO_ROOT/.zelligent/layout.kdl" ]; then
   LAYOUT_TEMPLATE="$REPO_ROOT/.zelligent/layout.kdl"
@@ -168,23 +742,143 @@ else
   LAYOUT_TEMPLATE=""
 fi
 
+# setup.sh only runs for freshly created worktrees
+SETUP_SCRIPT="$REPO_ROOT/.zelligent/setup.sh"
+if [ "$NEW_WORKTREE" != true ] || [ ! -f "$SETUP_SCRIPT" ]; then
+  SETUP_SCRIPT=""
+fi
+
+# Shell snippet that runs setup.sh before the agent; empty when not needed.
+# Custom layouts place it with {{setup_preamble}}, e.g.
+#   args "-c" "{{setup_preamble}}exec {{agent_cmd}}"
+# prepare reports it so the plugin runs exactly the same snippet.
+SETUP_PREAMBLE=""
+if [ -n "$SETUP_SCRIPT" ]; then
+  SETUP_PREAMBLE="bash $(shell_quote "$SETUP_SCRIPT") $(shell_quote "$REPO_ROOT") $(shell_quote "$WORKTREE_PATH") || { echo 'Setup failed (exit '\$?'). Press Enter to close.'; read; exit 1; }; "
+fi
+
+if [ "$COMMAND" = "prepare" ]; then
+  TEMPLATE_CONTENT=""
+  [ -n "$LAYOUT_TEMPLATE" ] && TEMPLATE_CONTENT=$(<"$LAYOUT_TEMPLATE")
+  if [ "$JSON" = true ]; then
+    printf '{"version":%d,"branch":"%s","worktree_path":"%s","repo_root":"%s","new_worktree":%s,"setup_script":"%s","setup_preamble":"%s","layout_template":"%s","prompt_file":"%s"}\n' \
+      "$JSON_SCHEMA_VERSION" "$(json_escape "$BRANCH_NAME")" "$(json_escape "$WORKTREE_PATH")" \
+      "$(json_escape "$REPO_ROOT")" "$NEW_WORKTREE" "$(json_escape "$SETUP_SCRIPT")" \
+      "$(json_escape "$SETUP_PREAMBLE")" "$(json_escape "$TEMPLATE_CONTENT")" "$(json_escape "$PROMPT_FILE")" >&3
+  else
+    {
+      echo "branch=$BRANCH_NAME"
+      echo "worktree_path=$WORKTREE_PATH"
+      echo "new_worktree=$NEW_WORKTREE"
+      echo "setup_script=$SETUP_SCRIPT"
+      echo "prompt_file=$PROMPT_FILE"
+    } >&3
+  fi
+  exit 0
+fi
+
 # Generate temp layout files
 mkdir -p "$HOME/.zelligent/tmp"
 LAYOUT=$(mktemp "$HOME/.zelligent/tmp/layout-XXXXXX")
 trap 'rm -f "$LAYOUT"' EXIT
 
-# Build the agent pane command, prepending setup.sh for new worktrees
-SETUP_SCRIPT="$REPO_ROOT/.zelligent/setup.sh"
-if [ "$NEW_WORKTREE" = true ] && [ -f "$SETUP_SCRIPT" ]; then
-  AGENT_PANE="pane command=\"bash\" cwd=\"$WORKTREE_PATH\" size=\"70%\" {
-            args \"-c\" \"bash \\\"\$1\\\" \\\"\$2\\\" \\\"\$3\\\" || { echo 'Setup failed (exit '\$?'). Press Enter to close.'; read; exit 1; }; exec $AGENT_CMD_KDL\" \"--\" \"$SETUP_SCRIPT\" \"$REPO_ROOT\" \"$WORKTREE_PATH\"
-        }"
-else
-  AGENT_PANE="pane command=\"bash\" cwd=\"$WORKTREE_PATH\" size=\"70%\" {
-            args \"-c\" \"exec $AGENT_CMD_KDL\"
-        }"
+if [ -n "$LAYOUT_TEMPLATE" ] && [ -n "$SETUP_SCRIPT" ] \
+  && ! grep -qF '{{setup_preamble}}' "$LAYOUT_TEMPLATE"; then
+  echo "⚠️  .zelligent/layout.kdl has no {{setup_preamble}}; setup.sh will not run."
 fi
 
+# Hand the prompt to the agent: at {{prompt_file}} in its command (as a
+# quoted path), or else as a final argument holding the text. The plugin
+# applies the same rules (agent::with_prompt_file). Without an agent command
+# the pane runs a plain $SHELL, which would take the text for a script path.
+if [[ "$AGENT_CMD" == *"{{prompt_file}}"* ]]; then
+  AGENT_CMD=${AGENT_CMD//"{{prompt_file}}"/$(shell_quote "${PROMPT_FILE:-/dev/null}")}
+elif [ -n "$PROMPT_FILE" ] && [ -z "${POSITIONAL[1]}" ]; then
+  echo "⚠️  No agent command; the prompt is not passed to \$SHELL (it is in $PROMPT_FILE)."
+elif [ -n "$PROMPT_FILE" ]; then
+  AGENT_CMD="$AGENT_CMD \"\$(cat $(shell_quote "$PROMPT_FILE"))\""
+fi
+
+# Values are escaped once here; every layout below only embeds the *_KDL forms.
+CWD_KDL=$(kdl_escape "$WORKTREE_PATH")
+AGENT_CMD_KDL=$(kdl_escape "$AGENT_CMD")
+BRANCH_KDL=$(kdl_escape "$BRANCH_NAME")
+REPO_ROOT_KDL=$(kdl_escape "$REPO_ROOT")
+SETUP_PREAMBLE_KDL=$(kdl_escape "$SETUP_PREAMBLE")
+PROMPT_FILE_KDL=$(kdl_escape "${PROMPT_FILE:-/dev/null}")
+TAB_NAME_KDL=$(kdl_escape "$TAB_NAME")
+
+# The trailing "zelligent-worktree" <path> arguments are unused by the
+# script ($0 and $1); they mark the pane so the plugin can find the tab even
+# after it is renamed.
+AGENT_PANE="pane command=\"bash\" cwd=\"$CWD_KDL\" size=\"70%\" {
+            args \"-c\" \"${SETUP_PREAMBLE_KDL}exec $AGENT_CMD_KDL\" \"zelligent-worktree\" \"$CWD_KDL\"
+        }"
+
+# Substitute {{placeholders}} in the custom template, with the rules of
+# layout::render in the plugin: names may be padded with spaces, and an
+# unknown or unterminated placeholder is an error rather than being left in
+# the layout. Placeholders sit inside KDL strings, so the escaped values are
+# inserted literally (no sed, so `|`, `&` and newlines in values are safe).
+render_template() {
+  local rest out="" name value
+  rest=$(<"$LAYOUT_TEMPLATE")
+  while [[ $rest == *"{{"* ]]; do
+    out+=${rest%%"{{"*}
+    rest=${rest#*"{{"}
+    if [[ $rest != *"}}"* ]]; then
+      echo "Error: .zelligent/layout.kdl: unterminated {{ placeholder in layout" >&2
+      return 1
+    fi
+    name=${rest%%"}}"*}
+    rest=${rest#*"}}"}
+    name=${name#"${name%%[![:space:]]*}"}
+    name=${name%"${name##*[![:space:]]}"}
+    case "$name" in
+      cwd) value=$CWD_KDL ;;
+      agent_cmd) value=$AGENT_CMD_KDL ;;
+      branch) value=$BRANCH_KDL ;;
+      repo_root) value=$REPO_ROOT_KDL ;;
+      setup_preamble) value=$SETUP_PREAMBLE_KDL ;;
+      prompt_file) value=$PROMPT_FILE_KDL ;;
+      *)
+        echo "Error: .zelligent/layout.kdl: unknown placeholder {{$name}} in layout" >&2
+        return 1
+        ;;
+    esac
+    out+=$value
+  done
+  printf '%s\n' "$out$rest"
+}
+
+# Rough stand-in for the plugin's KDL parse of a rendered layout: strings
+# must be closed and braces balanced, with a single top-level `layout` node
+# (the outside-Zellij path below relies on its first and last lines).
+check_layout() {
+  printf '%s\n' "$1" | awk '
+    { text = text $0 "\n" }
+    END {
+      n = length(text); depth = 0; str = 0; nodes = 0; first = ""
+      for (i = 1; i <= n; i++) {
+        c = substr(text, i, 1)
+        if (str) {
+          if (c == "\\") i++
+          else if (c == "\"") str = 0
+          continue
+        }
+        if (c == "\"") str = 1
+        else if (c == "{") depth++
+        else if (c == "}") { if (--depth < 0) exit 1 }
+        else if (depth == 0 && c ~ /[^ \t\n;]/) {
+          nodes++
+          if (first == "") { first = substr(text, i); sub(/[ \t{\n].*/, "", first) }
+          while (i < n && substr(text, i + 1, 1) ~ /[^ \t\n{]/) i++
+        }
+      }
+      exit !(str == 0 && depth == 0 && nodes == 1 && first == "layout")
+    }'
+}
+
 # Pane content shared by both layouts
 pane_content() {
   cat <<EOF
@@ -193,7 +887,7 @@ pane_content() {
     }
     pane split_direction="vertical" {
         $AGENT_PANE
-        pane command="lazygit" cwd="$WORKTREE_PATH" size="30%"
+        pane command="lazygit" cwd="$CWD_KDL" size="30%"
     }
     pane size=1 borderless=true {
         plugin location="zellij:status-bar"
@@ -201,29 +895,36 @@ pane_content() {
 EOF
 }
 
+if [ -n "$LAYOUT_TEMPLATE" ]; then
+  RENDERED=$(render_template) || exit 1
+  if ! check_layout "$RENDERED"; then
+    echo "Error: .zelligent/layout.kdl: invalid layout: expected a single top-level \`layout { ... }\` node with closed strings and braces" >&2
+    exit 1
+  fi
+fi
 if [ -n "$LAYOUT_TEMPLATE" ] && [ -n "$ZELLIJ" ]; then
   # Inside Zellij with custom template: substitute vars, use as-is for new-tab
-  sed -e "s|{{cwd}}|$WORKTREE_PATH|g" -e "s|{{agent_cmd}}|$AGENT_CMD|g" "$LAYOUT_TEMPLATE" > "$LAYOUT"
+  printf '%s\n' "$RENDERED" > "$LAYOUT"
 elif [ -n "$LAYOUT_TEMPLATE" ]; then
   # Outside Zellij with custom template: strip outer layout{} and wrap in a named tab
-  INNER=$(sed -e "s|{{cwd}}|$WORKTREE_PATH|g" -e "s|{{agent_cmd}}|$AGENT_CMD|g" "$LAYOUT_TEMPLATE" | sed '1d;$d')
-  { echo "layout {"; echo "    tab name=\"$SESSION_NAME\" {"; echo "$INNER"; echo "    }"; echo "}"; } > "$LAYOUT"
+  INNER=$(printf '%s\n' "$RENDERED" | sed '1d;$d')
+  { echo "layout {"; echo "    tab name=\"$TAB_NAME_KDL\" {"; echo "$INNER"; echo "    }"; echo "}"; } > "$LAYOUT"
 elif [ -n "$ZELLIJ" ]; then
   # Tab layout: no tab wrapper (new-tab provides the tab context)
   { echo "layout {"; pane_content; echo "}"; } > "$LAYOUT"
 else
   # Session layout: wrap in a named tab
-  { echo "layout {"; echo "    tab name=\"$SESSION_NAME\" {"; pane_content; echo "    }"; echo "}"; } > "$LAYOUT"
+  { echo "layout {"; echo "    tab name=\"$TAB_NAME_KDL\" {"; pane_content; echo "    }"; echo "}"; } > "$LAYOUT"
 fi
 
 # Inside Zellij: open as a new tab in the current session.
 # Outside Zellij: create or attach to a repo-named session, open worktree as a tab.
 if [ -n "$ZELLIJ" ]; then
-  echo "🪟 Opening tab '$SESSION_NAME'..."
-  zellij action new-tab --layout "$LAYOUT" --name "$SESSION_NAME"
+  echo "🪟 Opening tab '$TAB_NAME'..."
+  zellij action new-tab --layout "$LAYOUT" --name "$TAB_NAME"
 elif zellij list-sessions --no-formatting --short 2>/dev/null | grep -qxF "$REPO_NAME"; then
-  echo "🪟 Attaching to session '$REPO_NAME', opening tab '$SESSION_NAME'..."
-  ZELLIJ_SESSION_NAME="$REPO_NAME" zellij action new-tab --layout "$LAYOUT" --name "$SESSION_NAME"
+  echo "🪟 Attaching to session '$REPO_NAME', opening tab '$TAB_NAME'..."
+  ZELLIJ_SESSION_NAME="$REPO_NAME" zellij action new-tab --layout "$LAYOUT" --name "$TAB_NAME"
   zellij attach "$REPO_NAME"
 else
   echo "🪟 Creating Zellij session '$REPO_NAME'..."